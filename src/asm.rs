@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use instruction::Instruction;
+use ram::ROM_START;
+
+// Assembles the mnemonics `Instruction` prints, so disassembler output can be
+// fed straight back in. On top of those there are labels (`name:`), `DB` and
+// `DW` for data, and `;` comments. Mnemonics and register names are case
+// insensitive; numbers are decimal, or hex with a `0x`, `$` or `#` prefix, or
+// binary with `0b`. Addresses may be given as labels.
+#[derive(Clone, PartialEq, Debug)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Operand {
+    V(u8),
+    I,
+    AtI,
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    Number(u32),
+    Label(String),
+}
+
+struct Statement {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<Operand>,
+}
+
+impl Statement {
+    fn size(&self) -> usize {
+        match self.mnemonic.as_str() {
+            "DB" => self.operands.len(),
+            "DW" => self.operands.len() * 2,
+            _ => 2,
+        }
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut addr = ROM_START;
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let mut text = text.split(';').next().unwrap_or("").trim();
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if !is_identifier(label) {
+                return Err(error(line, format!("bad label \"{}\"", label)));
+            }
+            if labels.insert(label.to_string(), addr).is_some() {
+                return Err(error(line, format!("label \"{}\" defined twice", label)));
+            }
+            text = text[colon + 1..].trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+        let (mnemonic, rest) = match text.find(char::is_whitespace) {
+            Some(space) => (&text[..space], text[space..].trim()),
+            None => (text, ""),
+        };
+        let operands = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',')
+                .map(|operand| parse_operand(operand.trim()).ok_or_else(|| error(line, format!("bad operand \"{}\"", operand.trim()))))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        let statement = Statement {
+            line,
+            mnemonic: mnemonic.to_ascii_uppercase(),
+            operands,
+        };
+        addr += statement.size();
+        statements.push(statement);
+    }
+
+    let mut out = Vec::new();
+    for statement in statements {
+        let resolve = |operand: &Operand| -> Result<u32, AsmError> {
+            match *operand {
+                Operand::Number(n) => Ok(n),
+                Operand::Label(ref name) => labels
+                    .get(name)
+                    .map(|&addr| addr as u32)
+                    .ok_or_else(|| error(statement.line, format!("unknown label \"{}\"", name))),
+                _ => Err(error(statement.line, "expected a number or label".to_string())),
+            }
+        };
+        match statement.mnemonic.as_str() {
+            "DB" => {
+                for operand in statement.operands.iter() {
+                    out.push(fit(resolve(operand)?, 0xFF, statement.line)? as u8);
+                }
+            }
+            "DW" => {
+                for operand in statement.operands.iter() {
+                    let word = fit(resolve(operand)?, 0xFFFF, statement.line)? as u16;
+                    out.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+            _ => {
+                let instruction = encode(&statement, &resolve)?;
+                out.extend_from_slice(&instruction.encode().to_be_bytes());
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn encode<F>(statement: &Statement, resolve: &F) -> Result<Instruction, AsmError>
+where
+    F: Fn(&Operand) -> Result<u32, AsmError>,
+{
+    use self::Instruction::*;
+    use self::Operand::*;
+
+    let line = statement.line;
+    let addr = |operand: &Operand| -> Result<u16, AsmError> { Ok(fit(resolve(operand)?, 0xFFF, line)? as u16) };
+    let byte = |operand: &Operand| -> Result<u8, AsmError> { Ok(fit(resolve(operand)?, 0xFF, line)? as u8) };
+    let nibble = |operand: &Operand| -> Result<u8, AsmError> { Ok(fit(resolve(operand)?, 0xF, line)? as u8) };
+
+    let instruction = match (statement.mnemonic.as_str(), statement.operands.as_slice()) {
+        ("CLS", []) => Cls,
+        ("RET", []) => Ret,
+        ("JP", [V(0), target]) => JpV0(addr(target)?),
+        ("JP", [target]) => Jp(addr(target)?),
+        ("CALL", [target]) => Call(addr(target)?),
+        ("SE", [V(x), V(y)]) => SeReg(*x, *y),
+        ("SE", [V(x), nn]) => SeByte(*x, byte(nn)?),
+        ("SNE", [V(x), V(y)]) => SneReg(*x, *y),
+        ("SNE", [V(x), nn]) => SneByte(*x, byte(nn)?),
+        ("LD", [V(x), V(y)]) => LdReg(*x, *y),
+        ("LD", [V(x), Dt]) => LdVxDt(*x),
+        ("LD", [V(x), K]) => LdVxK(*x),
+        ("LD", [V(x), AtI]) => LdVxI(*x),
+        ("LD", [V(x), nn]) => LdByte(*x, byte(nn)?),
+        ("LD", [I, target]) => LdI(addr(target)?),
+        ("LD", [Dt, V(x)]) => LdDtVx(*x),
+        ("LD", [St, V(x)]) => LdStVx(*x),
+        ("LD", [F, V(x)]) => LdFVx(*x),
+        ("LD", [B, V(x)]) => LdBVx(*x),
+        ("LD", [AtI, V(x)]) => LdIVx(*x),
+        ("ADD", [I, V(x)]) => AddIVx(*x),
+        ("ADD", [V(x), V(y)]) => AddReg(*x, *y),
+        ("ADD", [V(x), nn]) => AddByte(*x, byte(nn)?),
+        ("OR", [V(x), V(y)]) => Or(*x, *y),
+        ("AND", [V(x), V(y)]) => And(*x, *y),
+        ("XOR", [V(x), V(y)]) => Xor(*x, *y),
+        ("SUB", [V(x), V(y)]) => Sub(*x, *y),
+        ("SUBN", [V(x), V(y)]) => Subn(*x, *y),
+        ("SHR", [V(x), V(y)]) => Shr(*x, *y),
+        ("SHR", [V(x)]) => Shr(*x, *x),
+        ("SHL", [V(x), V(y)]) => Shl(*x, *y),
+        ("SHL", [V(x)]) => Shl(*x, *x),
+        ("RND", [V(x), nn]) => Rnd(*x, byte(nn)?),
+        ("DRW", [V(x), V(y), n]) => Drw(*x, *y, nibble(n)?),
+        ("SKP", [V(x)]) => Skp(*x),
+        ("SKNP", [V(x)]) => Sknp(*x),
+        (mnemonic, _) => {
+            return Err(error(line, format!("no form of {} takes these operands", mnemonic)));
+        }
+    };
+    Ok(instruction)
+}
+
+fn parse_operand(text: &str) -> Option<Operand> {
+    let upper = text.to_ascii_uppercase();
+    let operand = match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::AtI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "B" => Operand::B,
+        _ if upper.len() == 2 && upper.starts_with('V') => Operand::V(u8::from_str_radix(&upper[1..], 16).ok()?),
+        _ if is_identifier(text) => Operand::Label(text.to_string()),
+        _ => Operand::Number(parse_number(&upper)?),
+    };
+    Some(operand)
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    if let Some(hex) = text.strip_prefix("0X").or_else(|| text.strip_prefix('$')).or_else(|| text.strip_prefix('#')) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix("0B") {
+        u32::from_str_radix(binary, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+fn fit(value: u32, max: u32, line: usize) -> Result<u32, AsmError> {
+    if value <= max {
+        Ok(value)
+    } else {
+        Err(error(line, format!("0x{:X} doesn't fit in 0x{:X}", value, max)))
+    }
+}
+
+fn error(line: usize, message: String) -> AsmError {
+    AsmError { line, message }
+}
+
+#[test]
+fn test_assemble() {
+    let source = "
+        start:  LD V0, 0x0A   ; counter
+                LD I, sprite
+        loop:   DRW V0, V1, 5
+                ADD v0, -1
+                SE V0, 0
+                JP loop
+                SHR V3
+                JP start
+        sprite: DB $F0, #90, 0b11110000
+                DW 0x1234
+    ";
+    assert_eq!(assemble(source), Err(error(5, "bad operand \"-1\"".to_string())));
+    let bytes = assemble(&source.replace("-1", "255")).unwrap();
+    assert_eq!(
+        bytes,
+        vec![
+            0x60, 0x0A, 0xA2, 0x10, 0xD0, 0x15, 0x70, 0xFF, 0x30, 0x00, 0x12, 0x04, 0x83, 0x36, 0x12, 0x00, 0xF0,
+            0x90, 0xF0, 0x12, 0x34,
+        ]
+    );
+}
+
+#[test]
+fn test_every_instruction_round_trips() {
+    for op in 0..=0xFFFFu16 {
+        let instruction = Instruction::decode(op);
+        let bytes = assemble(&instruction.to_string()).unwrap();
+        assert_eq!(bytes, op.to_be_bytes().to_vec(), "{}", instruction);
+    }
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(assemble("JP nowhere").unwrap_err().message, "unknown label \"nowhere\"");
+    assert_eq!(assemble("a:\na:").unwrap_err().line, 2);
+    assert_eq!(assemble("LD V0, 256").unwrap_err().message, "0x100 doesn't fit in 0xFF");
+    assert!(assemble("DRW V0, V1").is_err());
+    assert!(assemble("FOO").is_err());
+}
@@ -1,44 +1,658 @@
 extern crate rust8;
 extern crate termios;
 
-use std::fs::File;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
 use std::io;
-use std::io::Read;
-use std::io::Write;
-use std::sync::mpsc::channel;
+use std::io::{BufRead, Read, Write};
+use std::panic;
+use std::path::Path;
+use std::process;
+use std::sync::mpsc::{channel, RecvTimeoutError};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::thread::sleep;
 use std::time;
 
 use termios::{tcsetattr, Termios, ECHO, ICANON, TCSANOW};
 
+use rust8::asm;
+use rust8::cli::{self, CliError, Command, Options};
+use rust8::config::{self, Config, RendererChoice, Settings};
 use rust8::cpu::CPU;
+use rust8::harness::Harness;
+use rust8::disasm;
 use rust8::display::Display;
-use rust8::displayimpl::{AsciiDisplay, DisplayImpl};
-use rust8::keyboard::{Keyboard, EXIT_CHAR};
-use rust8::ram::RAM;
+use rust8::displayimpl::{self, AsciiDisplay, DisplayImpl};
+use rust8::framebuffer;
+use rust8::keyboard::{Keyboard, Keymap, EXIT_CHAR};
+use rust8::palette::Palette;
+use rust8::phosphor::PhosphorDisplay;
+use rust8::quirks::Quirks;
+use rust8::ram::{RAM, ROM_START};
+use rust8::recompile;
+use rust8::recorder::Recorder;
+use rust8::regress::{self, Manifest};
+use rust8::rom::Rom;
+use rust8::romdb::{RomDatabase, RomInfo};
+use rust8::sandbox::{RunOutcome, Sandbox};
+use rust8::screenshot::{self, ImageFormat, Screenshot};
+use rust8::sixel::{self, SixelDisplay};
+use rust8::swapchain::swap_chain;
+use rust8::trace::{self, Trace};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+// Instructions per frame for headless runs when neither the command line nor
+// the ROM says.
+const DEFAULT_IPF: u32 = 15;
+const DEFAULT_TRACE_FRAMES: u64 = 60;
+const DEFAULT_BENCH_INSTRUCTIONS: u64 = 1_000_000;
+// Without a known tickrate, `run` checks the clock and keyboard between
+// batches of this many instructions.
+const FREE_RUN_BATCH: u64 = 64;
 
-// Yes, this is all hideously ugly - in flux as overall design for keyboard / display being put in place, after which the Refactoring will begin.
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        writeln!(std::io::stderr(), "Usage: rust8 ROMFILE").unwrap();
-        std::process::exit(1);
+    let options = match cli::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(CliError::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    };
+    let result = match options.command {
+        Command::Asm => assemble(&options),
+        Command::Disasm => disassemble(&options),
+        Command::Recompile => recompile(&options),
+        Command::Info => info(&options),
+        Command::Run | Command::Record if options.is_headless() => headless(&options),
+        Command::Run | Command::Record => play(&options),
+        Command::Debug => debug(&options),
+        Command::Trace => trace(&options),
+        Command::Bench => bench(&options),
+        Command::Regress => regress(&options),
+        Command::Replay => replay(&options),
+        Command::ConfigShow => show_config(&options),
+    };
+    if let Err(err) = result {
+        eprintln!("rust8: {}", err);
+        process::exit(1);
+    }
+}
+
+// How to run a ROM. Every setting but `ipf` is filled in: the command line
+// wins, then the config file's sections for the ROM, then whatever the ROM
+// brought with it or the database knows, then the config file's settings for
+// every ROM, then the platform's defaults.
+struct Setup {
+    rom: Rom,
+    info: Option<RomInfo>,
+    settings: Settings,
+}
+
+impl Setup {
+    fn quirks(&self) -> Quirks {
+        self.settings.quirks.unwrap()
+    }
+
+    fn palette(&self) -> Palette {
+        self.settings.palette.clone().unwrap()
+    }
+
+    fn scale(&self) -> usize {
+        self.settings.scale.unwrap()
+    }
+}
+
+fn load_config() -> Config {
+    Config::standard().unwrap_or_else(|err| {
+        eprintln!("Ignoring config file: {}", err);
+        Config::default()
+    })
+}
+
+fn setup(options: &Options) -> Result<Setup> {
+    let path = options.input();
+    let mut rom = Rom::load(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let database = RomDatabase::standard().unwrap_or_else(|err| {
+        eprintln!("Ignoring ROM database: {}", err);
+        RomDatabase::builtin()
+    });
+    let info = match rom.info() {
+        Some(info) => {
+            eprintln!("Using the settings from cartridge {}", info.title);
+            Some(info.clone())
+        }
+        None => database.lookup(&rom.sha1_hex()).cloned().inspect(|info| {
+            eprintln!("Found {} in the ROM database", info.title);
+        }),
+    };
+
+    let config = load_config();
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut settings = config.global.clone();
+    if let Some(ref info) = info {
+        settings.merge(&Settings::from_info(info));
+    }
+    settings.merge(&config.for_rom(&file_name, &rom.sha1_hex()));
+    settings.merge(&options.settings);
+
+    if let Some(platform) = settings.platform {
+        rom = rom
+            .with_platform(platform)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+    settings.platform = Some(rom.platform());
+    settings.quirks.get_or_insert(Quirks::for_platform(rom.platform()));
+    settings.keymap.get_or_insert_with(Keymap::default);
+    settings.renderer.get_or_insert(RendererChoice::Auto);
+    settings.scale.get_or_insert(4);
+    settings.palette.get_or_insert_with(Palette::default);
+    eprintln!(
+        "Loaded {} ({} bytes, {}, sha1 {})",
+        rom.name(),
+        rom.size(),
+        rom.platform(),
+        rom.sha1_hex()
+    );
+    Ok(Setup { rom, info, settings })
+}
+
+fn configure(cpu: &mut CPU, setup: &Setup, options: &Options) {
+    cpu.load_rom(setup.rom.bytes());
+    cpu.set_quirks(setup.quirks());
+    if let Some(seed) = options.seed {
+        cpu.set_seed(seed);
+    }
+}
+
+// Runs `f` on a configured CPU with nothing attached to its keyboard, so key
+// waits never finish. Opcodes are logged to `log` if given.
+fn with_headless_cpu<T, F>(setup: &Setup, options: &Options, log: Option<&Path>, f: F) -> Result<T>
+where
+    F: FnOnce(&mut CPU) -> Result<T>,
+{
+    let (_, receiver) = channel();
+    let mut keyboard = Arc::new(Mutex::new(Keyboard::init(receiver)));
+    let mut ram = RAM::with_size(setup.rom.platform().memory_size());
+    let mut display = Display::init();
+    let mut logfile = match log {
+        Some(path) => Some(File::create(path)?),
+        None => None,
+    };
+    let mut cpu = CPU::init(&mut ram, &mut display, &mut keyboard, logfile.as_mut());
+    configure(&mut cpu, setup, options);
+    f(&mut cpu)
+}
+
+// The instruction the CPU stopped at, as an error.
+fn check_fault(cpu: &CPU) -> Result<()> {
+    match cpu.fault() {
+        Some(fault) => Err(format!("{} at 0x{:03X}", fault, cpu.get_pc()).into()),
+        None => Ok(()),
+    }
+}
+
+fn assemble(options: &Options) -> Result<()> {
+    let source = fs::read_to_string(options.input())?;
+    let bytes = asm::assemble(&source).map_err(|err| format!("{}: {}", options.input().display(), err))?;
+    let output = options
+        .output
+        .clone()
+        .unwrap_or_else(|| options.input().with_extension("ch8"));
+    fs::write(&output, &bytes)?;
+    eprintln!("Wrote {} bytes to {}", bytes.len(), output.display());
+    Ok(())
+}
+
+fn disassemble(options: &Options) -> Result<()> {
+    let rom = Rom::load(options.input()).map_err(|err| format!("{}: {}", options.input().display(), err))?;
+    let listing = disasm::listing(rom.bytes(), ROM_START as u16);
+    match options.output {
+        Some(ref path) => fs::write(path, listing)?,
+        None => print!("{}", listing),
+    }
+    Ok(())
+}
+
+fn recompile(options: &Options) -> Result<()> {
+    let path = options.input();
+    let rom = Rom::load(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let source = recompile::recompile(rom.bytes(), &name);
+    let output = options.output.clone().unwrap_or_else(|| path.with_extension("rs"));
+    fs::write(&output, source)?;
+    eprintln!("Wrote {}", output.display());
+    Ok(())
+}
+
+fn info(options: &Options) -> Result<()> {
+    let setup = setup(options)?;
+    let rom = &setup.rom;
+    println!("Name:      {}", rom.name());
+    println!("Size:      {} bytes", rom.size());
+    println!("Platform:  {}", rom.platform());
+    println!("SHA-1:     {}", rom.sha1_hex());
+    println!("Quirks:    {}", setup.quirks());
+    if let Some(ipf) = setup.settings.ipf {
+        println!("Tickrate:  {} instructions per frame", ipf);
+    }
+    if let Some(ref info) = setup.info {
+        println!("Title:     {}", info.title);
+        for &(ref action, key) in info.keys.iter() {
+            println!("Key:       {} on {:X}", action, key);
+        }
+    }
+    Ok(())
+}
+
+// Prints the effective settings in config file form, for one ROM if given.
+fn show_config(options: &Options) -> Result<()> {
+    match config::config_path() {
+        Some(ref path) if path.exists() => println!("# From {} and the command line", path.display()),
+        _ => println!("# No config file; from the command line"),
+    }
+    if options.files.is_empty() {
+        let mut settings = load_config().global;
+        settings.merge(&options.settings);
+        print!("{}", settings);
+    } else {
+        let setup = setup(options)?;
+        println!("# {} (sha1 {})", setup.rom.name(), setup.rom.sha1_hex());
+        print!("{}", setup.settings);
+    }
+    Ok(())
+}
+
+// Runs for `--frames` frames with no terminal attached, then prints the
+// screen. `record` also saves every frame.
+fn headless(options: &Options) -> Result<()> {
+    let setup = setup(options)?;
+    let ipf = setup.settings.ipf.unwrap_or(DEFAULT_IPF);
+    let mut sandbox = Sandbox::new(ipf as u64);
+    if let Some(frames) = options.frames {
+        sandbox = sandbox.frames(frames);
+    }
+    if let Some(instructions) = options.instructions {
+        sandbox = sandbox.instructions(instructions);
+    }
+    if let Some(timeout) = options.timeout {
+        sandbox = sandbox.timeout(timeout);
+    }
+    let mut recorder = Recorder::new(setup.scale(), setup.palette());
+    let (outcome, screen) = with_headless_cpu(&setup, options, options.log.as_deref(), |cpu| {
+        let outcome = sandbox.run(cpu, |cpu| recorder.capture(cpu.display()));
+        Ok((outcome, displayimpl::screen_to_ascii(cpu.display())))
+    })?;
+    if let RunOutcome::Error(..) = outcome {
+        return Err(outcome.to_string().into());
     }
-    let mut file = File::open(&args[1]).expect("Couldn't load ROM file");
-    let mut rom = [0u8; 4000 - 0x200];
-    file.read(&mut rom).expect("Couldn't read ROM file");
+    print!("{}", screen);
+    eprintln!(
+        "After {} frames ({} instructions): {}",
+        sandbox.frames_run(),
+        sandbox.instructions_run(),
+        outcome
+    );
+    if options.command == Command::Record {
+        recorder.save(&options.files[1])?;
+    }
+    Ok(())
+}
+
+fn trace(options: &Options) -> Result<()> {
+    let setup = setup(options)?;
+    let frames = options.frames.unwrap_or(DEFAULT_TRACE_FRAMES);
+    let ipf = setup.settings.ipf.unwrap_or(DEFAULT_IPF);
+    let mut out: Box<dyn Write> = match options.log {
+        Some(ref path) => Box::new(io::BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout()),
+    };
+    with_headless_cpu(&setup, options, None, |cpu| {
+        for _ in 0..frames {
+            for n in 0..ipf {
+                let pc = cpu.get_pc();
+                let op = (cpu.get_mem8(pc as usize) as u16) << 8 | cpu.get_mem8(pc as usize + 1) as u16;
+                let idle = cpu.is_waiting_for_vblank();
+                cpu.run_cycle();
+                check_fault(cpu)?;
+                // The timers tick before the frame's last line, which shows
+                // what the next instruction starts with, as `replay` expects.
+                if n + 1 == ipf {
+                    cpu.end_frame();
+                }
+                // Waiting for the frame to end runs nothing worth a line.
+                if !idle {
+                    writeln!(out, "{}", trace::line(pc, op, cpu))?;
+                }
+            }
+        }
+        Ok(())
+    })?;
+    out.flush()?;
+    Ok(())
+}
+
+fn registers(cpu: &CPU) -> String {
+    let regs: Vec<String> = (0..16).map(|x| format!("{:02X}", cpu.get_reg(x))).collect();
+    format!(
+        "V {} I {:03X} DT {:02X} ST {:02X}",
+        regs.join(" "),
+        cpu.get_i(),
+        cpu.get_delay(),
+        cpu.get_sound()
+    )
+}
+
+// Runs each ROM for `--instructions` instructions, ticking the timers every
+// `ipf`, once instruction by instruction and once from the block cache, and
+// reports how fast each went.
+fn bench(options: &Options) -> Result<()> {
+    let instructions = options.instructions.unwrap_or(DEFAULT_BENCH_INSTRUCTIONS);
+    for file in options.files.iter() {
+        let options = Options {
+            files: vec![file.clone()],
+            ..options.clone()
+        };
+        let setup = setup(&options)?;
+        let ipf = setup.settings.ipf.unwrap_or(DEFAULT_IPF) as u64;
+        let time = |cached: bool| {
+            with_headless_cpu(&setup, &options, None, |cpu| {
+                let start = time::Instant::now();
+                let mut left = instructions;
+                while left > 0 {
+                    let count = ipf.min(left);
+                    if cached {
+                        cpu.run_cycles(count);
+                    } else {
+                        for _ in 0..count {
+                            cpu.run_cycle();
+                        }
+                    }
+                    cpu.end_frame();
+                    check_fault(cpu)?;
+                    left -= count;
+                }
+                Ok(start.elapsed().as_secs_f64())
+            })
+        };
+        let interpreted = time(false)?;
+        let cached = time(true)?;
+        println!(
+            "{}: {} instructions, interpreted in {:.3}s ({:.0}/s), cached in {:.3}s ({:.0}/s)",
+            setup.rom.name(),
+            instructions,
+            interpreted,
+            instructions as f64 / interpreted,
+            cached,
+            instructions as f64 / cached
+        );
+    }
+    Ok(())
+}
+
+// Runs every ROM in the manifest and compares the screen each ends on with
+// its baseline, writing a PNG diff for each that changed. With `--update`
+// the new screens become the baselines instead.
+fn regress(options: &Options) -> Result<()> {
+    let manifest = Manifest::load(options.input())?;
+    let diffs = options.output.clone().unwrap_or_else(|| manifest.baseline.clone());
+    let scale = options.settings.scale.unwrap_or(4);
+    // ROMs that crash are reported with the rest.
+    panic::set_hook(Box::new(|_| {}));
+    fs::create_dir_all(&manifest.baseline)?;
+    fs::create_dir_all(&diffs)?;
+    let (mut passed, mut changed, mut missing, mut failed) = (0, 0, 0, 0);
+    for entry in manifest.entries.iter() {
+        let path = manifest.baseline_path(entry);
+        let diff_path = diffs.join(format!("{}.diff.png", entry.name));
+        let screen = match regress::run(entry) {
+            Ok(screen) => screen,
+            Err(err) => {
+                println!("{}: failed: {}", entry.name, err);
+                failed += 1;
+                continue;
+            }
+        };
+        match regress::load_baseline(&path)? {
+            Some(ref baseline) if regress::differing_pixels(baseline, &screen) == 0 => {
+                println!("{}: ok", entry.name);
+                passed += 1;
+            }
+            Some(ref baseline) if !options.update => {
+                fs::write(&diff_path, regress::diff_png(baseline, &screen, scale))?;
+                println!(
+                    "{}: changed, {} pixels differ (see {})",
+                    entry.name,
+                    regress::differing_pixels(baseline, &screen),
+                    diff_path.display()
+                );
+                changed += 1;
+                continue;
+            }
+            None if !options.update => {
+                println!("{}: no baseline; run with --update to store one", entry.name);
+                missing += 1;
+                continue;
+            }
+            baseline => {
+                regress::save_baseline(&path, &screen)?;
+                let what = if baseline.is_some() { "updated" } else { "stored" };
+                println!("{}: {} {}", entry.name, what, path.display());
+                passed += 1;
+            }
+        }
+        if diff_path.exists() {
+            fs::remove_file(&diff_path)?;
+        }
+    }
+    println!(
+        "{} ROMs: {} ok, {} changed, {} without a baseline, {} failed",
+        manifest.entries.len(),
+        passed,
+        changed,
+        missing,
+        failed
+    );
+    if changed + missing + failed > 0 {
+        return Err("regressions found".into());
+    }
+    Ok(())
+}
+
+// Runs a ROM against another emulator's trace of it, with the same settings
+// as `trace`, and reports the first step where they part ways.
+fn replay(options: &Options) -> Result<()> {
+    let setup = setup(options)?;
+    let path = &options.files[1];
+    let trace = Trace::load(path, options.trace_format).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let harness = Harness::new(setup.rom.bytes())
+        .platform(setup.rom.platform())
+        .quirks(setup.quirks())
+        .ipf(setup.settings.ipf.unwrap_or(DEFAULT_IPF) as u64)
+        .seed(options.seed.unwrap_or(0));
+    // A crash is reported as the divergence.
+    panic::set_hook(Box::new(|_| {}));
+    match harness.run(|machine| trace::replay(machine, &trace)) {
+        Ok(steps) => {
+            println!("All {} steps match the trace", steps);
+            Ok(())
+        }
+        Err(divergence) => {
+            print!("{}", divergence);
+            Err("the ROM ran differently from the trace".into())
+        }
+    }
+}
+
+const DEBUG_HELP: &str = "\
+s [N]          step N instructions
+c [FRAMES]     continue to a breakpoint, for at most FRAMES frames
+b ADDR         set or clear a breakpoint
+r              show registers
+m ADDR [LEN]   dump memory
+d [ADDR] [N]   disassemble N instructions
+p              print the screen
+q              quit
+Numbers are decimal; write addresses as 0x200.";
+
+fn debug(options: &Options) -> Result<()> {
+    let setup = setup(options)?;
+    let mut debugger = Debugger {
+        breakpoints: Vec::new(),
+        cycles: 0,
+        ipf: setup.settings.ipf.unwrap_or(DEFAULT_IPF),
+        max_frames: options.frames.unwrap_or(3600),
+    };
+    with_headless_cpu(&setup, options, options.log.as_deref(), |cpu| {
+        println!("{}", DEBUG_HELP);
+        print_next(cpu);
+        let stdin = io::stdin();
+        loop {
+            print!("> ");
+            io::stdout().flush()?;
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match debugger.command(cpu, &words) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(message) => println!("{}", message),
+            }
+        }
+    })
+}
+
+struct Debugger {
+    breakpoints: Vec<u16>,
+    cycles: u64,
+    ipf: u32,
+    max_frames: u64,
+}
+
+impl Debugger {
+    fn step(&mut self, cpu: &mut CPU) {
+        cpu.run_cycle();
+        self.cycles += 1;
+        if self.cycles.is_multiple_of(self.ipf as u64) {
+            cpu.end_frame();
+        }
+    }
+
+    // Returns false once it's time to quit. An empty line steps.
+    fn command(&mut self, cpu: &mut CPU, words: &[&str]) -> std::result::Result<bool, String> {
+        let arg = |n: usize, default: usize| match words.get(n) {
+            Some(word) => parse_number(word).ok_or_else(|| format!("bad number {}", word)),
+            None => Ok(default),
+        };
+        match words.first().cloned().unwrap_or("s") {
+            "s" => {
+                for _ in 0..arg(1, 1)? {
+                    self.step(cpu);
+                    if cpu.fault().is_some() {
+                        break;
+                    }
+                }
+                print_next(cpu);
+            }
+            "c" => {
+                let cycles = arg(1, self.max_frames as usize)? * self.ipf as usize;
+                for _ in 0..cycles {
+                    self.step(cpu);
+                    if cpu.fault().is_some() || self.breakpoints.contains(&cpu.get_pc()) {
+                        break;
+                    }
+                }
+                print_next(cpu);
+            }
+            "b" => {
+                let addr = arg(1, cpu.get_pc() as usize)? as u16;
+                match self.breakpoints.iter().position(|&b| b == addr) {
+                    Some(i) => {
+                        self.breakpoints.remove(i);
+                        println!("Cleared breakpoint at {:03X}", addr);
+                    }
+                    None => {
+                        self.breakpoints.push(addr);
+                        println!("Breakpoint at {:03X}", addr);
+                    }
+                }
+            }
+            "r" => println!("PC {:03X} {} stack {:03X?}", cpu.get_pc(), registers(cpu), cpu.get_stack()),
+            "m" => {
+                let start = arg(1, cpu.get_i() as usize)?;
+                let end = start + arg(2, 16)?;
+                for row in (start..end).step_by(16) {
+                    let bytes: Vec<String> = (row..(row + 16).min(end))
+                        .map(|addr| format!("{:02X}", cpu.get_mem8(addr)))
+                        .collect();
+                    println!("{:03X}: {}", row, bytes.join(" "));
+                }
+            }
+            "d" => {
+                let start = arg(1, cpu.get_pc() as usize)?;
+                let bytes: Vec<u8> = (start..start + arg(2, 8)? * 2).map(|addr| cpu.get_mem8(addr)).collect();
+                print!("{}", disasm::listing(&bytes, start as u16));
+            }
+            "p" => print!("{}", displayimpl::screen_to_ascii(cpu.display())),
+            "q" => return Ok(false),
+            _ => println!("{}", DEBUG_HELP),
+        }
+        Ok(true)
+    }
+}
+
+fn print_next(cpu: &CPU) {
+    if let Some(fault) = cpu.fault() {
+        println!("Stopped: {}", fault);
+    } else if cpu.is_waiting_for_vblank() {
+        println!("Waiting for the frame to end");
+    }
+    let pc = cpu.get_pc() as usize;
+    print!("{}", disasm::listing(&[cpu.get_mem8(pc), cpu.get_mem8(pc + 1)], pc as u16));
+}
+
+fn parse_number(word: &str) -> Option<usize> {
+    match word.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+// Plays the ROM in the terminal until the exit key is pressed.
+fn play(options: &Options) -> Result<()> {
+    let setup = setup(options)?;
+
+    // Must happen before the keyboard thread starts consuming stdin.
+    let renderer: Box<dyn DisplayImpl + Send> = match setup.settings.renderer.unwrap() {
+        RendererChoice::Auto => sixel::detect_renderer(setup.scale(), setup.palette()),
+        RendererChoice::Sixel => Box::new(SixelDisplay::new(setup.scale(), setup.palette())),
+        RendererChoice::Ascii => Box::new(AsciiDisplay()),
+    };
+    let renderer: Box<dyn DisplayImpl + Send> = match setup.settings.phosphor {
+        Some(mode) => Box::new(PhosphorDisplay::new(renderer, mode)),
+        None => renderer,
+    };
+    let fading = setup.settings.phosphor.is_some();
 
     let (sender, receiver) = channel();
 
     let stdin = 0;
-    let termios = Termios::from_fd(stdin).unwrap();
-    let mut new_termios = termios.clone();
+    let termios = Termios::from_fd(stdin)?;
+    let mut new_termios = termios;
     new_termios.c_lflag &= !(ICANON | ECHO);
 
     let handle_keyboard = thread::spawn(move || loop {
-        tcsetattr(stdin, TCSANOW, &mut new_termios).unwrap();
+        tcsetattr(stdin, TCSANOW, &new_termios).unwrap();
         let stdout = io::stdout();
         let reader = io::stdin();
         let mut buffer = [0; 1];
@@ -54,43 +668,124 @@ fn main() {
         }
     });
 
-    let mut ram = RAM::init();
-    let display = Arc::new(Mutex::new(Display::init()));
-    let keyboard = Arc::new(Mutex::new(Keyboard::init(receiver)));
+    let mut ram = RAM::with_size(setup.rom.platform().memory_size());
+    let mut display = Display::init();
+    let keymap = setup.settings.keymap.clone().unwrap();
+    let keyboard = Arc::new(Mutex::new(Keyboard::with_keymap(receiver, keymap)));
 
-    let mut cpu_display = display.clone();
     let mut cpu_keyboard = keyboard.clone();
     let display_keyboard = keyboard.clone();
 
-    let mut logfile = File::create("opcode_logfile.txt").unwrap();
+    let mut logfile = match options.log {
+        Some(ref path) => Some(File::create(path)?),
+        None => None,
+    };
 
-    let mut cpu = CPU::init(&mut ram, &mut cpu_display, &mut cpu_keyboard, &mut logfile);
-    cpu.load_rom(&rom);
+    let mut cpu = CPU::init(&mut ram, &mut display, &mut cpu_keyboard, logfile.as_mut());
+    configure(&mut cpu, &setup, options);
 
     let display_hz: f64 = 60.0;
     let display_time = time::Duration::from_millis((1000.0 / display_hz).floor() as u64);
 
+    let (frame_sender, frame_receiver) = channel();
+    let (frame_publisher, mut frame_reader) = swap_chain();
+    cpu.set_frame_sender(frame_sender);
+    cpu.set_frame_publisher(frame_publisher);
+
+    // Draws the whole screen once, then only the rows that differ from the
+    // last frame shown. With phosphor, lit pixels go on fading while the
+    // screen stands still, so every frame is drawn. Exits once the CPU (and
+    // with it the sender) is gone.
     let handle_display = thread::spawn(move || {
-        let ascii_display = AsciiDisplay();
+        let keys = || display_keyboard.lock().unwrap().keys;
+        let mut shown = Display::init();
+        renderer.draw_rows(&shown, &keys(), framebuffer::all_rows(&shown));
         loop {
-            ascii_display.draw(&display.lock().unwrap(), &display_keyboard.lock().unwrap());
-            sleep(display_time);
+            let running = if fading {
+                frame_receiver.recv_timeout(display_time) != Err(RecvTimeoutError::Disconnected)
+            } else {
+                frame_receiver.recv().is_ok()
+            };
+            if !running {
+                break;
+            }
+            while frame_receiver.try_recv().is_ok() {}
+            match frame_reader.new_frame() {
+                Some(frame) => {
+                    renderer.draw_rows(frame, &keys(), frame.diff_rows(&shown) as u64);
+                    shown.clone_from(frame);
+                }
+                None if fading => renderer.draw(&shown, &keys()),
+                None => {}
+            }
         }
     });
 
-    let mut time = time::SystemTime::now();
+    let mut recorder = if options.command == Command::Record {
+        Some(Recorder::new(setup.scale(), setup.palette()))
+    } else {
+        None
+    };
+
+    // The keyboard is polled once a frame; the CPU takes the keys held from
+    // it as the frame ends.
+    let mut frame_end = time::Instant::now() + display_time;
+    let mut fault_shown = false;
     loop {
-        keyboard.lock().unwrap().read_input();
-        cpu.run_cycle();
-        if keyboard.lock().unwrap().exit_key() {
-            break;
+        {
+            let mut keyboard = keyboard.lock().unwrap();
+            keyboard.read_input();
+            if keyboard.exit_key() {
+                break;
+            }
+            if keyboard.take_screenshot_request() {
+                let dir = setup.settings.screenshot_dir.as_deref().unwrap_or_else(|| Path::new("."));
+                let saved = fs::create_dir_all(dir).and_then(|_| {
+                    let path = screenshot::next_free_path(dir, "rust8-screenshot", ImageFormat::Png);
+                    Screenshot::default().save(cpu.display(), path)
+                });
+                if let Err(err) = saved {
+                    eprint!("\r\nCouldn't save a screenshot in {}: {}\r\n", dir.display(), err);
+                }
+            }
+        }
+        // With a known tickrate, the rest of the frame is spent waiting.
+        match setup.settings.ipf {
+            Some(rate) => {
+                cpu.run_cycles(rate as u64);
+                let now = time::Instant::now();
+                if frame_end > now {
+                    thread::sleep(frame_end - now);
+                }
+            }
+            None => {
+                while time::Instant::now() < frame_end {
+                    cpu.run_cycles(FREE_RUN_BATCH);
+                }
+            }
+        }
+        cpu.end_frame();
+        // A stopped CPU leaves its last screen up until the exit key.
+        if let (Some(fault), false) = (cpu.fault(), fault_shown) {
+            eprint!("\r\nStopped: {} at 0x{:03X}; press {} to quit\r\n", fault, cpu.get_pc(), EXIT_CHAR);
+            fault_shown = true;
         }
-        if time::SystemTime::now() > time + display_time {
-            cpu.dec_delay();
-            time += display_time;
+        if let Some(ref mut recorder) = recorder {
+            recorder.capture(cpu.display());
+        }
+        frame_end += display_time;
+    }
+
+    if let Some(recorder) = recorder {
+        let path = &options.files[1];
+        if let Err(err) = recorder.save(path) {
+            eprintln!("Couldn't write recording {}: {}", path.display(), err);
         }
     }
 
+    let fault = check_fault(&cpu);
+    drop(cpu);
     handle_keyboard.join().unwrap();
     handle_display.join().unwrap();
+    fault
 }
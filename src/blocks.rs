@@ -0,0 +1,189 @@
+use std::ops::Range;
+
+use ram::RAM;
+
+// Longest block decoded, in instructions. Keeps the window searched when
+// memory is written small.
+pub const MAX_BLOCK: usize = 32;
+
+// Once this many instructions have been decoded, dropped blocks are swept
+// out by starting over.
+const MAX_DECODED: usize = 1 << 16;
+
+struct Block {
+    // Where the block's instructions sit in `ops`.
+    first: usize,
+    len: usize,
+    // One past the block's last byte in memory.
+    end: usize,
+}
+
+// Straight-line runs of instructions ("basic blocks"), decoded once and kept
+// by the address they start at. Each instruction is stored as whatever
+// `decode` made of its opcode.
+//
+// A block ends after any instruction that may not continue with the next one
+// (jumps, calls, returns, skips, key waits) or that writes memory, so code a
+// block changes is never run from the cache. Writes that land in a cached
+// block must be reported to `invalidate`.
+pub struct BlockCache<H> {
+    // For every address, 1 + the index in `blocks` of the block starting
+    // there, or 0.
+    starts: Vec<u32>,
+    // Whether each address has been decoded into a block since the last
+    // `clear`, so writes to data skip the search for blocks to drop.
+    decoded: Vec<bool>,
+    blocks: Vec<Block>,
+    ops: Vec<H>,
+}
+
+impl<H: Copy> BlockCache<H> {
+    pub fn new(memory_size: usize) -> BlockCache<H> {
+        BlockCache {
+            starts: vec![0; memory_size],
+            decoded: vec![false; memory_size],
+            blocks: Vec::new(),
+            ops: Vec::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for start in self.starts.iter_mut() {
+            *start = 0;
+        }
+        for decoded in self.decoded.iter_mut() {
+            *decoded = false;
+        }
+        self.blocks.clear();
+        self.ops.clear();
+    }
+
+    // The block starting at `pc`, decoding it from `ram` first if needed, as
+    // a range of indices for `op`. Empty if there's no whole opcode at `pc`.
+    pub fn block<F>(&mut self, pc: usize, ram: &RAM, decode: F) -> Range<usize>
+    where
+        F: Fn(u16) -> H,
+    {
+        if pc + 1 >= self.starts.len().min(ram.size()) {
+            return 0..0;
+        }
+        match self.starts[pc] {
+            0 => self.decode(pc, ram, decode),
+            index => {
+                let block = &self.blocks[index as usize - 1];
+                block.first..block.first + block.len
+            }
+        }
+    }
+
+    pub fn op(&self, index: usize) -> H {
+        self.ops[index]
+    }
+
+    fn decode<F>(&mut self, pc: usize, ram: &RAM, decode: F) -> Range<usize>
+    where
+        F: Fn(u16) -> H,
+    {
+        if self.ops.len() >= MAX_DECODED {
+            self.clear();
+        }
+        let first = self.ops.len();
+        let mut addr = pc;
+        while addr + 1 < ram.size() && self.ops.len() - first < MAX_BLOCK {
+            let opcode = ram.get_mem16(addr);
+            self.ops.push(decode(opcode));
+            addr += 2;
+            if ends_block(opcode) {
+                break;
+            }
+        }
+        let len = self.ops.len() - first;
+        for decoded in self.decoded[pc..addr].iter_mut() {
+            *decoded = true;
+        }
+        self.blocks.push(Block { first, len, end: addr });
+        self.starts[pc] = self.blocks.len() as u32;
+        first..first + len
+    }
+
+    // Forgets the blocks holding any of the `len` bytes from `addr`.
+    pub fn invalidate(&mut self, addr: usize, len: usize) {
+        let written = addr.min(self.decoded.len())..(addr + len).min(self.decoded.len());
+        if !self.decoded[written].iter().any(|&decoded| decoded) {
+            return;
+        }
+        let lowest = addr.saturating_sub(MAX_BLOCK * 2 - 1);
+        let highest = (addr + len).min(self.starts.len());
+        for start in lowest..highest {
+            let index = self.starts[start];
+            if index != 0 && self.blocks[index as usize - 1].end > addr {
+                self.starts[start] = 0;
+            }
+        }
+    }
+}
+
+// Whether the instruction might be followed by anything but the next one, or
+// writes memory.
+pub fn ends_block(opcode: u16) -> bool {
+    match opcode >> 12 {
+        0x0 => opcode == 0x00EE,
+        0x1 | 0x2 | 0x3 | 0x4 | 0x5 | 0x9 | 0xB | 0xE => true,
+        0xF => matches!(opcode & 0xFF, 0x0A | 0x33 | 0x55),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+fn test_ram(code: &[u8]) -> RAM {
+    let mut ram = RAM::init();
+    ram.load_rom(code);
+    ram
+}
+
+#[test]
+fn test_blocks_end_at_control_flow() {
+    let ram = test_ram(&[0x60, 0x01, 0x70, 0x02, 0x12, 0x00, 0x60, 0x03]);
+    let mut cache = BlockCache::new(ram.size());
+    let block = cache.block(0x200, &ram, |opcode| opcode >> 12);
+    assert_eq!(block.len(), 3);
+    let ops: Vec<u16> = block.map(|i| cache.op(i)).collect();
+    assert_eq!(ops, [0x6, 0x7, 0x1]);
+    assert_eq!(cache.block(0x202, &ram, |opcode| opcode >> 12).len(), 2);
+}
+
+#[test]
+fn test_blocks_are_cached() {
+    let mut ram = test_ram(&[0x60, 0x01, 0x12, 0x00]);
+    let mut cache = BlockCache::new(ram.size());
+    let first = cache.block(0x200, &ram, |opcode| opcode);
+    ram.set_mem8(0x201, 0x02);
+    assert_eq!(cache.block(0x200, &ram, |opcode| opcode), first);
+    assert_eq!(cache.op(first.start), 0x6001);
+}
+
+#[test]
+fn test_invalidate() {
+    let mut ram = test_ram(&[0x60, 0x01, 0x61, 0x02, 0xF1, 0x55, 0x60, 0x09]);
+    let mut cache = BlockCache::new(ram.size());
+    let decode = |opcode| opcode;
+    let block = cache.block(0x200, &ram, decode);
+    assert_eq!(block.len(), 3);
+
+    // Writes after the block leave it alone.
+    cache.invalidate(0x206, 2);
+    assert_eq!(cache.block(0x200, &ram, decode), block);
+
+    ram.set_mem8(0x203, 0x07);
+    cache.invalidate(0x203, 1);
+    let block = cache.block(0x200, &ram, decode);
+    assert_eq!(cache.op(block.start + 1), 0x6107);
+}
+
+#[test]
+fn test_block_at_end_of_memory() {
+    let ram = RAM::with_size(0x204);
+    let mut cache: BlockCache<u16> = BlockCache::new(ram.size());
+    assert_eq!(cache.block(0x200, &ram, |opcode| opcode).len(), 2);
+    assert_eq!(cache.block(0x203, &ram, |opcode| opcode).len(), 0);
+}
@@ -0,0 +1,241 @@
+use std::error::Error;
+use std::fmt;
+
+use asm::AsmError;
+use gif::{self, GifError};
+use json::{Json, JsonError};
+use octo;
+use palette::{Color, Palette};
+use platform::Platform;
+use quirks::Quirks;
+use rom;
+use romdb::RomInfo;
+
+// Octo hides a cartridge's payload in the pixels of an animated GIF: the low
+// four bits of each pixel's color index, two pixels to a byte with the high
+// nibble first, across all frames in order. The payload starts with its own
+// length as a big-endian u32 and is UTF-8 JSON of the form
+// `{"program": ..., "options": {...}}`, where the program is Octo source,
+// or in cartridges made by other tools, an array of bytes.
+#[derive(Debug)]
+pub enum CartridgeError {
+    Gif(GifError),
+    Json(JsonError),
+    Format(String),
+    // The Octo source didn't assemble.
+    Program(AsmError),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CartridgeError::Gif(ref err) => write!(f, "{}", err),
+            CartridgeError::Json(ref err) => write!(f, "cartridge payload: {}", err),
+            CartridgeError::Format(ref message) => write!(f, "not an Octo cartridge: {}", message),
+            CartridgeError::Program(ref err) => write!(f, "cartridge program: {}", err),
+        }
+    }
+}
+
+impl Error for CartridgeError {}
+
+impl From<GifError> for CartridgeError {
+    fn from(err: GifError) -> CartridgeError {
+        CartridgeError::Gif(err)
+    }
+}
+
+impl From<AsmError> for CartridgeError {
+    fn from(err: AsmError) -> CartridgeError {
+        CartridgeError::Program(err)
+    }
+}
+
+impl From<JsonError> for CartridgeError {
+    fn from(err: JsonError) -> CartridgeError {
+        CartridgeError::Json(err)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Cartridge {
+    pub program: Vec<u8>,
+    pub platform: Platform,
+    // The cartridge's options, in the same shape as a ROM database entry.
+    pub info: RomInfo,
+}
+
+impl Cartridge {
+    pub fn decode(title: &str, bytes: &[u8]) -> Result<Cartridge, CartridgeError> {
+        let payload = extract_payload(bytes)?;
+        let text = String::from_utf8(payload)
+            .map_err(|_| CartridgeError::Format("payload is not UTF-8".to_string()))?;
+        let json = Json::parse(&text)?;
+        let program = read_program(json.get("program"))?;
+        let empty = Json::Object(Vec::new());
+        let options = json.get("options").unwrap_or(&empty);
+
+        let max_size = options.get("maxSize").and_then(|size| size.as_u64());
+        let platform = match max_size {
+            Some(size) if size as usize > rom::max_size(Platform::SuperChip) => Platform::XoChip,
+            _ => rom::detect_platform(&program),
+        };
+        Ok(Cartridge {
+            info: RomInfo {
+                title: title.to_string(),
+                platform_id: Some(platform_id(platform).to_string()),
+                quirks: Some(read_quirks(options)),
+                tickrate: options.get("tickrate").and_then(|rate| rate.as_u64()).map(|rate| rate as u32),
+                palette: read_palette(options),
+                keys: Vec::new(),
+            },
+            program,
+            platform,
+        })
+    }
+}
+
+// Far more than Octo source for the largest XO-CHIP program needs; the
+// images may hold two pixels per byte of it, plus the length.
+const MAX_PAYLOAD: usize = 1 << 20;
+
+fn extract_payload(bytes: &[u8]) -> Result<Vec<u8>, CartridgeError> {
+    let frames = gif::decode(bytes, (MAX_PAYLOAD + 4) * 2)?;
+    let nibbles: Vec<u8> = frames
+        .iter()
+        .flat_map(|frame| frame.pixels.iter().map(|&pixel| pixel & 0x0F))
+        .collect();
+    let mut data = nibbles.chunks(2).filter(|pair| pair.len() == 2).map(|pair| pair[0] << 4 | pair[1]);
+    let mut header = [0; 4];
+    for byte in header.iter_mut() {
+        *byte = data
+            .next()
+            .ok_or_else(|| CartridgeError::Format("image too small to hold a payload".to_string()))?;
+    }
+    let len = u32::from_be_bytes(header) as usize;
+    let payload: Vec<u8> = data.take(len).collect();
+    if payload.len() < len {
+        return Err(CartridgeError::Format(format!(
+            "payload claims {} bytes but the image holds {}",
+            len,
+            payload.len()
+        )));
+    }
+    Ok(payload)
+}
+
+fn read_program(program: Option<&Json>) -> Result<Vec<u8>, CartridgeError> {
+    match program {
+        Some(Json::Array(bytes)) => bytes
+            .iter()
+            .map(|byte| byte.as_u64().filter(|&byte| byte < 256).map(|byte| byte as u8))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| CartridgeError::Format("program has a value that isn't a byte".to_string())),
+        Some(Json::String(source)) => Ok(octo::assemble(source)?),
+        _ => Err(CartridgeError::Format("no program".to_string())),
+    }
+}
+
+// Octo's quirk options all default to off, which is its own modern behaviour
+// rather than any of the database presets.
+fn read_quirks(options: &Json) -> Quirks {
+    let flag = |name: &str| options.get(name).and_then(|value| value.as_bool()).unwrap_or(false);
+    Quirks {
+        shift: flag("shiftQuirks"),
+        memory_increment_by_x: false,
+        memory_leave_i_unchanged: flag("loadStoreQuirks"),
+        wrap: !flag("clipQuirks"),
+        jump: flag("jumpQuirks"),
+        vblank: flag("vBlankQuirks"),
+        logic: flag("logicQuirks"),
+    }
+}
+
+// Background, then the colors of planes 1, 2 and both, as in XO-CHIP.
+fn read_palette(options: &Json) -> Option<Palette> {
+    let color = |name: &str| options.get(name).and_then(|value| value.as_str()).and_then(Color::from_hex);
+    let background = color("backgroundColor")?;
+    let fill = color("fillColor")?;
+    match (color("fillColor2"), color("blendColor")) {
+        (Some(fill2), Some(blend)) => Some(Palette::from_colors(vec![background, fill, fill2, blend])),
+        _ => Some(Palette::new(background, fill)),
+    }
+}
+
+fn platform_id(platform: Platform) -> &'static str {
+    match platform {
+        Platform::Chip8 => "modernChip8",
+        Platform::SuperChip => "superchip",
+        Platform::XoChip => "xochip",
+    }
+}
+
+#[cfg(test)]
+pub fn build_cartridge(payload: &str) -> Vec<u8> {
+    use gif::GifEncoder;
+
+    let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+    bytes.extend_from_slice(payload.as_bytes());
+    // Spread over as many 16x16 frames as it takes, with a label colour in
+    // the high bits.
+    let mut pixels: Vec<u8> = bytes.iter().flat_map(|&b| vec![0x10 | b >> 4, 0x10 | (b & 0x0F)]).collect();
+    let frames = pixels.len().div_ceil(256).max(2);
+    pixels.resize(frames * 256, 0);
+    let colors: Vec<Color> = (0..32).map(|i| Color::rgb(i * 8, 0, 0)).collect();
+    let mut encoder = GifEncoder::new(Vec::new(), 16, 16, &colors).unwrap();
+    for frame in pixels.chunks(256) {
+        encoder.write_frame(frame, 10).unwrap();
+    }
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn test_decode() {
+    let gif = build_cartridge(
+        r##"{"program": [0, 224, 18, 0], "options": {"tickrate": 500, "clipQuirks": true,
+            "shiftQuirks": true, "backgroundColor": "#996600", "fillColor": "#FFCC00"}}"##,
+    );
+    let cartridge = Cartridge::decode("cart", &gif).unwrap();
+    assert_eq!(cartridge.program, vec![0x00, 0xE0, 0x12, 0x00]);
+    assert_eq!(cartridge.platform, Platform::Chip8);
+    assert_eq!(cartridge.info.platform(), Some(Platform::Chip8));
+    assert_eq!(cartridge.info.tickrate, Some(500));
+    let quirks = cartridge.info.quirks.unwrap();
+    assert!(quirks.shift && !quirks.wrap && !quirks.jump);
+    assert_eq!(cartridge.info.palette, Palette::named("octo"));
+}
+
+#[test]
+fn test_xo_chip_size() {
+    let gif = build_cartridge(r#"{"program": [0, 224], "options": {"maxSize": 65024}}"#);
+    assert_eq!(Cartridge::decode("cart", &gif).unwrap().platform, Platform::XoChip);
+}
+
+// A payload as Octo writes one: the source as typed, and every option.
+#[test]
+fn test_octo_source() {
+    let gif = build_cartridge(
+        r##"{"program":"# Draws a digit, then stops.\n\n: main\n\tv0 := 7\n\ti := hex v0\n\tsprite v1 v1 5\n\tloop again\n","options":{"tickrate":20,"fillColor":"#FFCC00","fillColor2":"#FF6600","blendColor":"#662200","backgroundColor":"#996600","buzzColor":"#FFAA00","quietColor":"#000000","shiftQuirks":false,"loadStoreQuirks":false,"vfOrderQuirks":false,"clipQuirks":false,"vBlankQuirks":false,"jumpQuirks":false,"logicQuirks":false,"screenRotation":0,"maxSize":3584,"touchInputMode":"none","fontStyle":"octo"}}"##,
+    );
+    let cartridge = Cartridge::decode("cart", &gif).unwrap();
+    assert_eq!(cartridge.program, vec![0x60, 0x07, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06]);
+    assert_eq!(cartridge.platform, Platform::Chip8);
+    assert_eq!(cartridge.info.tickrate, Some(20));
+    assert_eq!(cartridge.info.palette.map(|palette| palette.len()), Some(4));
+}
+
+#[test]
+fn test_errors() {
+    match Cartridge::decode("cart", &build_cartridge(r#"{"program": ": main loop"}"#)) {
+        Err(CartridgeError::Program(ref err)) if err.message == "loop without again" => {}
+        other => panic!("{:?}", other),
+    }
+    match Cartridge::decode("cart", &build_cartridge(r#"{"options": {}}"#)) {
+        Err(CartridgeError::Format(_)) => {}
+        other => panic!("{:?}", other),
+    }
+    match Cartridge::decode("cart", b"\x00\xE0") {
+        Err(CartridgeError::Gif(GifError::NotAGif)) => {}
+        other => panic!("{:?}", other),
+    }
+}
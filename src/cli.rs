@@ -0,0 +1,334 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use config::Settings;
+use trace::Format;
+
+pub const USAGE: &str = "\
+Usage: rust8 COMMAND [OPTIONS] FILE...
+
+Commands:
+  run ROM            play a ROM (the default when no command is given)
+  debug ROM          step through a ROM from a prompt
+  disasm ROM         print a ROM as assembly
+  asm SOURCE         assemble SOURCE into a ROM
+  recompile ROM      translate a ROM into a Rust module
+  info ROM           show what is known about a ROM
+  trace ROM          log every instruction with the registers after it
+  replay ROM TRACE   run a ROM against another emulator's trace of it
+  bench ROM...       run ROMs headless and report their speed
+  regress MANIFEST   check ROMs' final screens against stored baselines
+  record ROM VIDEO   play a ROM, saving a .gif or .y4m of every frame
+  config show [ROM]  print the settings a ROM would run with
+
+Options:
+  --platform NAME    chip8, schip or xochip
+  --quirks SPEC      a quirks preset and/or quirks, e.g. superchip,no-jump
+  --ipf N            instructions per 60Hz frame
+  --seed N           seed for the random number generator
+  --keymap SPEC      dvorak, qwerty or the 16 characters for keys 0-F
+  --renderer NAME    auto, sixel or ascii
+  --scale N          pixel size for sixel output and recordings
+  --colors SPEC      a palette name or hex colors, background first
+  --phosphor MODE    keep pixels lit a while when playing: blend:N (the last
+                     N frames), decay:N (fade over N frames) or last-two
+  --screenshot-dir DIR
+                     where screenshots go (default: the current directory)
+  --log PATH         log every opcode run to PATH
+  --frames N         run headless for N frames, or until the ROM stops, then
+                     print the screen
+  --instructions N   instructions to run for bench, or at most headless
+  --timeout SECS     run headless for at most SECS seconds
+  --trace-format F   rust8, keyed or csv, with ,before if the trace logs
+                     registers before each instruction (default: guessed)
+  -o, --output PATH  where asm, disasm and recompile write their output, and
+                     where regress writes PNG diffs
+  --update           store new baselines for regress
+";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command {
+    Run,
+    Debug,
+    Disasm,
+    Asm,
+    Recompile,
+    Info,
+    Trace,
+    Replay,
+    Bench,
+    Regress,
+    Record,
+    ConfigShow,
+}
+
+impl Command {
+    pub fn from_name(name: &str) -> Option<Command> {
+        match name {
+            "run" => Some(Command::Run),
+            "debug" => Some(Command::Debug),
+            "disasm" => Some(Command::Disasm),
+            "asm" => Some(Command::Asm),
+            "recompile" => Some(Command::Recompile),
+            "info" => Some(Command::Info),
+            "trace" => Some(Command::Trace),
+            "replay" => Some(Command::Replay),
+            "bench" => Some(Command::Bench),
+            "regress" => Some(Command::Regress),
+            "record" => Some(Command::Record),
+            "config" => Some(Command::ConfigShow),
+            _ => None,
+        }
+    }
+
+    // How many file arguments the command takes.
+    fn files(&self) -> RangeInclusive<usize> {
+        match *self {
+            Command::Record | Command::Replay => 2..=2,
+            Command::ConfigShow => 0..=1,
+            Command::Bench => 1..=usize::MAX,
+            _ => 1..=1,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum CliError {
+    Help,
+    Usage(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CliError::Help => write!(f, "{}", USAGE),
+            CliError::Usage(ref message) => write!(f, "{}\n\n{}", message, USAGE),
+        }
+    }
+}
+
+impl Error for CliError {}
+
+// Everything on the command line. Settings that weren't given are left out,
+// so they can be filled in from config files, the ROM database or the
+// platform's defaults.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Options {
+    pub command: Command,
+    pub files: Vec<PathBuf>,
+    pub output: Option<PathBuf>,
+    pub settings: Settings,
+    pub seed: Option<u64>,
+    pub log: Option<PathBuf>,
+    pub frames: Option<u64>,
+    pub instructions: Option<u64>,
+    pub timeout: Option<Duration>,
+    pub trace_format: Option<Format>,
+    // Whether regress stores what it finds as the new baselines.
+    pub update: bool,
+}
+
+impl Options {
+    fn new(command: Command) -> Options {
+        Options {
+            command,
+            files: Vec::new(),
+            output: None,
+            settings: Settings::default(),
+            seed: None,
+            log: None,
+            frames: None,
+            instructions: None,
+            timeout: None,
+            trace_format: None,
+            update: false,
+        }
+    }
+
+    // Whether to run without a terminal, stopping at the first budget to
+    // run out.
+    pub fn is_headless(&self) -> bool {
+        self.frames.is_some() || self.timeout.is_some()
+    }
+
+    // The ROM, the source file for `asm` or the manifest for `regress`.
+    pub fn input(&self) -> &PathBuf {
+        &self.files[0]
+    }
+}
+
+// `args` excludes the program name. Options may come before or after the
+// files, and take their value either as the next argument or after `=`.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Options, CliError> {
+    let mut args = args.into_iter().peekable();
+    let command = match args.peek().map(|arg| arg.as_str()) {
+        None | Some("help") | Some("-h") | Some("--help") => return Err(CliError::Help),
+        Some(name) => Command::from_name(name),
+    };
+    let mut options = match command {
+        Some(command) => {
+            args.next();
+            Options::new(command)
+        }
+        None => Options::new(Command::Run),
+    };
+    if options.command == Command::ConfigShow && args.next().as_deref() != Some("show") {
+        return Err(usage("the only config command is \"config show\"".to_string()));
+    }
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            options.files.push(PathBuf::from(arg));
+            continue;
+        }
+        if arg == "-h" || arg == "--help" {
+            return Err(CliError::Help);
+        }
+        if arg == "--update" {
+            options.update = true;
+            continue;
+        }
+        let (name, inline) = match arg.find('=') {
+            Some(eq) => (arg[..eq].to_string(), Some(arg[eq + 1..].to_string())),
+            None => (arg.clone(), None),
+        };
+        let value = match inline.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(usage(format!("{} needs a value", name))),
+        };
+        let bad = || usage(format!("bad value for {}: {}", name, value));
+        match name.as_str() {
+            "--seed" => options.seed = Some(value.parse().map_err(|_| bad())?),
+            "--log" => options.log = Some(PathBuf::from(value)),
+            "--frames" => options.frames = Some(value.parse().map_err(|_| bad())?),
+            "--instructions" => options.instructions = Some(value.parse().map_err(|_| bad())?),
+            "--timeout" => {
+                let secs: f64 = value.parse().map_err(|_| bad())?;
+                if !(secs > 0.0 && secs.is_finite()) {
+                    return Err(bad());
+                }
+                options.timeout = Some(Duration::from_secs_f64(secs));
+            }
+            "--trace-format" => options.trace_format = Some(Format::parse(&value).ok_or_else(bad)?),
+            "-o" | "--output" => options.output = Some(PathBuf::from(value)),
+            _ => match name.strip_prefix("--") {
+                Some(key) if Settings::keys().contains(&key) => {
+                    if !options.settings.set(key, &value) {
+                        return Err(bad());
+                    }
+                }
+                _ => return Err(usage(format!("unknown option {}", name))),
+            },
+        }
+    }
+
+    if !options.command.files().contains(&options.files.len()) {
+        let what = match options.command {
+            Command::Asm => "a source file",
+            Command::Record => "a ROM and a video file",
+            Command::Replay => "a ROM and a trace",
+            Command::ConfigShow => "at most one ROM",
+            Command::Bench => "at least one ROM",
+            Command::Regress => "a manifest",
+            _ => "a ROM",
+        };
+        return Err(usage(format!("expected {}", what)));
+    }
+    Ok(options)
+}
+
+fn usage(message: String) -> CliError {
+    CliError::Usage(message)
+}
+
+#[cfg(test)]
+fn parse_str(args: &str) -> Result<Options, CliError> {
+    parse(args.split_whitespace().map(|arg| arg.to_string()))
+}
+
+#[test]
+fn test_bare_rom_runs() {
+    let options = parse_str("game.ch8").unwrap();
+    assert_eq!(options.command, Command::Run);
+    assert_eq!(options.input(), &PathBuf::from("game.ch8"));
+    assert_eq!(options.settings, Settings::default());
+}
+
+#[test]
+fn test_options() {
+    use config::RendererChoice;
+    use keyboard::Keymap;
+    use palette::Palette;
+    use phosphor::PhosphorMode;
+    use platform::Platform;
+    use quirks::Quirks;
+
+    let options = parse_str(
+        "trace --platform schip game.sc8 --quirks=superchip,no-jump --ipf 30 --seed 7 \
+         --keymap qwerty --renderer ascii --scale 2 --colors amber --phosphor blend:3 --log out.txt --frames 100",
+    )
+    .unwrap();
+    assert_eq!(options.command, Command::Trace);
+    let settings = &options.settings;
+    assert_eq!(settings.platform, Some(Platform::SuperChip));
+    assert_eq!(settings.quirks, Quirks::parse("superchip,no-jump"));
+    assert_eq!((settings.ipf, options.seed, options.frames), (Some(30), Some(7), Some(100)));
+    assert_eq!(settings.keymap, Keymap::parse("qwerty"));
+    assert_eq!(settings.renderer, Some(RendererChoice::Ascii));
+    assert_eq!(settings.scale, Some(2));
+    assert_eq!(settings.palette, Palette::named("amber"));
+    assert_eq!(settings.phosphor, Some(PhosphorMode::Blend(3)));
+    assert_eq!(options.log, Some(PathBuf::from("out.txt")));
+    assert!(options.is_headless());
+}
+
+#[test]
+fn test_timeout() {
+    let options = parse_str("run game.ch8 --timeout 2.5 --instructions 1000").unwrap();
+    assert_eq!(options.timeout, Some(Duration::from_millis(2500)));
+    assert_eq!(options.instructions, Some(1000));
+    assert!(options.is_headless());
+    assert!(!parse_str("run game.ch8").unwrap().is_headless());
+    assert!(parse_str("run game.ch8 --timeout 0").is_err());
+    assert!(parse_str("run game.ch8 --timeout -1").is_err());
+    assert!(parse_str("run game.ch8 --timeout soon").is_err());
+}
+
+#[test]
+fn test_files() {
+    let options = parse_str("record game.ch8 out.gif").unwrap();
+    assert_eq!(options.files, vec![PathBuf::from("game.ch8"), PathBuf::from("out.gif")]);
+    assert_eq!(parse_str("asm game.asm -o game.ch8").unwrap().output, Some(PathBuf::from("game.ch8")));
+    assert_eq!(parse_str("recompile game.ch8 -o game.rs").unwrap().command, Command::Recompile);
+    assert!(parse_str("record game.ch8").is_err());
+    assert!(parse_str("info a.ch8 b.ch8").is_err());
+    assert_eq!(parse_str("bench a.ch8 b.ch8").unwrap().files.len(), 2);
+    assert!(parse_str("bench").is_err());
+    assert!(parse_str("config show").unwrap().files.is_empty());
+    assert_eq!(parse_str("config show game.ch8").unwrap().command, Command::ConfigShow);
+    assert!(parse_str("config edit").is_err());
+    let options = parse_str("regress roms.manifest --update").unwrap();
+    assert_eq!((options.command, options.update), (Command::Regress, true));
+    assert!(parse_str("regress").is_err());
+    let options = parse_str("replay game.ch8 other.log --trace-format keyed,before").unwrap();
+    assert_eq!((options.command, options.files.len()), (Command::Replay, 2));
+    assert_eq!(options.trace_format, Format::parse("keyed,before"));
+    assert!(parse_str("replay game.ch8").is_err());
+    assert!(parse_str("replay game.ch8 other.log --trace-format bochs").is_err());
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(parse_str(""), Err(CliError::Help));
+    assert_eq!(parse_str("run --help"), Err(CliError::Help));
+    assert!(parse_str("run game.ch8 --ipf").is_err());
+    assert!(parse_str("run game.ch8 --ipf fast").is_err());
+    assert!(parse_str("bench game.ch8 --ipf 0").is_err());
+    assert!(parse_str("run game.ch8 --scale 0").is_err());
+    assert!(parse_str("run game.ch8 --phosphor glow").is_err());
+    assert!(parse_str("run game.ch8 --platform gameboy").is_err());
+    assert!(parse_str("run game.ch8 --bogus 1").is_err());
+}
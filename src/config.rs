@@ -0,0 +1,386 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use keyboard::Keymap;
+use palette::Palette;
+use phosphor::PhosphorMode;
+use platform::Platform;
+use quirks::Quirks;
+use romdb::RomInfo;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RendererChoice {
+    Auto,
+    Sixel,
+    Ascii,
+}
+
+impl RendererChoice {
+    pub fn from_name(name: &str) -> Option<RendererChoice> {
+        match name {
+            "auto" => Some(RendererChoice::Auto),
+            "sixel" => Some(RendererChoice::Sixel),
+            "ascii" => Some(RendererChoice::Ascii),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            RendererChoice::Auto => "auto",
+            RendererChoice::Sixel => "sixel",
+            RendererChoice::Ascii => "ascii",
+        }
+    }
+}
+
+// How to run a ROM, as far as one source (a config section, the ROM database,
+// the command line) says. Anything it doesn't say is `None`.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Settings {
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    // Instructions per 60Hz frame.
+    pub ipf: Option<u32>,
+    pub keymap: Option<Keymap>,
+    pub renderer: Option<RendererChoice>,
+    pub scale: Option<usize>,
+    pub palette: Option<Palette>,
+    // Persistence of lit pixels when playing.
+    pub phosphor: Option<PhosphorMode>,
+    pub screenshot_dir: Option<PathBuf>,
+}
+
+impl Settings {
+    pub fn keys() -> &'static [&'static str] {
+        &["platform", "quirks", "ipf", "keymap", "renderer", "scale", "colors", "phosphor", "screenshot-dir"]
+    }
+
+    // Parses `value` for the setting `key`, one of `keys()`. Returns false if
+    // either is no good. The command line's `--key value` options are the
+    // same settings.
+    pub fn set(&mut self, key: &str, value: &str) -> bool {
+        fn store<T>(slot: &mut Option<T>, value: Option<T>) -> bool {
+            let ok = value.is_some();
+            if ok {
+                *slot = value;
+            }
+            ok
+        }
+        match key {
+            "platform" => store(&mut self.platform, Platform::from_name(value)),
+            "quirks" => store(&mut self.quirks, Quirks::parse(value)),
+            "ipf" => store(&mut self.ipf, value.parse().ok().filter(|&ipf| ipf > 0)),
+            "keymap" => store(&mut self.keymap, Keymap::parse(value)),
+            "renderer" => store(&mut self.renderer, RendererChoice::from_name(value)),
+            "scale" => store(&mut self.scale, value.parse().ok().filter(|&scale| scale > 0)),
+            "colors" => store(&mut self.palette, Palette::parse(value)),
+            "phosphor" => store(&mut self.phosphor, PhosphorMode::parse(value)),
+            "screenshot-dir" => store(&mut self.screenshot_dir, Some(expand_home(value))),
+            _ => false,
+        }
+    }
+
+    // What the ROM database or a cartridge says about a ROM.
+    pub fn from_info(info: &RomInfo) -> Settings {
+        Settings {
+            platform: info.platform(),
+            quirks: info.quirks,
+            ipf: info.tickrate.filter(|&rate| rate > 0),
+            palette: info.palette.clone(),
+            ..Settings::default()
+        }
+    }
+
+    // Settings in `over` replace these. A platform without quirks brings the
+    // platform's own quirks rather than keeping ones meant for another.
+    pub fn merge(&mut self, over: &Settings) {
+        if over.platform.is_some() && over.quirks.is_none() {
+            self.quirks = None;
+        }
+        macro_rules! take {
+            ($($field:ident),*) => {
+                $(if over.$field.is_some() {
+                    self.$field = over.$field.clone();
+                })*
+            };
+        }
+        take!(platform, quirks, ipf, keymap, renderer, scale, palette, phosphor, screenshot_dir);
+    }
+}
+
+// The settings that are set, one `key = value` line each, in the form the
+// config file reads back.
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(platform) = self.platform {
+            writeln!(f, "platform = {}", platform)?;
+        }
+        if let Some(quirks) = self.quirks {
+            writeln!(f, "quirks = {}", quirks)?;
+        }
+        if let Some(ipf) = self.ipf {
+            writeln!(f, "ipf = {}", ipf)?;
+        }
+        if let Some(ref keymap) = self.keymap {
+            writeln!(f, "keymap = {}", keymap.layout())?;
+        }
+        if let Some(renderer) = self.renderer {
+            writeln!(f, "renderer = {}", renderer.name())?;
+        }
+        if let Some(scale) = self.scale {
+            writeln!(f, "scale = {}", scale)?;
+        }
+        if let Some(ref palette) = self.palette {
+            writeln!(f, "colors = {}", palette.to_spec())?;
+        }
+        if let Some(phosphor) = self.phosphor {
+            writeln!(f, "phosphor = {}", phosphor.to_spec())?;
+        }
+        if let Some(ref screenshot_dir) = self.screenshot_dir {
+            writeln!(f, "screenshot-dir = {}", screenshot_dir.display())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Syntax { line: usize, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref path, ref err) => write!(f, "couldn't read {}: {}", path.display(), err),
+            ConfigError::Syntax { line, ref message } => write!(f, "config line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+// A line of a config file, or of anything laid out like one (see `Config`):
+// a section header or a `key = value` setting.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Line<'a> {
+    Section(&'a str),
+    Setting(&'a str, &'a str),
+}
+
+// The section headers and settings in `text`, with their line numbers,
+// skipping blank lines and lines starting with `#`. Names, keys and values
+// are trimmed, and section names are never empty.
+pub fn parse_lines(text: &str) -> Result<Vec<(usize, Line<'_>)>, ConfigError> {
+    let mut lines = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let syntax = |message: String| ConfigError::Syntax { line: i + 1, message };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            let name = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+                .map(|name| name.trim())
+                .filter(|name| !name.is_empty())
+                .ok_or_else(|| syntax(format!("bad section header {}", line)))?;
+            lines.push((i + 1, Line::Section(name)));
+            continue;
+        }
+        match line.find('=') {
+            Some(eq) => lines.push((i + 1, Line::Setting(line[..eq].trim(), line[eq + 1..].trim()))),
+            None => return Err(syntax(format!("expected key = value, found {}", line))),
+        }
+    }
+    Ok(lines)
+}
+
+// Which ROMs a section applies to.
+#[derive(Clone, PartialEq, Debug)]
+pub enum RomKey {
+    Sha1(String),
+    FileName(String),
+}
+
+// A config file: settings for every ROM at the top, then sections of settings
+// for particular ROMs, headed by the ROM's SHA-1 or file name:
+//
+//     ipf = 15
+//     keymap = qwerty
+//
+//     [pong.ch8]
+//     quirks = superchip,no-jump
+//
+//     [a9993e364706816aba3e25717850c26c9cd0d89d]
+//     colors = amber
+//
+// Blank lines and lines starting with `#` are ignored.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Config {
+    pub global: Settings,
+    pub roms: Vec<(RomKey, Settings)>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        for (number, line) in parse_lines(text)? {
+            let syntax = |message: String| ConfigError::Syntax { line: number, message };
+            let (key, value) = match line {
+                Line::Section(name) => {
+                    let key = if name.len() == 40 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+                        RomKey::Sha1(name.to_ascii_lowercase())
+                    } else {
+                        RomKey::FileName(name.to_string())
+                    };
+                    config.roms.push((key, Settings::default()));
+                    continue;
+                }
+                Line::Setting(key, value) => (key, value),
+            };
+            let settings = match config.roms.last_mut() {
+                Some((_, settings)) => settings,
+                None => &mut config.global,
+            };
+            if !Settings::keys().contains(&key) {
+                return Err(syntax(format!("unknown setting {}", key)));
+            }
+            if !settings.set(key, value) {
+                return Err(syntax(format!("bad value for {}: {}", key, value)));
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let path = path.as_ref();
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        Config::parse(&text)
+    }
+
+    // The user's config file, or an empty config if there isn't one.
+    pub fn standard() -> Result<Config, ConfigError> {
+        match config_path() {
+            Some(ref path) if path.exists() => Config::load(path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    // The sections for one ROM, merged in file order. Sections for its hash
+    // come after those for its file name, so they win.
+    pub fn for_rom(&self, file_name: &str, sha1_hex: &str) -> Settings {
+        let mut settings = Settings::default();
+        for (key, section) in self.roms.iter() {
+            if *key == RomKey::FileName(file_name.to_string()) {
+                settings.merge(section);
+            }
+        }
+        for (key, section) in self.roms.iter() {
+            if *key == RomKey::Sha1(sha1_hex.to_ascii_lowercase()) {
+                settings.merge(section);
+            }
+        }
+        settings
+    }
+}
+
+// `$RUST8_CONFIG`, or `~/.config/rust8/config`.
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("RUST8_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    env::var_os("HOME").map(|home| Path::new(&home).join(".config/rust8/config"))
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+const SAMPLE: &str = "
+# Defaults
+ipf = 15
+keymap = qwerty
+
+[pong.ch8]
+quirks = superchip,no-jump
+ipf = 30
+
+[A9993E364706816ABA3E25717850C26C9CD0D89D]
+platform = xochip
+colors = amber
+";
+
+#[test]
+fn test_parse() {
+    let config = Config::parse(SAMPLE).unwrap();
+    assert_eq!(config.global.ipf, Some(15));
+    assert_eq!(config.global.keymap, Keymap::parse("qwerty"));
+    assert_eq!(config.roms.len(), 2);
+    assert_eq!(config.roms[0].0, RomKey::FileName("pong.ch8".to_string()));
+    assert_eq!(config.roms[1].0, RomKey::Sha1("a9993e364706816aba3e25717850c26c9cd0d89d".to_string()));
+}
+
+#[test]
+fn test_for_rom() {
+    let config = Config::parse(SAMPLE).unwrap();
+    let pong = config.for_rom("pong.ch8", "0000");
+    assert_eq!(pong.ipf, Some(30));
+    assert_eq!(pong.quirks, Quirks::parse("superchip,no-jump"));
+    assert_eq!(pong.keymap, None);
+
+    // The hash section's platform drops the file name section's quirks.
+    let both = config.for_rom("pong.ch8", "a9993e364706816aba3e25717850c26c9cd0d89d");
+    assert_eq!(both.platform, Some(Platform::XoChip));
+    assert_eq!(both.quirks, None);
+    assert_eq!(both.ipf, Some(30));
+    assert_eq!(both.palette, Palette::named("amber"));
+
+    assert_eq!(config.for_rom("other.ch8", "0000"), Settings::default());
+}
+
+#[test]
+fn test_display_round_trips() {
+    let config = Config::parse(SAMPLE).unwrap();
+    let mut settings = config.global.clone();
+    settings.merge(&config.for_rom("pong.ch8", "a9993e364706816aba3e25717850c26c9cd0d89d"));
+    settings.set("screenshot-dir", "/tmp/shots");
+    settings.set("phosphor", "decay:6");
+    assert_eq!(Config::parse(&settings.to_string()).unwrap().global, settings);
+}
+
+#[test]
+fn test_parse_lines() {
+    let lines = parse_lines("# settings\nipf = 15\n\n[ pong.ch8 ]\ncolors=amber\n").unwrap();
+    assert_eq!(
+        lines,
+        [(2, Line::Setting("ipf", "15")), (4, Line::Section("pong.ch8")), (5, Line::Setting("colors", "amber"))]
+    );
+}
+
+#[test]
+fn test_errors() {
+    match Config::parse("ipf = 15\nipf = fast") {
+        Err(ConfigError::Syntax { line: 2, .. }) => {}
+        other => panic!("{:?}", other),
+    }
+    assert!(Config::parse("ipf = 0").is_err());
+    assert!(Config::parse("speed = 3").is_err());
+    assert!(Config::parse("[pong.ch8").is_err());
+    assert!(Config::parse("[]").is_err());
+    assert!(Config::parse("just words").is_err());
+}
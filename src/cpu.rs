@@ -1,15 +1,35 @@
 extern crate rand;
 
+use self::rand::{Rng, SeedableRng, XorShiftRng};
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
+use std::sync::mpsc::Sender;
 use std::sync::Arc;
 use std::sync::Mutex;
 
-use display::Display;
+use blocks::BlockCache;
+use display::{Display, DrawMode, FrameReady};
+use instruction::Instruction;
+use swapchain::FramePublisher;
 use keyboard::Keyboard;
+use observer::Observer;
 use opcode::Opcode;
+use quirks::Quirks;
 use ram::RAM;
 
+// Calls `$event` on every registered observer. With none registered this is
+// a single length check, and the event's arguments are never computed.
+macro_rules! notify {
+    ($cpu:expr, $observer:ident => $event:expr) => {
+        for $observer in $cpu.observers.iter_mut() {
+            $event;
+        }
+    };
+}
+
+// Calls deeper than this are a fault, as on the original interpreters.
+pub const STACK_DEPTH: usize = 16;
+
 pub struct CPU<'a> {
     sound_reg: u8,
     delay_reg: u8,
@@ -18,19 +38,41 @@ pub struct CPU<'a> {
     i: u16,
     reg: [u8; 16],
     ram: &'a mut RAM,
-    display: &'a mut Arc<Mutex<Display>>,
+    display: &'a mut Display,
     keyboard: &'a mut Arc<Mutex<Keyboard>>,
-    logfile: &'a mut File,
+    // The keys held as of the last frame's end, which EX9E/EXA1 read, so
+    // they needn't lock the keyboard.
+    keys: [bool; 16],
+    // Whether EX9E/EXA1 ran this frame, so the end of it takes new input.
+    keys_read: bool,
+    // Every opcode run, one per line, when tracing is on.
+    logfile: Option<BufWriter<&'a mut File>>,
+    frame_sender: Option<Sender<FrameReady>>,
+    frame_publisher: Option<FramePublisher>,
+    last_generation: u64,
+    observers: Vec<Box<dyn Observer>>,
+    quirks: Quirks,
+    rng: XorShiftRng,
+    // Numbers CXNN has drawn from `rng`.
+    draws: u64,
+    blocks: BlockCache<Instruction>,
+    // Why the CPU stopped, if it met an instruction it can't run.
+    fault: Option<String>,
+    // Set by DXYN with the vblank quirk: nothing more runs until the frame
+    // ends, as the original interpreter waited for the display interrupt.
+    vblank_wait: bool,
 }
 
 impl<'a> CPU<'a> {
     pub fn init(
         ram: &'a mut RAM,
-        display: &'a mut Arc<Mutex<Display>>,
+        display: &'a mut Display,
         keyboard: &'a mut Arc<Mutex<Keyboard>>,
-        logfile: &'a mut File,
+        logfile: Option<&'a mut File>,
     ) -> CPU<'a> {
-        CPU {
+        let blocks = BlockCache::new(ram.size());
+        let keys = keyboard.lock().unwrap().keys;
+        let mut cpu = CPU {
             sound_reg: 0,
             delay_reg: 0,
             stack: Vec::with_capacity(16),
@@ -40,18 +82,77 @@ impl<'a> CPU<'a> {
             ram,
             display,
             keyboard,
-            logfile,
-        }
+            keys,
+            keys_read: false,
+            logfile: logfile.map(BufWriter::new),
+            frame_sender: None,
+            frame_publisher: None,
+            last_generation: 0,
+            observers: Vec::new(),
+            quirks: Quirks::default(),
+            rng: rand::weak_rng(),
+            draws: 0,
+            blocks,
+            fault: None,
+            vblank_wait: false,
+        };
+        cpu.set_quirks(Quirks::default());
+        cpu
+    }
+
+    // Wrapping is the display's business, so it is switched there too.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        self.display
+            .set_draw_mode(if quirks.wrap { DrawMode::Wrap } else { DrawMode::Clip });
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    // Makes CXNN produce the same numbers on every run with the same seed.
+    pub fn set_seed(&mut self, seed: u64) {
+        // Xorshift never leaves an all-zero state, so the seed is mixed with
+        // constants that keep it from starting in one.
+        let lo = seed as u32;
+        let hi = (seed >> 32) as u32;
+        self.rng = XorShiftRng::from_seed([lo ^ 0x193A_6754, hi ^ 0xA8A7_D469, 0x9783_0E05, 0x113B_A7BB]);
+    }
+
+    // Renderers listening on `sender` hear about every frame that changed the
+    // screen, instead of polling the display.
+    pub fn set_frame_sender(&mut self, sender: Sender<FrameReady>) {
+        self.frame_sender = Some(sender);
+    }
+
+    // Completed frames are copied to `publisher` for renderers on other
+    // threads, so drawing never has to share the display with the CPU.
+    pub fn set_frame_publisher(&mut self, publisher: FramePublisher) {
+        self.frame_publisher = Some(publisher);
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+    }
+
+    pub fn display(&self) -> &Display {
+        self.display
     }
 
     pub fn get_display(&self) -> [u64; 32] {
-        self.display.lock().unwrap().get_display()
+        self.display.get_display()
     }
 
     pub fn get_reg(&self, x: usize) -> u8 {
         self.reg[x]
     }
 
+    // For replaying another emulator's random numbers.
+    pub fn set_reg(&mut self, x: usize, val: u8) {
+        self.reg[x] = val;
+    }
+
     pub fn get_carry(&self) -> u8 {
         self.get_reg(15)
     }
@@ -68,8 +169,61 @@ impl<'a> CPU<'a> {
         self.i
     }
 
+    pub fn get_pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn get_stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn get_mem8(&self, addr: usize) -> u8 {
+        self.ram.get_mem8(addr)
+    }
+
+    // All of memory, for comparing against another machine's.
+    pub fn memory(&self) -> &[u8] {
+        self.ram.bytes()
+    }
+
+    // Whether `key` is held, as EX9E/EXA1 see it.
     pub fn get_key(&self, key: usize) -> bool {
-        self.keyboard.lock().unwrap().is_pressed(key)
+        self.keys[key]
+    }
+
+    // Takes the keyboard's next input, then the keys held after it.
+    pub fn poll_keys(&mut self) {
+        self.keyboard.lock().unwrap().read_input();
+        self.sync_keys();
+    }
+
+    // Takes the keys held now, for keys pressed or released on the keyboard
+    // directly.
+    pub fn sync_keys(&mut self) {
+        self.keys = self.keyboard.lock().unwrap().keys;
+    }
+
+    // Whether a logfile or observers want to hear about every instruction
+    // run, as only `run_cycle` tells them.
+    pub fn is_traced(&self) -> bool {
+        self.logfile.is_some() || !self.observers.is_empty()
+    }
+
+    // Why the CPU stopped, if it did. Once faulted it runs nothing more.
+    pub fn fault(&self) -> Option<&str> {
+        self.fault.as_deref()
+    }
+
+    // Whether a sprite was drawn with the vblank quirk on, so the CPU idles
+    // until `end_frame`.
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.vblank_wait
+    }
+
+    // How many random numbers CXNN has drawn. A run that draws none does
+    // the same thing from the same state every time.
+    pub fn random_draws(&self) -> u64 {
+        self.draws
     }
 
     pub fn get_at_i(&self) -> u8 {
@@ -79,290 +233,387 @@ impl<'a> CPU<'a> {
     pub fn load_rom(&mut self, rom: &[u8]) {
         self.ram.load_fontset();
         self.ram.load_rom(rom);
+        self.blocks.clear();
+    }
+
+    // PC wraps around the end of memory, as every address does.
+    fn inc_pc(&mut self) {
+        let pc = self.pc as usize + 2;
+        self.pc = if pc < self.ram.size() { pc } else { pc - self.ram.size() } as u16;
     }
 
-    fn fetch(&self) -> Opcode {
-        Opcode::from_rom(self.ram.get_mem16(self.pc as usize))
+    fn wrap(&self, addr: usize) -> u16 {
+        (addr % self.ram.size()) as u16
     }
 
-    fn inc_pc(&mut self) {
-        self.pc += 2;
+    // Forgets cached code in the `len` bytes from `addr`, which may run past
+    // the end of memory and wrap.
+    fn invalidate(&mut self, addr: usize, len: usize) {
+        let addr = addr % self.ram.size();
+        let first = len.min(self.ram.size() - addr);
+        self.blocks.invalidate(addr, first);
+        if first < len {
+            self.blocks.invalidate(0, len - first);
+        }
     }
 
     pub fn dec_delay(&mut self) {
         if self.sound_reg > 0 {
             self.sound_reg -= 1;
+            if self.sound_reg == 0 {
+                notify!(self, observer => observer.sound(false));
+            }
         }
         if self.delay_reg > 0 {
             self.delay_reg -= 1;
         }
     }
 
-    fn set_carry(&mut self, carry: u8) {
-        self.reg[15] = carry;
-    }
-
-    fn run_0(&mut self, data: u16) {
-        match data {
-            0xE0 => {
-                self.display.lock().unwrap().clear();
-                self.inc_pc();
+    // Called once per 60Hz frame: ticks the timers, takes the keys held for
+    // the next frame and, if anything was drawn since the previous frame,
+    // publishes and announces the new one. Keyboard input is only taken for
+    // frames that read keys, so presses wait for FX0A otherwise.
+    pub fn end_frame(&mut self) {
+        self.vblank_wait = false;
+        if self.keys_read {
+            self.keys_read = false;
+            self.poll_keys();
+        } else {
+            self.sync_keys();
+        }
+        self.dec_delay();
+        let generation = self.display.generation();
+        if generation != self.last_generation {
+            self.last_generation = generation;
+            if let Some(ref mut publisher) = self.frame_publisher {
+                publisher.publish(self.display);
             }
-            0xEE => {
-                self.pc = self.stack.pop().unwrap();
-                self.inc_pc();
+            if let Some(ref sender) = self.frame_sender {
+                let _ = sender.send(FrameReady { generation });
             }
-            _ => panic!("Illegal data for 0x0_ op: {}", data),
         }
     }
 
-    fn run_1(&mut self, data: u16) {
-        self.pc = data;
-    }
-
-    fn run_2(&mut self, data: u16) {
-        self.stack.push(self.pc);
-        self.pc = data;
+    fn set_carry(&mut self, carry: u8) {
+        self.reg[15] = carry;
     }
 
-    fn run_3(&mut self, data: u16) {
-        let x = (data >> 8) as usize;
-        let comp = (data & 0xFF) as u8;
-        if self.reg[x] == comp {
-            self.inc_pc();
+    fn set_sound(&mut self, val: u8) {
+        if (self.sound_reg == 0) != (val == 0) {
+            notify!(self, observer => observer.sound(val != 0));
         }
-        self.inc_pc();
+        self.sound_reg = val;
     }
 
-    fn run_4(&mut self, data: u16) {
-        let x = (data >> 8) as usize;
-        let comp = (data & 0xFF) as u8;
-        if self.reg[x] != comp {
-            self.inc_pc();
-        }
-        self.inc_pc();
+    // Stops the CPU at the current instruction, for good.
+    fn fail(&mut self, message: String) {
+        notify!(self, observer => observer.error(self.pc, &message));
+        self.fault = Some(message);
     }
 
-    fn run_5(&mut self, data: u16) {
-        let x = (0x0F & (data >> 8)) as usize;
-        let y = (0x0F & (data >> 4)) as usize;
-        if self.reg[x] == self.reg[y] {
-            self.inc_pc();
-        }
-
-        self.inc_pc();
-    }
-
-    fn run_6(&mut self, data: u16) {
-        let x = (data >> 8) as usize;
-        let val = (data & 0xFF) as u8;
-        self.reg[x] = val;
-
-        self.inc_pc();
-    }
+    // Runs one decoded instruction and nothing more: `execute` adds the
+    // checks for faults and vblank waits, logging and observers. Recompiled
+    // programs call it directly. As in an opcode, only the low nibble of a
+    // register and the low 12 bits of an address count.
+    pub fn run(&mut self, instruction: Instruction) {
+        use instruction::Instruction::*;
 
-    fn run_7(&mut self, data: u16) {
-        let x = (data >> 8) as usize;
-        let val = (data & 0xFF) as u8;
-        self.reg[x] = self.reg[x].wrapping_add(val);
-
-        self.inc_pc();
-    }
-
-    fn run_8(&mut self, data: u16) {
-        let x = (data >> 8) as usize;
-        let y = ((data >> 4) & 0x0F) as usize;
-        let op = (data & 0x0F) as u8;
-        match op {
-            0 => self.reg[x] = self.reg[y],
-            1 => self.reg[x] |= self.reg[y],
-            2 => self.reg[x] &= self.reg[y],
-            3 => self.reg[x] ^= self.reg[y],
-            4 => {
-                let (res, carry) = self.reg[x].overflowing_add(self.reg[y]);
-                self.reg[x] = res;
-                if carry {
-                    self.set_carry(1);
-                } else {
-                    self.set_carry(0);
+        match instruction {
+            Cls => {
+                self.display.clear();
+                notify!(self, observer => observer.screen_cleared());
+            }
+            Ret => {
+                match self.stack.pop() {
+                    Some(addr) => {
+                        let from = self.pc;
+                        self.pc = addr;
+                        self.inc_pc();
+                        notify!(self, observer => observer.subroutine_return(from, self.pc));
+                    }
+                    None => self.fail("RET with an empty stack".to_string()),
+                }
+                return;
+            }
+            Jp(nnn) => {
+                self.pc = nnn & 0x0FFF;
+                return;
+            }
+            Call(nnn) => {
+                if self.stack.len() == STACK_DEPTH {
+                    return self.fail(format!("CALL with {} calls already on the stack", STACK_DEPTH));
                 }
+                notify!(self, observer => observer.subroutine_call(self.pc, nnn & 0x0FFF));
+                self.stack.push(self.pc);
+                self.pc = nnn & 0x0FFF;
+                return;
             }
-            5 => {
-                let (res, carry) = self.reg[x].overflowing_sub(self.reg[y]);
-                self.reg[x] = res;
-                if carry {
-                    self.set_carry(1);
-                } else {
-                    self.set_carry(0);
+            SeByte(x, nn) => {
+                if self.reg[reg(x)] == nn {
+                    self.inc_pc();
                 }
             }
-            6 => {
-                let carry = self.reg[y] & 0x01;
-                self.reg[x] = self.reg[y] >> 1;
-                self.set_carry(carry)
+            SneByte(x, nn) => {
+                if self.reg[reg(x)] != nn {
+                    self.inc_pc();
+                }
             }
-            7 => {
-                let (res, carry) = self.reg[y].overflowing_sub(self.reg[x]);
-                self.reg[x] = res;
-                if carry {
-                    self.set_carry(1);
-                } else {
-                    self.set_carry(0);
+            SeReg(x, y) => {
+                if self.reg[reg(x)] == self.reg[reg(y)] {
+                    self.inc_pc();
                 }
             }
-            0xE => {
-                let carry = (self.reg[y] & 0x80) >> 7;
-                self.reg[x] = self.reg[y] << 1;
-                self.set_carry(carry)
+            SneReg(x, y) => {
+                if self.reg[reg(x)] != self.reg[reg(y)] {
+                    self.inc_pc();
+                }
+            }
+            LdByte(x, nn) => self.reg[reg(x)] = nn,
+            AddByte(x, nn) => self.reg[reg(x)] = self.reg[reg(x)].wrapping_add(nn),
+            LdReg(x, y) => self.reg[reg(x)] = self.reg[reg(y)],
+            Or(x, y) => self.logic(reg(x), self.reg[reg(x)] | self.reg[reg(y)]),
+            And(x, y) => self.logic(reg(x), self.reg[reg(x)] & self.reg[reg(y)]),
+            Xor(x, y) => self.logic(reg(x), self.reg[reg(x)] ^ self.reg[reg(y)]),
+            AddReg(x, y) => {
+                let (res, carry) = self.reg[reg(x)].overflowing_add(self.reg[reg(y)]);
+                self.reg[reg(x)] = res;
+                self.set_carry(if carry { 1 } else { 0 });
+            }
+            Sub(x, y) => {
+                let (res, borrow) = self.reg[reg(x)].overflowing_sub(self.reg[reg(y)]);
+                self.reg[reg(x)] = res;
+                self.set_carry(if borrow { 0 } else { 1 });
+            }
+            Subn(x, y) => {
+                let (res, borrow) = self.reg[reg(y)].overflowing_sub(self.reg[reg(x)]);
+                self.reg[reg(x)] = res;
+                self.set_carry(if borrow { 0 } else { 1 });
             }
-            _ => panic!("Illegal op for 8: {}", op),
+            Shr(x, y) => {
+                let val = self.shift_source(reg(x), reg(y));
+                self.reg[reg(x)] = val >> 1;
+                self.set_carry(val & 0x01)
+            }
+            Shl(x, y) => {
+                let val = self.shift_source(reg(x), reg(y));
+                self.reg[reg(x)] = val << 1;
+                self.set_carry((val & 0x80) >> 7)
+            }
+            LdI(nnn) => self.i = nnn & 0x0FFF,
+            JpV0(nnn) => {
+                let nnn = nnn & 0x0FFF;
+                let x = if self.quirks.jump { (nnn >> 8) as usize } else { 0 };
+                self.pc = self.wrap(self.reg[x] as usize + nnn as usize);
+                return;
+            }
+            Rnd(x, nn) => {
+                self.reg[reg(x)] = nn & self.rng.gen::<u8>();
+                self.draws += 1;
+            }
+            Drw(x, y, n) => self.draw(reg(x), reg(y), (n & 0x0F) as usize),
+            Skp(x) => {
+                if self.key(reg(x)) {
+                    self.inc_pc();
+                }
+            }
+            Sknp(x) => {
+                if !self.key(reg(x)) {
+                    self.inc_pc();
+                }
+            }
+            LdVxDt(x) => self.reg[reg(x)] = self.delay_reg,
+            LdVxK(x) => {
+                if !self.wait_for_key(reg(x)) {
+                    return;
+                }
+            }
+            LdDtVx(x) => self.delay_reg = self.reg[reg(x)],
+            LdStVx(x) => {
+                let val = self.reg[reg(x)];
+                self.set_sound(val);
+            }
+            // I wraps around the end of memory.
+            AddIVx(x) => self.i = self.wrap(self.i as usize + self.reg[reg(x)] as usize),
+            // Only the low nibble of VX picks the digit.
+            LdFVx(x) => self.i = (self.reg[reg(x)] & 0x0F) as u16 * 5,
+            LdBVx(x) => self.store_bcd(reg(x)),
+            LdIVx(x) => self.store_regs(reg(x)),
+            LdVxI(x) => self.load_regs(reg(x)),
+            Data(op) => return self.fail(format!("Illegal opcode 0x{:04X}", op)),
         }
 
         self.inc_pc();
     }
 
-    fn run_9(&mut self, data: u16) {
-        let x = (data >> 8) as usize;
-        let y = (data & 0xFF) as usize;
-        if self.reg[x] != self.reg[y] {
-            self.inc_pc();
+    fn logic(&mut self, x: usize, val: u8) {
+        self.reg[x] = val;
+        if self.quirks.logic {
+            self.set_carry(0);
         }
-        self.inc_pc();
     }
 
-    fn run_a(&mut self, data: u16) {
-        self.i = data;
-
-        self.inc_pc();
-    }
-
-    fn run_b(&mut self, data: u16) {
-        self.pc = (self.reg[0] as u16) + data;
-    }
-
-    fn run_c(&mut self, data: u16) {
-        let x = (data >> 8) as usize;
-        let val = (data & 0xFF) as u8;
-        self.reg[x] = val & rand::random::<u8>();
-        self.inc_pc();
+    fn shift_source(&self, x: usize, y: usize) -> u8 {
+        if self.quirks.shift {
+            self.reg[x]
+        } else {
+            self.reg[y]
+        }
     }
 
-    fn run_d(&mut self, data: u16) {
-        let x = (data >> 8) as usize;
-        let y = ((data >> 4) & 0x0F) as usize;
-        let n = (data & 0x0F) as usize;
-        let mut sprite = Vec::with_capacity(n);
-        for i in 0..n {
-            sprite.push(self.ram.get_mem8((self.i as usize) + i));
+    fn draw(&mut self, x: usize, y: usize, n: usize) {
+        let mut sprite = [0; 16];
+        for (i, row) in sprite.iter_mut().enumerate().take(n) {
+            let addr = (self.i as usize + i) % self.ram.size();
+            let val = self.ram.get_mem8(addr);
+            notify!(self, observer => observer.memory_read(addr, val));
+            *row = val;
         }
-        let carry = self.display
-            .lock()
-            .unwrap()
-            .set_sprite(self.reg[y], self.reg[x], &sprite);
+        let carry = self.display.set_sprite(self.reg[y], self.reg[x], &sprite[..n]);
         self.set_carry(if carry { 1 } else { 0 });
-
-        self.inc_pc();
-    }
-
-    fn run_e(&mut self, data: u16) {
-        self.keyboard.lock().unwrap().read_input();
-        let x = (data >> 8) as usize;
-        let op = (data & 0xFF) as u8;
-        let key = self.keyboard
-            .lock()
-            .unwrap()
-            .is_pressed(self.reg[x] as usize);
-        match op {
-            0x9E => {
-                if key {
-                    self.inc_pc();
-                }
+        notify!(self, observer => observer.sprite_drawn(self.reg[x], self.reg[y], n as u8, carry));
+        self.vblank_wait = self.quirks.vblank;
+    }
+
+    // Whether the key in VX is held, for EX9E/EXA1.
+    fn key(&mut self, x: usize) -> bool {
+        self.keys_read = true;
+        // Only the low nibble of VX picks the key.
+        self.keys[(self.reg[x] & 0x0F) as usize]
+    }
+
+    // Puts the next key pressed in VX, or says there's none to wait for.
+    fn wait_for_key(&mut self, x: usize) -> bool {
+        notify!(self, observer => observer.key_wait_started(x));
+        self.keyboard.lock().unwrap().reset_last_key();
+        let key = loop {
+            let mut keyboard = self.keyboard.lock().unwrap();
+            keyboard.read_input();
+            if let Some(key) = keyboard.last_key {
+                self.keys = keyboard.keys;
+                break key;
             }
-            0xA1 => {
-                if !key {
-                    self.inc_pc();
-                }
+            // With no input left to wait for, try again next cycle.
+            if keyboard.is_closed() {
+                return false;
             }
-            _ => panic!("Illegal op for E {}", op),
+        };
+        self.reg[x] = key;
+        notify!(self, observer => observer.key_wait_finished(self.reg[x]));
+        true
+    }
+
+    fn store_bcd(&mut self, x: usize) {
+        let val = self.reg[x];
+        let hundreds = (val / 100) % 10;
+        let tens = (val / 10) % 10;
+        let ones = val % 10;
+        let i = self.i as usize;
+        self.ram.set_mem8(i, hundreds);
+        self.ram.set_mem8(i + 1, tens);
+        self.ram.set_mem8(i + 2, ones);
+        self.invalidate(i, 3);
+        let size = self.ram.size();
+        notify!(self, observer => {
+            observer.memory_write(i, hundreds);
+            observer.memory_write((i + 1) % size, tens);
+            observer.memory_write((i + 2) % size, ones);
+        });
+    }
+
+    fn store_regs(&mut self, x: usize) {
+        self.ram.set_regs(self.i as usize, &self.reg, x as u8);
+        self.invalidate(self.i as usize, x + 1);
+        let size = self.ram.size();
+        notify!(self, observer => for (j, &val) in self.reg.iter().take(x + 1).enumerate() {
+            observer.memory_write((self.i as usize + j) % size, val);
+        });
+        self.i = self.wrap(self.i as usize + self.memory_increment(x) as usize);
+    }
+
+    fn load_regs(&mut self, x: usize) {
+        self.ram.get_regs(self.i as usize, &mut self.reg, x as u8);
+        let size = self.ram.size();
+        notify!(self, observer => for (j, &val) in self.reg.iter().take(x + 1).enumerate() {
+            observer.memory_read((self.i as usize + j) % size, val);
+        });
+        self.i = self.wrap(self.i as usize + self.memory_increment(x) as usize);
+    }
+
+    // How far FX55/FX65 move I.
+    fn memory_increment(&self, x: usize) -> u16 {
+        if self.quirks.memory_leave_i_unchanged {
+            0
+        } else if self.quirks.memory_increment_by_x {
+            x as u16
+        } else {
+            x as u16 + 1
         }
+    }
 
-        self.inc_pc();
+    // Without a logfile or observers, running an instruction allocates
+    // nothing, takes no locks (bar FX0A waiting for the keyboard) and
+    // does no I/O.
+    pub fn run_cycle(&mut self) {
+        let opcode = self.ram.get_mem16(self.pc as usize);
+        self.execute(opcode);
     }
 
-    fn run_f(&mut self, data: u16) {
-        let x = (data >> 8) as usize;
-        let op = (data & 0xFF) as u8;
-        match op {
-            0x07 => self.reg[x] = self.delay_reg,
-            0x0A => {
-                self.keyboard.lock().unwrap().reset_last_key();
-                loop {
-                    self.keyboard.lock().unwrap().read_input();
-                    if self.keyboard.lock().unwrap().last_key.is_some() {
-                        break;
-                    }
-                }
-                self.reg[x] = self.keyboard.lock().unwrap().last_key.unwrap();
-            }
-            0x15 => self.delay_reg = self.reg[x],
-            0x18 => self.sound_reg = self.reg[x],
-            0x1E => self.i += self.reg[x] as u16,
-            0x29 => self.i = (self.reg[x] * 5) as u16,
-            0x33 => {
-                let val = self.reg[x];
-                let hundreds = (val / 100) % 10;
-                let tens = (val / 10) % 10;
-                let ones = val % 10;
-                self.ram.set_mem8(self.i as usize, hundreds);
-                self.ram.set_mem8((self.i + 1) as usize, tens);
-                self.ram.set_mem8((self.i + 2) as usize, ones);
+    // Runs `opcode` as if it had been fetched from PC.
+    #[inline]
+    pub fn execute(&mut self, opcode: u16) {
+        if self.fault.is_some() || self.vblank_wait {
+            return;
+        }
+        let instruction = Instruction::decode(opcode);
+        let opcode = Opcode::from_rom(opcode);
+        if let Some(ref mut logfile) = self.logfile {
+            // A full disk shouldn't stop the game.
+            let _ = writeln!(logfile, "{}", opcode);
+        }
+        let pc = self.pc;
+        self.run(instruction);
+        notify!(self, observer => observer.instruction(pc, &opcode));
+    }
+
+    // Runs `n` instructions, leaving everything as `n` calls to `run_cycle`
+    // would, but from blocks decoded once and cached. Logging and observers
+    // want to hear about every instruction, so with either attached this is
+    // just `run_cycle` `n` times.
+    pub fn run_cycles(&mut self, n: u64) {
+        if self.is_traced() {
+            for _ in 0..n {
+                self.run_cycle();
             }
-            0x55 => {
-                self.ram
-                    .set_regs(self.i as usize, &self.reg, (data >> 8) as u8);
-                self.i += 8;
+            return;
+        }
+        if self.fault.is_some() || self.vblank_wait {
+            return;
+        }
+        let mut left = n;
+        while left > 0 {
+            let block = self.blocks.block(self.pc as usize, self.ram, Instruction::decode);
+            if block.is_empty() {
+                // An opcode in memory's last byte wraps around to its first,
+                // which blocks don't do.
+                self.run_cycle();
+                left -= 1;
+                continue;
             }
-            0x65 => {
-                self.ram
-                    .get_regs(self.i as usize, &mut self.reg, (data >> 8) as u8);
-                self.i += 8;
+            let count = (block.len() as u64).min(left);
+            for index in block.take(count as usize) {
+                let instruction = self.blocks.op(index);
+                self.run(instruction);
+                if self.fault.is_some() || self.vblank_wait {
+                    return;
+                }
             }
-            _ => panic!("Illegal op for F {}", op),
-        }
-
-        self.inc_pc();
-    }
-
-    fn run_opcode(&mut self, opcode: Opcode) {
-        match opcode.op() {
-            0x00 => self.run_0(opcode.data()),
-            0x01 => self.run_1(opcode.data()),
-            0x02 => self.run_2(opcode.data()),
-            0x03 => self.run_3(opcode.data()),
-            0x04 => self.run_4(opcode.data()),
-            0x05 => self.run_5(opcode.data()),
-            0x06 => self.run_6(opcode.data()),
-            0x07 => self.run_7(opcode.data()),
-            0x08 => self.run_8(opcode.data()),
-            0x09 => self.run_9(opcode.data()),
-            0x0A => self.run_a(opcode.data()),
-            0x0B => self.run_b(opcode.data()),
-            0x0C => self.run_c(opcode.data()),
-            0x0D => self.run_d(opcode.data()),
-            0x0E => self.run_e(opcode.data()),
-            0x0F => self.run_f(opcode.data()),
-            _ => panic!("Opcode {} > 0x0F", opcode.op()),
+            left -= count;
         }
     }
+}
 
-    pub fn run_cycle(&mut self) {
-        let opcode = self.fetch();
-        self.logfile
-            .write_all(&opcode.to_string().into_bytes())
-            .unwrap();
-        self.logfile.write_all(b"\n").unwrap();
-        let _ = self.logfile.flush();
-        self.run_opcode(opcode);
-        //self.dec_delay();
-    }
+// The register an instruction names. Decoded instructions name one of V0 to
+// VF, but hand-built ones might not.
+fn reg(x: u8) -> usize {
+    (x & 0x0F) as usize
 }
@@ -0,0 +1,46 @@
+use instruction::Instruction;
+
+// One line per instruction word, with its address and bytes in a comment:
+//
+//     LD V0, 0x0A        ; 200: 600A
+//
+// Code and data aren't told apart, so data shows up as whatever instructions
+// its bytes happen to spell. The listing assembles back to the same bytes.
+pub fn listing(bytes: &[u8], origin: u16) -> String {
+    let mut out = String::new();
+    for (i, word) in bytes.chunks(2).enumerate() {
+        let addr = origin as usize + i * 2;
+        let (text, hex) = match *word {
+            [hi, lo] => {
+                let op = (hi as u16) << 8 | lo as u16;
+                (Instruction::decode(op).to_string(), format!("{:04X}", op))
+            }
+            [byte] => (format!("DB 0x{:02X}", byte), format!("{:02X}", byte)),
+            _ => unreachable!(),
+        };
+        out.push_str(&format!("    {:<18} ; {:03X}: {}\n", text, addr, hex));
+    }
+    out
+}
+
+#[test]
+fn test_listing() {
+    let listing = listing(&[0x00, 0xE0, 0xD0, 0x15, 0xAB], 0x200);
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "    CLS                ; 200: 00E0",
+            "    DRW V0, V1, 5      ; 202: D015",
+            "    DB 0xAB            ; 204: AB",
+        ]
+    );
+}
+
+#[test]
+fn test_listing_reassembles() {
+    use asm::assemble;
+
+    let bytes: Vec<u8> = (0..=255).chain(0..3).collect();
+    assert_eq!(assemble(&listing(&bytes, 0x200)).unwrap(), bytes);
+}
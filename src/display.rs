@@ -1,119 +1,323 @@
-pub struct Display([u64; 32]);
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+// What happens to sprite pixels that run past the edge of the screen.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DrawMode {
+    Wrap,
+    Clip,
+}
+
+// Sent by the CPU at the end of each emulated frame in which the screen
+// changed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FrameReady {
+    pub generation: u64,
+}
+
+// Bit 63 of each row is the leftmost column.
+//
+// Besides the pixels, a display keeps a generation counter that goes up with
+// every change, and a mask of rows changed since a renderer last took it.
+#[derive(Clone, Debug)]
+pub struct Display {
+    rows: [u64; HEIGHT],
+    mode: DrawMode,
+    dirty: u32,
+    generation: u64,
+}
 
 impl Display {
     pub fn init() -> Display {
-        Display([0; 32])
+        Display::from_rows([0; HEIGHT])
+    }
+
+    pub fn from_rows(rows: [u64; HEIGHT]) -> Display {
+        Display {
+            rows,
+            mode: DrawMode::Wrap,
+            dirty: !0,
+            generation: 0,
+        }
     }
 
     pub fn clear(&mut self) {
-        self.0 = [0; 32];
+        let mut changed = 0;
+        for (i, row) in self.rows.iter_mut().enumerate() {
+            if *row != 0 {
+                changed |= 1 << i;
+                *row = 0;
+            }
+        }
+        if changed != 0 {
+            self.dirty |= changed;
+            self.generation += 1;
+        }
     }
 
-    fn set_sprite_row(&mut self, row: u8, col: u8, sprite_row: u8) -> bool {
-        let mrow = if row < 32 { row } else { row % 32 };
-        let mut collision = false;
-        if col <= (64 - 8) {
-            let fp = (sprite_row as u64) << (64 - col - 8);
-            let newrow1a = self.0[mrow as usize] ^ fp;
-            let newrow1b = self.0[mrow as usize] | fp;
-            self.0[mrow as usize] = newrow1a;
-            collision |= newrow1a != newrow1b;
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Bit `n` is set if row `n` changed since the last `take_dirty_rows`.
+    pub fn dirty_rows(&self) -> u32 {
+        self.dirty
+    }
+
+    pub fn take_dirty_rows(&mut self) -> u32 {
+        let dirty = self.dirty;
+        self.dirty = 0;
+        dirty
+    }
+
+    // Mask of rows whose pixels differ between the two displays.
+    pub fn diff_rows(&self, other: &Display) -> u32 {
+        let mut rows = 0;
+        for (i, (a, b)) in self.rows.iter().zip(other.rows.iter()).enumerate() {
+            if a != b {
+                rows |= 1 << i;
+            }
         }
-        if col > (64 - 8) {
-            let sp = (sprite_row as u64) >> (63 - col);
-            let newrow2a = self.0[(mrow + 1) as usize] ^ sp;
-            let newrow2b = self.0[(mrow + 1) as usize] | sp;
-            self.0[(mrow + 1) as usize] = newrow2a;
-            collision |= newrow2a != newrow2b;
+        rows
+    }
+
+    pub fn set_draw_mode(&mut self, mode: DrawMode) {
+        self.mode = mode;
+    }
+
+    pub fn draw_mode(&self) -> DrawMode {
+        self.mode
+    }
+
+    // `row` and `col` are the sprite's unwrapped position; they may run past
+    // the edges of the screen when the sprite does.
+    fn set_sprite_row(&mut self, row: usize, col: usize, sprite_row: u8) -> bool {
+        let row = match self.mode {
+            DrawMode::Wrap => row % HEIGHT,
+            DrawMode::Clip if row < HEIGHT => row,
+            DrawMode::Clip => return false,
+        };
+        let aligned = (sprite_row as u64) << (WIDTH - 8);
+        let bits = match self.mode {
+            DrawMode::Wrap => aligned.rotate_right(col as u32),
+            DrawMode::Clip => aligned >> col,
+        };
+        if bits != 0 {
+            self.rows[row] ^= bits;
+            self.dirty |= 1 << row;
         }
-        collision
+        self.rows[row] & bits != bits
     }
 
+    // Draws `sprite` with its top-left corner at (`col`, `row`), XORing it
+    // onto the screen. The starting position always wraps; pixels that run
+    // off an edge wrap or are dropped depending on the draw mode. Returns
+    // whether any lit pixel was turned off.
     pub fn set_sprite(&mut self, row: u8, col: u8, sprite: &[u8]) -> bool {
+        let row = row as usize % HEIGHT;
+        let col = col as usize % WIDTH;
         let mut collision = false;
-        for (i, &sprite_row) in sprite.into_iter().enumerate() {
-            collision = self.set_sprite_row(row + (i as u8), col, sprite_row) || collision;
+        for (i, &sprite_row) in sprite.iter().enumerate() {
+            collision = self.set_sprite_row(row + i, col, sprite_row) || collision;
+        }
+        if sprite.iter().any(|&sprite_row| sprite_row != 0) {
+            self.generation += 1;
         }
         collision
     }
 
+    pub fn get_pixel(&self, row: usize, col: usize) -> bool {
+        self.rows[row % HEIGHT] & (1 << (WIDTH - 1 - col % WIDTH)) != 0
+    }
+
     pub fn is_collision(&self, row: u8, col: u8) -> bool {
-        (self.0[row as usize] & (1 << col)) != 0
+        self.get_pixel(row as usize, col as usize)
     }
 
-    pub fn get_display(&self) -> [u64; 32] {
-        self.0
+    pub fn get_display(&self) -> [u64; HEIGHT] {
+        self.rows
+    }
+}
+
+// Displays are equal when they show the same pixels.
+impl PartialEq for Display {
+    fn eq(&self, other: &Display) -> bool {
+        self.rows == other.rows
     }
 }
 
-/*
 #[test]
 fn test_init() {
-    assert_eq!(Display::init().0[0], 0);
-    assert_eq!(Display::init().0[31], 0);
+    assert_eq!(Display::init().rows[0], 0);
+    assert_eq!(Display::init().rows[31], 0);
 }
 
 #[test]
 fn test_at_origin() {
     let mut display = Display::init();
     display.set_sprite_row(0, 0, 0xFF);
-    assert_eq!(display.0[0], 0xFF);
+    assert_eq!(display.rows[0], 0xFF << 56);
 }
 
 #[test]
 fn test_at_12() {
     let mut display = Display::init();
     display.set_sprite_row(1, 2, 0xFF);
-    assert_eq!(display.0[0], 0);
-    assert_eq!(display.0[1], 0xFF << 2);
+    assert_eq!(display.rows[0], 0);
+    assert_eq!(display.rows[1], 0xFF << 54);
 }
 
 #[test]
 fn test_overwrite() {
     let mut display = Display::init();
-    display.set_sprite_row(0,0,0x0F);
-    display.set_sprite_row(0,0,0x01);
-    assert_eq!(display.0[0], 0x0E);
+    display.set_sprite_row(0, 0, 0x0F);
+    display.set_sprite_row(0, 0, 0x01);
+    assert_eq!(display.rows[0], 0x0E << 56);
 }
 
 #[test]
 fn test_sprite() {
     let mut display = Display::init();
-    display.set_sprite(0,0, &[0xAB, 0xCD]);
-    assert_eq!(display.0[0], 0xAB);
-    assert_eq!(display.0[1], 0xCD);
+    display.set_sprite(0, 0, &[0xAB, 0xCD]);
+    assert_eq!(display.rows[0], 0xAB << 56);
+    assert_eq!(display.rows[1], 0xCD << 56);
 }
 
 #[test]
 fn test_collision_on_empty() {
     let display = Display::init();
-    assert!(!display.is_collision(0,1));
+    assert!(!display.is_collision(0, 1));
 }
 
 #[test]
 fn test_non_collision_direct() {
     let mut display = Display::init();
-    display.set_sprite(0,0,&[0x01]);
-    assert!(!display.is_collision(0,1));
+    display.set_sprite(0, 0, &[0x80]);
+    assert!(!display.is_collision(0, 1));
 }
 
 #[test]
 fn test_collision_direct() {
     let mut display = Display::init();
-    display.set_sprite(0,0,&[0xFF]);
-    assert!(display.is_collision(0,1));
+    display.set_sprite(0, 0, &[0xFF]);
+    assert!(display.is_collision(0, 1));
 }
 
 #[test]
 fn test_non_collision_drawing() {
     let mut display = Display::init();
-    assert!(!display.set_sprite(0,0,&[0x01]));
+    assert!(!display.set_sprite(0, 0, &[0x01]));
 }
 
 #[test]
 fn test_collision_drawing() {
     let mut display = Display::init();
-    display.set_sprite(0,0,&[0xFF]);
-    assert!(display.set_sprite(0,0,&[0x01]));
+    display.set_sprite(0, 0, &[0xFF]);
+    assert!(display.set_sprite(0, 0, &[0x01]));
+}
+
+#[test]
+fn test_wrap_right_edge_stays_on_row() {
+    let mut display = Display::init();
+    display.set_sprite(3, 60, &[0xFF]);
+    assert_eq!(display.rows[3], 0xF000_0000_0000_000F);
+    assert_eq!(display.rows[4], 0);
+}
+
+#[test]
+fn test_wrap_bottom_edge() {
+    let mut display = Display::init();
+    display.set_sprite(30, 0, &[0x80, 0x80, 0x80, 0x80]);
+    assert!(display.get_pixel(30, 0));
+    assert!(display.get_pixel(31, 0));
+    assert!(display.get_pixel(0, 0));
+    assert!(display.get_pixel(1, 0));
+}
+
+#[test]
+fn test_wrap_bottom_right_corner() {
+    let mut display = Display::init();
+    display.set_sprite(31, 63, &[0xC0, 0xC0]);
+    assert!(display.get_pixel(31, 63));
+    assert!(display.get_pixel(31, 0));
+    assert!(display.get_pixel(0, 63));
+    assert!(display.get_pixel(0, 0));
+}
+
+#[test]
+fn test_clip_right_and_bottom_edges() {
+    let mut display = Display::init();
+    display.set_draw_mode(DrawMode::Clip);
+    display.set_sprite(31, 60, &[0xFF, 0xFF]);
+    assert_eq!(display.rows[31], 0x0F);
+    assert_eq!(display.rows[0], 0);
+    assert_eq!(display.rows[30], 0);
+}
+
+#[test]
+fn test_clip_collision_ignores_dropped_pixels() {
+    let mut display = Display::init();
+    display.set_draw_mode(DrawMode::Clip);
+    display.set_sprite(0, 0, &[0x80]);
+    assert!(!display.set_sprite(0, 60, &[0x0F]));
+    assert!(!display.set_sprite(31, 0, &[0x00, 0x80]));
+}
+
+#[test]
+fn test_start_position_wraps() {
+    for &mode in &[DrawMode::Wrap, DrawMode::Clip] {
+        let mut display = Display::init();
+        display.set_draw_mode(mode);
+        display.set_sprite(33, 66, &[0x80]);
+        assert!(display.get_pixel(1, 2));
+        display.set_sprite(255, 255, &[0x80]);
+        assert!(display.get_pixel(31, 63));
+    }
+}
+
+#[test]
+fn test_tall_sprite_no_overflow() {
+    let mut display = Display::init();
+    display.set_sprite(250, 0, &[0x80; 15]);
+    let lit = (0..32).filter(|&row| display.get_pixel(row, 0)).count();
+    assert_eq!(lit, 15);
+}
+
+#[test]
+fn test_dirty_rows() {
+    let mut display = Display::init();
+    assert_eq!(display.take_dirty_rows(), !0);
+    assert_eq!(display.dirty_rows(), 0);
+
+    display.set_sprite(3, 0, &[0x80, 0x00, 0x80]);
+    assert_eq!(display.take_dirty_rows(), (1 << 3) | (1 << 5));
+
+    display.clear();
+    assert_eq!(display.take_dirty_rows(), (1 << 3) | (1 << 5));
+    display.clear();
+    assert_eq!(display.dirty_rows(), 0);
+}
+
+#[test]
+fn test_diff_rows() {
+    let mut a = Display::init();
+    let b = Display::init();
+    assert_eq!(a.diff_rows(&b), 0);
+    a.set_sprite(30, 0, &[0x80, 0x00, 0x80]);
+    assert_eq!(a.diff_rows(&b), (1 << 30) | 1);
+}
+
+#[test]
+fn test_generation() {
+    let mut display = Display::init();
+    assert_eq!(display.generation(), 0);
+    display.set_sprite(0, 0, &[0x00]);
+    display.clear();
+    assert_eq!(display.generation(), 0);
+    display.set_sprite(0, 0, &[0x80]);
+    assert_eq!(display.generation(), 1);
+    display.set_sprite(0, 0, &[0x80]);
+    assert_eq!(display.generation(), 2);
+    display.clear();
+    assert_eq!(display.generation(), 2);
 }
-*/
@@ -1,32 +1,90 @@
-use super::display::Display;
-use super::keyboard::Keyboard;
+use std::io;
+use std::io::Write;
+
+use super::framebuffer::{self, Frame, Framebuffer};
+use super::phosphor::Shades;
 
 pub trait DisplayImpl {
-    fn draw(&self, screen: &Display, keys: &Keyboard);
+    fn draw(&self, screen: &dyn Framebuffer, keys: &[bool; 16]);
+
+    // Renderers that can show intermediate intensities override this; the
+    // rest see every pixel that is at least half lit.
+    fn draw_shades(&self, shades: &Shades, keys: &[bool; 16]) {
+        self.draw(&shades.to_frame(128), keys);
+    }
+
+    // Redraws after a change to the rows set in `rows` (bit `n` for row `n`).
+    // Renderers that can update part of the screen override this.
+    fn draw_rows(&self, screen: &dyn Framebuffer, keys: &[bool; 16], rows: u64) {
+        let _ = rows;
+        self.draw(screen, keys);
+    }
+}
+
+// So a renderer chosen at run time can be wrapped, as in `PhosphorDisplay`.
+impl<D: DisplayImpl + ?Sized> DisplayImpl for Box<D> {
+    fn draw(&self, screen: &dyn Framebuffer, keys: &[bool; 16]) {
+        (**self).draw(screen, keys)
+    }
+
+    fn draw_shades(&self, shades: &Shades, keys: &[bool; 16]) {
+        (**self).draw_shades(shades, keys)
+    }
+
+    fn draw_rows(&self, screen: &dyn Framebuffer, keys: &[bool; 16], rows: u64) {
+        (**self).draw_rows(screen, keys, rows)
+    }
 }
 
 pub struct AsciiDisplay();
 
-static BLANK_SCREEN: &'static str = "\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n";
+static BLANK_SCREEN: &str = "\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n";
 
-impl AsciiDisplay {
-    fn row_to_ascii(&self, row: u64) -> String {
-        let mut s = String::new();
+// Characters for color indices 0-3; anything above is drawn as lit.
+const PIXELS: [char; 4] = [' ', '#', '+', '@'];
 
-        for i in 0..64 {
-            if row & (1 << (63 - i)) != 0 {
-                s.push('#');
-            } else {
-                s.push(' ');
-            }
-        }
-        s
+// The whole screen, one line per row, drawn as `AsciiDisplay` draws it.
+pub fn screen_to_ascii(screen: &dyn Framebuffer) -> String {
+    let mut out = String::new();
+    for y in 0..screen.height() {
+        out.push_str(&row_to_ascii(screen, y));
+        out.push('\n');
     }
+    out
+}
 
+// Reads back a screen written by `screen_to_ascii`. Rows may have lost their
+// trailing spaces; characters it doesn't write are read as lit.
+pub fn ascii_to_screen(text: &str) -> Frame {
+    let rows: Vec<&str> = text.lines().collect();
+    let width = rows.iter().map(|row| row.chars().count()).max().unwrap_or(0);
+    let mut indices = Vec::with_capacity(width * rows.len());
+    for row in rows.iter() {
+        let mut chars = row.chars();
+        indices.extend((0..width).map(|_| match chars.next() {
+            Some(c) => PIXELS.iter().position(|&pixel| pixel == c).unwrap_or(1) as u8,
+            None => 0,
+        }));
+    }
+    let planes = if indices.iter().any(|&index| index > 1) { 2 } else { 1 };
+    let mut frame = Frame::new(width, rows.len(), planes);
+    for (i, &index) in indices.iter().enumerate() {
+        frame.set_color_index(i % width, i / width, index);
+    }
+    frame
+}
+
+fn row_to_ascii(screen: &dyn Framebuffer, y: usize) -> String {
+    framebuffer::row(screen, y)
+        .map(|index| *PIXELS.get(index as usize).unwrap_or(&PIXELS[1]))
+        .collect()
+}
+
+impl AsciiDisplay {
     fn keys_to_ascii(&self, keys: &[bool; 16]) -> String {
         let mut s = String::new();
-        for i in 0..16 {
-            if keys[i] {
+        for &key in keys.iter() {
+            if key {
                 s.push('*');
             } else {
                 s.push('_');
@@ -41,16 +99,53 @@ impl AsciiDisplay {
 }
 
 impl DisplayImpl for AsciiDisplay {
-    fn draw(&self, screen: &Display, keys: &Keyboard) {
+    fn draw(&self, screen: &dyn Framebuffer, keys: &[bool; 16]) {
         self.clear();
-        let screen_rows = screen.get_display();
-        let key_presses = keys.keys;
-
-        for i in 0..32 {
-            let s = self.row_to_ascii(screen_rows[i]);
+        for y in 0..screen.height() {
+            let s = row_to_ascii(screen, y);
             println!("{}", s);
         }
-        println!("");
-        println!("{}", self.keys_to_ascii(&key_presses));
+        println!();
+        println!("{}", self.keys_to_ascii(keys));
+    }
+
+    // Partial updates address rows directly with cursor movement, so a full
+    // redraw (all rows dirty) first clears the terminal to home the screen.
+    fn draw_rows(&self, screen: &dyn Framebuffer, keys: &[bool; 16], rows: u64) {
+        let mut out = String::new();
+        if rows == framebuffer::all_rows(screen) {
+            out.push_str("\x1b[2J");
+        }
+        for y in 0..screen.height().min(64) {
+            if rows & (1 << y) != 0 {
+                out.push_str(&format!("\x1b[{};1H{}", y + 1, row_to_ascii(screen, y)));
+            }
+        }
+        out.push_str(&format!("\x1b[{};1H{}\n", screen.height() + 2, self.keys_to_ascii(keys)));
+
+        let stdout = io::stdout();
+        let mut handle = stdout.lock();
+        let _ = handle.write_all(out.as_bytes());
+        let _ = handle.flush();
     }
 }
+
+#[test]
+fn test_row_to_ascii() {
+    let mut frame = Frame::new(4, 1, 2);
+    frame.set_color_index(1, 0, 1);
+    frame.set_color_index(2, 0, 2);
+    frame.set_color_index(3, 0, 3);
+    assert_eq!(row_to_ascii(&frame, 0), " #+@");
+}
+
+#[test]
+fn test_ascii_round_trip() {
+    let mut frame = Frame::new(3, 2, 2);
+    frame.set_color_index(0, 0, 1);
+    frame.set_color_index(2, 1, 3);
+    let text = screen_to_ascii(&frame);
+    assert_eq!(text, "#  \n  @\n");
+    assert_eq!(ascii_to_screen(&text), frame);
+    assert_eq!(ascii_to_screen("#\n  @\n"), frame);
+}
@@ -0,0 +1,161 @@
+use display::Display;
+
+// Read access to a screen of any size. Each pixel has a color index made of
+// one bit per plane: bit `n` is set when the pixel is lit in plane `n`, and
+// index 0 is always the background.
+pub trait Framebuffer {
+    fn width(&self) -> usize;
+
+    fn height(&self) -> usize;
+
+    fn planes(&self) -> usize {
+        1
+    }
+
+    fn color_index(&self, x: usize, y: usize) -> u8;
+
+    fn pixel(&self, x: usize, y: usize) -> bool {
+        self.color_index(x, y) != 0
+    }
+
+    fn colors(&self) -> usize {
+        1 << self.planes()
+    }
+}
+
+// Iterates over the color indices of row `y`, left to right.
+pub fn row<'a, F: Framebuffer + ?Sized>(fb: &'a F, y: usize) -> Row<'a, F> {
+    Row { fb, y, x: 0 }
+}
+
+// Mask with a bit set for every row of `fb`, as passed to renderers that
+// redraw individual rows.
+pub fn all_rows<F: Framebuffer + ?Sized>(fb: &F) -> u64 {
+    if fb.height() >= 64 {
+        !0
+    } else {
+        (1 << fb.height()) - 1
+    }
+}
+
+pub struct Row<'a, F: Framebuffer + ?Sized + 'a> {
+    fb: &'a F,
+    y: usize,
+    x: usize,
+}
+
+impl<'a, F: Framebuffer + ?Sized> Iterator for Row<'a, F> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.x >= self.fb.width() {
+            return None;
+        }
+        let index = self.fb.color_index(self.x, self.y);
+        self.x += 1;
+        Some(index)
+    }
+}
+
+impl Framebuffer for Display {
+    fn width(&self) -> usize {
+        64
+    }
+
+    fn height(&self) -> usize {
+        32
+    }
+
+    fn color_index(&self, x: usize, y: usize) -> u8 {
+        self.get_pixel(y, x) as u8
+    }
+}
+
+// An owned copy of any framebuffer, for keeping frames around after the
+// source has moved on.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Frame {
+    width: usize,
+    height: usize,
+    planes: usize,
+    pixels: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize, planes: usize) -> Frame {
+        Frame {
+            width,
+            height,
+            planes,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn capture<F: Framebuffer + ?Sized>(fb: &F) -> Frame {
+        let mut pixels = Vec::with_capacity(fb.width() * fb.height());
+        for y in 0..fb.height() {
+            pixels.extend(row(fb, y));
+        }
+        Frame {
+            width: fb.width(),
+            height: fb.height(),
+            planes: fb.planes(),
+            pixels,
+        }
+    }
+
+    pub fn set_color_index(&mut self, x: usize, y: usize, index: u8) {
+        self.pixels[y * self.width + x] = index;
+    }
+}
+
+impl Framebuffer for Frame {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn planes(&self) -> usize {
+        self.planes
+    }
+
+    fn color_index(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+}
+
+#[test]
+fn test_display_framebuffer() {
+    let mut display = Display::init();
+    display.set_sprite(1, 2, &[0xA0]);
+    assert_eq!((display.width(), display.height(), display.planes()), (64, 32, 1));
+    assert!(display.pixel(2, 1));
+    assert!(!display.pixel(3, 1));
+    assert_eq!(display.color_index(4, 1), 1);
+    let lit: Vec<u8> = row(&display, 1).take(6).collect();
+    assert_eq!(lit, vec![0, 0, 1, 0, 1, 0]);
+    assert_eq!(row(&display, 0).count(), 64);
+}
+
+#[test]
+fn test_frame_capture() {
+    let mut display = Display::init();
+    display.set_sprite(31, 63, &[0x80]);
+    let frame = Frame::capture(&display);
+    assert!(frame.pixel(63, 31));
+    assert_eq!(frame.pixels.iter().filter(|&&p| p != 0).count(), 1);
+    assert_eq!(Frame::capture(&frame), frame);
+}
+
+#[test]
+fn test_larger_multiplane_frame() {
+    let mut frame = Frame::new(128, 64, 2);
+    frame.set_color_index(127, 63, 3);
+    assert_eq!(frame.colors(), 4);
+    assert_eq!(frame.color_index(127, 63), 3);
+    assert_eq!(all_rows(&frame), !0);
+    assert_eq!(all_rows(&Display::init()), 0xFFFF_FFFF);
+}
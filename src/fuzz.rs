@@ -0,0 +1,149 @@
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+
+use cpu::CPU;
+use display::Display;
+use keyboard::Keyboard;
+use platform::Platform;
+use quirks::Quirks;
+use ram::RAM;
+
+// Entry point for fuzzers (see fuzz/). Any input must run to the end without
+// panicking, and the block cache must agree with the interpreter on every
+// frame; a panic is a bug either way.
+//
+// The input is read as:
+//   byte 0      the platform
+//   byte 1      the quirks, one bit each
+//   bytes 2-3   the keys held down, one bit each
+//   byte 4      how many of the next bytes (up to 7) are typed in
+//   the rest    the ROM
+// Missing bytes read as 0, so every input is a valid one.
+
+const FRAMES: usize = 30;
+const IPF: u64 = 20;
+
+// Runs `data` as above, returning the fault the ROM stopped on, if any.
+pub fn run(data: &[u8]) -> Option<String> {
+    let input = Input::parse(data);
+    let interpreted = run_frames(&input, false);
+    let cached = run_frames(&input, true);
+    for (frame, (a, b)) in interpreted.iter().zip(cached.iter()).enumerate() {
+        assert!(a == b, "frame {}: interpreted {:?}, cached {:?}", frame, a, b);
+    }
+    interpreted.last().and_then(|state| state.fault.clone())
+}
+
+struct Input<'a> {
+    platform: Platform,
+    quirks: Quirks,
+    held: u16,
+    typed: &'a [u8],
+    rom: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    fn parse(data: &'a [u8]) -> Input<'a> {
+        let byte = |n: usize| data.get(n).cloned().unwrap_or(0);
+        let platforms = Platform::all();
+        let bits = byte(1);
+        let start = data.len().min(5);
+        let end = data.len().min(start + (byte(4) % 8) as usize);
+        Input {
+            platform: platforms[byte(0) as usize % platforms.len()],
+            quirks: Quirks {
+                shift: bits & 0x01 != 0,
+                memory_increment_by_x: bits & 0x02 != 0,
+                memory_leave_i_unchanged: bits & 0x04 != 0,
+                wrap: bits & 0x08 != 0,
+                jump: bits & 0x10 != 0,
+                vblank: bits & 0x20 != 0,
+                logic: bits & 0x40 != 0,
+            },
+            held: (byte(2) as u16) << 8 | byte(3) as u16,
+            typed: &data[start..end],
+            rom: &data[end..],
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+struct State {
+    pc: u16,
+    i: u16,
+    regs: Vec<u8>,
+    timers: (u8, u8),
+    display: Vec<u64>,
+    memory: Vec<u8>,
+    fault: Option<String>,
+}
+
+impl State {
+    fn capture(cpu: &CPU) -> State {
+        State {
+            pc: cpu.get_pc(),
+            i: cpu.get_i(),
+            regs: (0..16).map(|x| cpu.get_reg(x)).collect(),
+            timers: (cpu.get_delay(), cpu.get_sound()),
+            display: cpu.get_display().to_vec(),
+            memory: cpu.memory().to_vec(),
+            fault: cpu.fault().map(|fault| fault.to_string()),
+        }
+    }
+}
+
+// The state at the end of each frame, run one instruction at a time or from
+// the block cache. The typed keys are queued up front and the keyboard then
+// closes, so key waits give up rather than hang.
+fn run_frames(input: &Input, cached: bool) -> Vec<State> {
+    let (sender, receiver) = channel();
+    for &byte in input.typed {
+        sender.send(byte).unwrap();
+    }
+    drop(sender);
+    let mut keyboard = Arc::new(Mutex::new(Keyboard::init(receiver)));
+    for key in 0..16 {
+        if input.held & 1 << key != 0 {
+            keyboard.lock().unwrap().push_key(key);
+        }
+    }
+    let mut display = Display::init();
+    let mut ram = RAM::with_size(input.platform.memory_size());
+    let mut cpu = CPU::init(&mut ram, &mut display, &mut keyboard, None);
+    cpu.load_rom(input.rom);
+    cpu.set_quirks(input.quirks);
+    cpu.set_seed(0);
+    let mut states = Vec::with_capacity(FRAMES);
+    for _ in 0..FRAMES {
+        if cached {
+            cpu.run_cycles(IPF);
+        } else {
+            for _ in 0..IPF {
+                cpu.run_cycle();
+            }
+        }
+        cpu.end_frame();
+        states.push(State::capture(&cpu));
+    }
+    states
+}
+
+#[test]
+fn test_reports_faults() {
+    // Zeroed memory is 0000 after 0000, which isn't an instruction.
+    assert_eq!(run(&[]), Some("Illegal opcode 0x0000".to_string()));
+    assert_eq!(run(&[0, 0, 0, 0, 0, 0x00, 0xEE]), Some("RET with an empty stack".to_string()));
+    // A loop that never stops is no fault.
+    assert_eq!(run(&[0, 0, 0, 0, 0, 0x12, 0x00]), None);
+}
+
+#[test]
+fn test_typed_keys_reach_the_rom() {
+    // LD V0, K, then RET to stop there: '4' is key 3 on the default keymap.
+    let data = [0, 0, 0, 0, 1, b'4', 0xF0, 0x0A, 0x00, 0xEE];
+    let input = Input::parse(&data);
+    assert_eq!((input.typed, input.rom), (&b"4"[..], &data[6..]));
+    let states = run_frames(&input, false);
+    assert_eq!(states[0].regs[0], 3);
+    assert_eq!(states[0].fault, Some("RET with an empty stack".to_string()));
+}
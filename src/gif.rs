@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+use palette::Color;
+
+const MAX_CODE_SIZE: u8 = 12;
+
+// Writes an animated GIF frame by frame. All frames share one global color
+// table and cover the whole logical screen.
+pub struct GifEncoder<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+    min_code_size: u8,
+}
+
+impl<W: Write> GifEncoder<W> {
+    pub fn new(mut out: W, width: usize, height: usize, colors: &[Color]) -> io::Result<GifEncoder<W>> {
+        assert!(!colors.is_empty() && colors.len() <= 256);
+        let mut table_bits = 1;
+        while (1 << table_bits) < colors.len() {
+            table_bits += 1;
+        }
+
+        out.write_all(b"GIF89a")?;
+        out.write_all(&(width as u16).to_le_bytes())?;
+        out.write_all(&(height as u16).to_le_bytes())?;
+        out.write_all(&[0x80 | (table_bits - 1), 0, 0])?;
+        for i in 0..(1 << table_bits) {
+            let c = colors.get(i).cloned().unwrap_or_else(|| Color::rgb(0, 0, 0));
+            out.write_all(&[c.r, c.g, c.b])?;
+        }
+        // NETSCAPE2.0 application extension: loop forever.
+        out.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+        Ok(GifEncoder {
+            out,
+            width,
+            height,
+            min_code_size: table_bits.max(2),
+        })
+    }
+
+    // `delay` is in hundredths of a second.
+    pub fn write_frame(&mut self, pixels: &[u8], delay: u16) -> io::Result<()> {
+        assert_eq!(pixels.len(), self.width * self.height);
+        self.out.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0x00, 0x00])?;
+
+        self.out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&(self.width as u16).to_le_bytes())?;
+        self.out.write_all(&(self.height as u16).to_le_bytes())?;
+        self.out.write_all(&[0x00, self.min_code_size])?;
+        let data = lzw_encode(pixels, self.min_code_size);
+        for block in data.chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0x00])
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0x3B])?;
+        Ok(self.out)
+    }
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.acc |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+pub fn lzw_encode(pixels: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut writer = BitWriter {
+        bytes: Vec::new(),
+        acc: 0,
+        bits: 0,
+    };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = end + 1;
+    let mut code_size = min_code_size + 1;
+
+    writer.write(clear, code_size);
+    let mut iter = pixels.iter();
+    let mut prefix = match iter.next() {
+        Some(&p) => p as u16,
+        None => {
+            writer.write(end, code_size);
+            return writer.finish();
+        }
+    };
+    for &pixel in iter {
+        if let Some(&code) = table.get(&(prefix, pixel)) {
+            prefix = code;
+            continue;
+        }
+        writer.write(prefix, code_size);
+        if next_code < (1 << MAX_CODE_SIZE) {
+            table.insert((prefix, pixel), next_code);
+            if next_code == (1 << code_size) && code_size < MAX_CODE_SIZE {
+                code_size += 1;
+            }
+            next_code += 1;
+        } else {
+            writer.write(clear, code_size);
+            table.clear();
+            next_code = end + 1;
+            code_size = min_code_size + 1;
+        }
+        prefix = pixel as u16;
+    }
+    writer.write(prefix, code_size);
+    writer.write(end, code_size);
+    writer.finish()
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum GifError {
+    NotAGif,
+    Truncated,
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for GifError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GifError::NotAGif => write!(f, "not a GIF image"),
+            GifError::Truncated => write!(f, "GIF image ends early"),
+            GifError::Corrupt(what) => write!(f, "corrupt GIF image: {}", what),
+        }
+    }
+}
+
+impl Error for GifError {}
+
+pub fn is_gif(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a")
+}
+
+// One image from a GIF, as color table indices in row order. Frames are not
+// composited onto the logical screen, so each covers only its own rectangle.
+#[derive(Clone, PartialEq, Debug)]
+pub struct GifFrame {
+    pub left: usize,
+    pub top: usize,
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], GifError> {
+        let end = self.pos.checked_add(n).filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or(GifError::Truncated)?;
+        let taken = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, GifError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<usize, GifError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+    }
+
+    // Concatenates a run of data sub-blocks, up to the empty one ending it.
+    fn sub_blocks(&mut self) -> Result<Vec<u8>, GifError> {
+        let mut data = Vec::new();
+        loop {
+            let len = self.byte()? as usize;
+            if len == 0 {
+                return Ok(data);
+            }
+            data.extend_from_slice(self.take(len)?);
+        }
+    }
+}
+
+// Decodes every image in a GIF. Only the pixel indices are kept; color
+// tables, timing and disposal are skipped. The header's sizes aren't to be
+// trusted, so the images together may hold at most `max_pixels`.
+pub fn decode(bytes: &[u8], max_pixels: usize) -> Result<Vec<GifFrame>, GifError> {
+    if !is_gif(bytes) {
+        return Err(GifError::NotAGif);
+    }
+    let mut reader = Reader { bytes, pos: 6 };
+    reader.take(4)?;
+    let flags = reader.byte()?;
+    reader.take(2)?;
+    if flags & 0x80 != 0 {
+        reader.take(3 << ((flags & 0x07) + 1))?;
+    }
+
+    let mut frames = Vec::new();
+    let mut budget = max_pixels;
+    loop {
+        match reader.byte()? {
+            0x21 => {
+                reader.byte()?;
+                reader.sub_blocks()?;
+            }
+            0x2C => {
+                let left = reader.u16()?;
+                let top = reader.u16()?;
+                let width = reader.u16()?;
+                let height = reader.u16()?;
+                if width * height > budget {
+                    return Err(GifError::Corrupt("image too large"));
+                }
+                budget -= width * height;
+                let flags = reader.byte()?;
+                if flags & 0x80 != 0 {
+                    reader.take(3 << ((flags & 0x07) + 1))?;
+                }
+                let min_code_size = reader.byte()?;
+                let data = reader.sub_blocks()?;
+                let mut pixels = lzw_decode(&data, min_code_size, width * height)?;
+                pixels.resize(width * height, 0);
+                if flags & 0x40 != 0 {
+                    pixels = deinterlace(&pixels, width, height);
+                }
+                frames.push(GifFrame {
+                    left,
+                    top,
+                    width,
+                    height,
+                    pixels,
+                });
+            }
+            0x3B => return Ok(frames),
+            _ => return Err(GifError::Corrupt("unknown block")),
+        }
+    }
+}
+
+// Interlaced images store every eighth row from 0, every eighth from 4,
+// every fourth from 2 and finally every second from 1.
+fn deinterlace(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = vec![0; pixels.len()];
+    let order = (0..height)
+        .step_by(8)
+        .chain((4..height).step_by(8))
+        .chain((2..height).step_by(4))
+        .chain((1..height).step_by(2));
+    for (src, dst) in order.enumerate() {
+        out[dst * width..(dst + 1) * width].copy_from_slice(&pixels[src * width..(src + 1) * width]);
+    }
+    out
+}
+
+// Decodes at most `limit` pixels, ignoring anything after them.
+pub fn lzw_decode(data: &[u8], min_code_size: u8, limit: usize) -> Result<Vec<u8>, GifError> {
+    if !(1..MAX_CODE_SIZE).contains(&min_code_size) {
+        return Err(GifError::Corrupt("bad LZW code size"));
+    }
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    // Each code is a previous code plus one pixel; roots have no prefix.
+    let mut prefixes: Vec<Option<u16>> = (0..=end).map(|_| None).collect();
+    let mut suffixes: Vec<u8> = (0..=end).map(|code| code as u8).collect();
+    let mut code_size = min_code_size + 1;
+    let mut previous: Option<u16> = None;
+    let mut out = Vec::new();
+    let mut string = Vec::new();
+
+    let mut acc = 0u32;
+    let mut bits = 0u8;
+    let mut bytes = data.iter();
+    while out.len() < limit {
+        while bits < code_size {
+            match bytes.next() {
+                Some(&byte) => {
+                    acc |= (byte as u32) << bits;
+                    bits += 8;
+                }
+                None => return Ok(out),
+            }
+        }
+        let code = (acc & ((1 << code_size) - 1)) as u16;
+        acc >>= code_size;
+        bits -= code_size;
+
+        if code == clear {
+            prefixes.truncate(end as usize + 1);
+            suffixes.truncate(end as usize + 1);
+            code_size = min_code_size + 1;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            break;
+        }
+        let next = prefixes.len() as u16;
+        let known = (code as usize) < prefixes.len() && code != clear && code != end;
+        if !known && (code != next || previous.is_none()) {
+            return Err(GifError::Corrupt("LZW code out of range"));
+        }
+
+        string.clear();
+        let mut walk = if known { Some(code) } else { previous };
+        while let Some(c) = walk {
+            string.push(suffixes[c as usize]);
+            walk = prefixes[c as usize];
+        }
+        string.reverse();
+        let first = string[0];
+        if !known {
+            string.push(first);
+        }
+        out.extend_from_slice(&string);
+
+        if let Some(previous) = previous {
+            if prefixes.len() < (1 << MAX_CODE_SIZE) {
+                prefixes.push(Some(previous));
+                suffixes.push(first);
+                if prefixes.len() == (1 << code_size) && code_size < MAX_CODE_SIZE {
+                    code_size += 1;
+                }
+            }
+        }
+        previous = Some(code);
+    }
+    out.truncate(limit);
+    Ok(out)
+}
+
+#[test]
+fn test_lzw_small() {
+    // Known encoding of four pixels of color 0 at minimum code size 2:
+    // CLEAR(4) 0 6 0 END(5), each three bits wide.
+    assert_eq!(lzw_encode(&[0, 0, 0, 0], 2), vec![0x84, 0x51]);
+}
+
+#[test]
+fn test_header() {
+    let colors = [Color::rgb(0, 0, 0), Color::rgb(255, 255, 255)];
+    let encoder = GifEncoder::new(Vec::new(), 2, 2, &colors).unwrap();
+    let out = encoder.finish().unwrap();
+    assert_eq!(&out[..6], b"GIF89a");
+    assert_eq!(&out[6..11], &[2, 0, 2, 0, 0x80]);
+    assert_eq!(&out[13..19], &[0, 0, 0, 255, 255, 255]);
+    assert_eq!(out[out.len() - 1], 0x3B);
+}
+
+#[test]
+fn test_lzw_round_trip() {
+    let pixels: Vec<u8> = (0..5000).map(|i| ((i * 7 / 3) % 13) as u8).collect();
+    let data = lzw_encode(&pixels, 4);
+    assert_eq!(lzw_decode(&data, 4, pixels.len()).unwrap(), pixels);
+    assert_eq!(lzw_decode(&data, 4, 10).unwrap(), &pixels[..10]);
+}
+
+#[test]
+fn test_decode_frames() {
+    let colors: Vec<Color> = (0..16).map(|i| Color::rgb(i * 16, 0, 0)).collect();
+    let first: Vec<u8> = (0..12).collect();
+    let second = vec![15; 12];
+    let mut encoder = GifEncoder::new(Vec::new(), 4, 3, &colors).unwrap();
+    encoder.write_frame(&first, 10).unwrap();
+    encoder.write_frame(&second, 10).unwrap();
+    let bytes = encoder.finish().unwrap();
+
+    let frames = decode(&bytes, 24).unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!((frames[0].width, frames[0].height), (4, 3));
+    assert_eq!(frames[0].pixels, first);
+    assert_eq!(frames[1].pixels, second);
+
+    assert_eq!(decode(b"PNG", 24), Err(GifError::NotAGif));
+    assert_eq!(decode(&bytes[..bytes.len() / 2], 24), Err(GifError::Truncated));
+    assert_eq!(decode(&bytes, 23), Err(GifError::Corrupt("image too large")));
+}
+
+#[test]
+fn test_huge_header() {
+    // A 65535x65535 image with a few bytes of data, refused before any of it
+    // is decoded.
+    let colors = [Color::rgb(0, 0, 0), Color::rgb(255, 255, 255)];
+    let mut bytes = GifEncoder::new(Vec::new(), 1, 1, &colors).unwrap().finish().unwrap();
+    bytes.pop();
+    bytes.extend_from_slice(&[0x2C, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0, 2, 2, 0x84, 0x51, 0, 0x3B]);
+    assert_eq!(decode(&bytes, 1 << 20), Err(GifError::Corrupt("image too large")));
+}
+
+#[test]
+fn test_deinterlace() {
+    let rows: Vec<u8> = vec![0, 8, 4, 2, 6, 1, 3, 5, 7, 9];
+    assert_eq!(deinterlace(&rows, 1, 10), (0..10).collect::<Vec<u8>>());
+}
@@ -0,0 +1,333 @@
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+
+use cpu::CPU;
+use display::Display;
+use framebuffer::Framebuffer;
+use keyboard::{Keyboard, Keymap};
+use platform::Platform;
+use quirks::Quirks;
+use ram::RAM;
+use rombuilder::Reg;
+use sandbox;
+
+// Runs ROMs headless for tests, without setting up a CPU's memory, display
+// and keyboard by hand:
+//
+//     Harness::new(&rom).run(|machine| {
+//         machine.run_until_halt();
+//         machine.assert_reg(V0, 5);
+//         machine.assert_screen("
+//             #..#
+//             .##.
+//         ");
+//     });
+pub struct Harness {
+    rom: Vec<u8>,
+    quirks: Quirks,
+    memory_size: usize,
+    ipf: u64,
+    seed: u64,
+    limit: u64,
+}
+
+impl Harness {
+    pub fn new(rom: &[u8]) -> Harness {
+        Harness {
+            rom: rom.to_vec(),
+            quirks: Quirks::default(),
+            memory_size: RAM::init().size(),
+            ipf: 15,
+            seed: 0,
+            limit: 1_000_000,
+        }
+    }
+
+    // The platform's memory size and quirks.
+    pub fn platform(mut self, platform: Platform) -> Harness {
+        self.quirks = Quirks::for_platform(platform);
+        self.memory_size = platform.memory_size();
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Harness {
+        self.quirks = quirks;
+        self
+    }
+
+    // Instructions run between timer ticks.
+    pub fn ipf(mut self, ipf: u64) -> Harness {
+        self.ipf = ipf.max(1);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Harness {
+        self.seed = seed;
+        self
+    }
+
+    // Most instructions any one `run_*` call may take before failing the
+    // test, so ROMs that never get there don't hang it.
+    pub fn limit(mut self, instructions: u64) -> Harness {
+        self.limit = instructions;
+        self
+    }
+
+    pub fn run<F, R>(&self, test: F) -> R
+    where
+        F: FnOnce(&mut Machine) -> R,
+    {
+        let (sender, receiver) = channel();
+        let keymap = Keymap::default();
+        let layout = keymap.layout().chars().collect();
+        let mut keyboard = Arc::new(Mutex::new(Keyboard::with_keymap(receiver, keymap)));
+        let shared = keyboard.clone();
+        let mut display = Display::init();
+        let mut ram = RAM::with_size(self.memory_size);
+        let mut cpu = CPU::init(&mut ram, &mut display, &mut keyboard, None);
+        cpu.load_rom(&self.rom);
+        cpu.set_quirks(self.quirks);
+        cpu.set_seed(self.seed);
+        let mut machine = Machine {
+            cpu,
+            keyboard: shared,
+            keys: sender,
+            layout,
+            pending_keys: 0,
+            ipf: self.ipf,
+            limit: self.limit,
+            cycle: 0,
+            instructions: 0,
+            frames: 0,
+        };
+        test(&mut machine)
+    }
+}
+
+// A booted ROM, run one instruction at a time with the timers ticking every
+// `ipf` instructions. Assertions panic with what differed.
+pub struct Machine<'a> {
+    cpu: CPU<'a>,
+    keyboard: Arc<Mutex<Keyboard>>,
+    keys: Sender<u8>,
+    layout: Vec<char>,
+    // Presses sent that the ROM hasn't read yet.
+    pending_keys: usize,
+    ipf: u64,
+    limit: u64,
+    // Instructions run since the last timer tick.
+    cycle: u64,
+    instructions: u64,
+    frames: u64,
+}
+
+impl<'a> Machine<'a> {
+    pub fn cpu(&mut self) -> &mut CPU<'a> {
+        &mut self.cpu
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Presses `key` (0-F) at the ROM's next key read, after any presses
+    // before it. It stays held until another key is pressed or it is
+    // released, as with a terminal.
+    pub fn press(&mut self, key: u8) {
+        self.keys.send(self.layout[key as usize & 0x0F] as u8).unwrap();
+        self.pending_keys += 1;
+    }
+
+    pub fn release(&mut self, key: u8) {
+        self.keyboard.lock().unwrap().release_key(key as usize & 0x0F);
+        self.cpu.sync_keys();
+    }
+
+    // Whether the ROM jumps to itself, as ROMs do once they're done.
+    pub fn is_halted(&self) -> bool {
+        sandbox::is_halted(&self.cpu)
+    }
+
+    // Whether the ROM waits for a key (FX0A) with no press left to read.
+    pub fn is_waiting_for_key(&self) -> bool {
+        sandbox::is_key_wait(&self.cpu) && self.pending_keys == 0
+    }
+
+    #[track_caller]
+    pub fn step(&mut self) {
+        if self.is_waiting_for_key() {
+            panic!("ROM waits for a key at 0x{:03X}, but none is pressed", self.cpu.get_pc());
+        }
+        self.keyboard.lock().unwrap().reset_last_key();
+        // The CPU takes keys at the end of a frame; a press waiting for a key
+        // check gets there in time for it.
+        if self.pending_keys > 0 && sandbox::is_key_read(&self.cpu) {
+            self.cpu.poll_keys();
+        }
+        self.cpu.run_cycle();
+        if let Some(fault) = self.cpu.fault() {
+            panic!("{} at 0x{:03X}", fault, self.cpu.get_pc());
+        }
+        if self.keyboard.lock().unwrap().last_key.is_some() {
+            self.pending_keys = self.pending_keys.saturating_sub(1);
+        }
+        self.count_instruction();
+    }
+
+    // Lets an instruction's time pass without running it, as when the ROM
+    // waits for a key that another emulator's trace says never came.
+    pub fn idle(&mut self) {
+        self.count_instruction();
+    }
+
+    fn count_instruction(&mut self) {
+        self.instructions += 1;
+        self.cycle += 1;
+        if self.cycle == self.ipf {
+            self.cpu.end_frame();
+            self.cycle = 0;
+            self.frames += 1;
+        }
+    }
+
+    #[track_caller]
+    pub fn steps(&mut self, n: u64) {
+        for _ in 0..n {
+            self.step();
+        }
+    }
+
+    // Runs until `done` holds, failing after the harness's limit.
+    #[track_caller]
+    pub fn run_until<F>(&mut self, mut done: F)
+    where
+        F: FnMut(&Machine) -> bool,
+    {
+        let start = self.instructions;
+        while !done(self) {
+            if self.instructions - start >= self.limit {
+                panic!("still running after {} instructions, at 0x{:03X}", self.limit, self.cpu.get_pc());
+            }
+            self.step();
+        }
+    }
+
+    #[track_caller]
+    pub fn run_until_pc(&mut self, pc: u16) {
+        self.run_until(|machine| machine.cpu.get_pc() == pc);
+    }
+
+    #[track_caller]
+    pub fn run_frames(&mut self, n: u64) {
+        let end = self.frames + n;
+        self.run_until(|machine| machine.frames >= end);
+    }
+
+    // Runs to the end of the current frame. A ROM waiting for a key that
+    // isn't pressed idles until the frame ends, as with nobody at the
+    // keyboard, rather than failing.
+    pub fn run_frame(&mut self) {
+        let frame = self.frames;
+        while self.frames == frame {
+            if self.is_waiting_for_key() {
+                self.cpu.end_frame();
+                self.cycle = 0;
+                self.frames += 1;
+            } else {
+                self.step();
+            }
+        }
+    }
+
+    // Runs until the ROM halts or waits for a key that isn't coming.
+    #[track_caller]
+    pub fn run_until_halt(&mut self) {
+        self.run_until(|machine| machine.is_halted() || machine.is_waiting_for_key());
+    }
+
+    // The screen as `assert_screen` takes it: `#` for lit pixels and `.` for
+    // the rest, a line per row.
+    pub fn screen(&self) -> String {
+        let display = self.cpu.display();
+        let mut out = String::new();
+        for y in 0..display.height() {
+            out.extend((0..display.width()).map(|x| if display.pixel(x, y) { '#' } else { '.' }));
+            out.push('\n');
+        }
+        out
+    }
+
+    // Checks the screen against `art`, drawn as `screen` does. Indentation
+    // and blank lines are ignored, and `art` need only cover the top left of
+    // the screen: the rest must be dark. Art bigger than the screen fails
+    // before any pixels are compared.
+    #[track_caller]
+    pub fn assert_screen(&self, art: &str) {
+        let actual: Vec<String> = self.screen().lines().map(str::to_string).collect();
+        let (width, height) = (actual[0].len(), actual.len());
+        let art: Vec<&str> = art.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+        let art_width = art.iter().map(|line| line.len()).max().unwrap_or(0);
+        if art_width > width || art.len() > height {
+            panic!("screen is {}x{}, but the art is {}x{}", width, height, art_width, art.len());
+        }
+        let mut expected: Vec<String> =
+            art.iter().map(|line| format!("{:.<width$}", line, width = width)).collect();
+        while expected.len() < actual.len() {
+            expected.push(".".repeat(width));
+        }
+        if expected != actual {
+            panic!("screen differs (expected | actual):\n{}", screen_diff(&expected, &actual));
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_reg(&self, reg: Reg, value: u8) {
+        let actual = self.cpu.get_reg(reg.0 as usize);
+        assert!(actual == value, "V{:X} is 0x{:02X}, expected 0x{:02X}", reg.0, actual, value);
+    }
+
+    #[track_caller]
+    pub fn assert_i(&self, value: u16) {
+        let actual = self.cpu.get_i();
+        assert!(actual == value, "I is 0x{:03X}, expected 0x{:03X}", actual, value);
+    }
+
+    #[track_caller]
+    pub fn assert_pc(&self, value: u16) {
+        let actual = self.cpu.get_pc();
+        assert!(actual == value, "PC is 0x{:03X}, expected 0x{:03X}", actual, value);
+    }
+
+    #[track_caller]
+    pub fn assert_memory(&self, addr: usize, bytes: &[u8]) {
+        let actual: Vec<u8> = (addr..addr + bytes.len()).map(|addr| self.cpu.get_mem8(addr)).collect();
+        assert!(
+            actual == bytes,
+            "memory at 0x{:03X} is {:02X?}, expected {:02X?}",
+            addr,
+            actual,
+            bytes
+        );
+    }
+}
+
+// Both screens side by side, rows that differ marked with `!`.
+fn screen_diff(expected: &[String], actual: &[String]) -> String {
+    let mut out = String::new();
+    for (row, (a, b)) in expected.iter().zip(actual.iter()).enumerate() {
+        let mark = if a == b { ' ' } else { '!' };
+        out.push_str(&format!("{}{:2} {} | {}\n", mark, row, a, b));
+    }
+    out
+}
+
+#[test]
+fn test_screen_diff_marks_rows() {
+    let expected = vec!["#.".to_string(), "..".to_string()];
+    let actual = vec!["#.".to_string(), ".#".to_string()];
+    assert_eq!(screen_diff(&expected, &actual), "  0 #. | #.\n! 1 .. | .#\n");
+}
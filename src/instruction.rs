@@ -0,0 +1,225 @@
+use std::fmt;
+
+// A decoded CHIP-8 instruction. Register operands are register numbers
+// (0-F), and mnemonics follow Cowgod's technical reference.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Instruction {
+    Cls,
+    Ret,
+    Jp(u16),
+    Call(u16),
+    SeByte(u8, u8),
+    SneByte(u8, u8),
+    SeReg(u8, u8),
+    LdByte(u8, u8),
+    AddByte(u8, u8),
+    LdReg(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    AddReg(u8, u8),
+    Sub(u8, u8),
+    Shr(u8, u8),
+    Subn(u8, u8),
+    Shl(u8, u8),
+    SneReg(u8, u8),
+    LdI(u16),
+    JpV0(u16),
+    Rnd(u8, u8),
+    Drw(u8, u8, u8),
+    Skp(u8),
+    Sknp(u8),
+    // VX = delay timer.
+    LdVxDt(u8),
+    // VX = next key pressed.
+    LdVxK(u8),
+    LdDtVx(u8),
+    LdStVx(u8),
+    AddIVx(u8),
+    // I = address of the font sprite for the digit in VX.
+    LdFVx(u8),
+    // Stores the decimal digits of VX at I, I + 1 and I + 2.
+    LdBVx(u8),
+    // Stores V0 to VX at I.
+    LdIVx(u8),
+    // Loads V0 to VX from I.
+    LdVxI(u8),
+    // Anything else: a word of data, or an instruction from a later platform.
+    Data(u16),
+}
+
+impl Instruction {
+    pub fn decode(op: u16) -> Instruction {
+        use self::Instruction::*;
+
+        let x = ((op >> 8) & 0x0F) as u8;
+        let y = ((op >> 4) & 0x0F) as u8;
+        let n = (op & 0x0F) as u8;
+        let nn = (op & 0xFF) as u8;
+        let nnn = op & 0x0FFF;
+        match op >> 12 {
+            0x0 => match op {
+                0x00E0 => Cls,
+                0x00EE => Ret,
+                _ => Data(op),
+            },
+            0x1 => Jp(nnn),
+            0x2 => Call(nnn),
+            0x3 => SeByte(x, nn),
+            0x4 => SneByte(x, nn),
+            0x5 if n == 0 => SeReg(x, y),
+            0x6 => LdByte(x, nn),
+            0x7 => AddByte(x, nn),
+            0x8 => match n {
+                0x0 => LdReg(x, y),
+                0x1 => Or(x, y),
+                0x2 => And(x, y),
+                0x3 => Xor(x, y),
+                0x4 => AddReg(x, y),
+                0x5 => Sub(x, y),
+                0x6 => Shr(x, y),
+                0x7 => Subn(x, y),
+                0xE => Shl(x, y),
+                _ => Data(op),
+            },
+            0x9 if n == 0 => SneReg(x, y),
+            0xA => LdI(nnn),
+            0xB => JpV0(nnn),
+            0xC => Rnd(x, nn),
+            0xD => Drw(x, y, n),
+            0xE => match nn {
+                0x9E => Skp(x),
+                0xA1 => Sknp(x),
+                _ => Data(op),
+            },
+            0xF => match nn {
+                0x07 => LdVxDt(x),
+                0x0A => LdVxK(x),
+                0x15 => LdDtVx(x),
+                0x18 => LdStVx(x),
+                0x1E => AddIVx(x),
+                0x29 => LdFVx(x),
+                0x33 => LdBVx(x),
+                0x55 => LdIVx(x),
+                0x65 => LdVxI(x),
+                _ => Data(op),
+            },
+            _ => Data(op),
+        }
+    }
+
+    // Operands are masked to their field widths, so `decode(encode())` is
+    // the identity for every instruction built from in-range operands.
+    pub fn encode(&self) -> u16 {
+        use self::Instruction::*;
+
+        fn xnn(op: u16, x: u8, nn: u8) -> u16 {
+            op | (x as u16 & 0x0F) << 8 | nn as u16
+        }
+        fn xyn(op: u16, x: u8, y: u8, n: u8) -> u16 {
+            op | (x as u16 & 0x0F) << 8 | (y as u16 & 0x0F) << 4 | (n as u16 & 0x0F)
+        }
+        match *self {
+            Cls => 0x00E0,
+            Ret => 0x00EE,
+            Jp(nnn) => 0x1000 | nnn & 0x0FFF,
+            Call(nnn) => 0x2000 | nnn & 0x0FFF,
+            SeByte(x, nn) => xnn(0x3000, x, nn),
+            SneByte(x, nn) => xnn(0x4000, x, nn),
+            SeReg(x, y) => xyn(0x5000, x, y, 0),
+            LdByte(x, nn) => xnn(0x6000, x, nn),
+            AddByte(x, nn) => xnn(0x7000, x, nn),
+            LdReg(x, y) => xyn(0x8000, x, y, 0x0),
+            Or(x, y) => xyn(0x8000, x, y, 0x1),
+            And(x, y) => xyn(0x8000, x, y, 0x2),
+            Xor(x, y) => xyn(0x8000, x, y, 0x3),
+            AddReg(x, y) => xyn(0x8000, x, y, 0x4),
+            Sub(x, y) => xyn(0x8000, x, y, 0x5),
+            Shr(x, y) => xyn(0x8000, x, y, 0x6),
+            Subn(x, y) => xyn(0x8000, x, y, 0x7),
+            Shl(x, y) => xyn(0x8000, x, y, 0xE),
+            SneReg(x, y) => xyn(0x9000, x, y, 0),
+            LdI(nnn) => 0xA000 | nnn & 0x0FFF,
+            JpV0(nnn) => 0xB000 | nnn & 0x0FFF,
+            Rnd(x, nn) => xnn(0xC000, x, nn),
+            Drw(x, y, n) => xyn(0xD000, x, y, n),
+            Skp(x) => xnn(0xE000, x, 0x9E),
+            Sknp(x) => xnn(0xE000, x, 0xA1),
+            LdVxDt(x) => xnn(0xF000, x, 0x07),
+            LdVxK(x) => xnn(0xF000, x, 0x0A),
+            LdDtVx(x) => xnn(0xF000, x, 0x15),
+            LdStVx(x) => xnn(0xF000, x, 0x18),
+            AddIVx(x) => xnn(0xF000, x, 0x1E),
+            LdFVx(x) => xnn(0xF000, x, 0x29),
+            LdBVx(x) => xnn(0xF000, x, 0x33),
+            LdIVx(x) => xnn(0xF000, x, 0x55),
+            LdVxI(x) => xnn(0xF000, x, 0x65),
+            Data(word) => word,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Instruction::*;
+
+        match *self {
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            Jp(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Call(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            SeByte(x, nn) => write!(f, "SE V{:X}, 0x{:02X}", x, nn),
+            SneByte(x, nn) => write!(f, "SNE V{:X}, 0x{:02X}", x, nn),
+            SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            LdByte(x, nn) => write!(f, "LD V{:X}, 0x{:02X}", x, nn),
+            AddByte(x, nn) => write!(f, "ADD V{:X}, 0x{:02X}", x, nn),
+            LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LdI(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            JpV0(nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            Rnd(x, nn) => write!(f, "RND V{:X}, 0x{:02X}", x, nn),
+            Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Skp(x) => write!(f, "SKP V{:X}", x),
+            Sknp(x) => write!(f, "SKNP V{:X}", x),
+            LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            AddIVx(x) => write!(f, "ADD I, V{:X}", x),
+            LdFVx(x) => write!(f, "LD F, V{:X}", x),
+            LdBVx(x) => write!(f, "LD B, V{:X}", x),
+            LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            Data(word) => write!(f, "DW 0x{:04X}", word),
+        }
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    for op in 0..=0xFFFFu16 {
+        let instruction = Instruction::decode(op);
+        if let Instruction::Data(word) = instruction {
+            assert_eq!(word, op);
+        }
+        assert_eq!(instruction.encode(), op, "{}", instruction);
+    }
+}
+
+#[test]
+fn test_mnemonics() {
+    assert_eq!(Instruction::decode(0x00E0).to_string(), "CLS");
+    assert_eq!(Instruction::decode(0x1234).to_string(), "JP 0x234");
+    assert_eq!(Instruction::decode(0x8AB6).to_string(), "SHR VA, VB");
+    assert_eq!(Instruction::decode(0xD125).to_string(), "DRW V1, V2, 5");
+    assert_eq!(Instruction::decode(0xF355).to_string(), "LD [I], V3");
+    assert_eq!(Instruction::decode(0x5121).to_string(), "DW 0x5121");
+}
@@ -1,8 +1,10 @@
 use std::collections::HashMap;
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::{Receiver, TryRecvError};
 
 pub const EXIT_CHAR: char = 'l';
 const EXIT_VAL: u8 = 17;
+pub const SCREENSHOT_CHAR: char = 'g';
+const SCREENSHOT_VAL: u8 = 18;
 
 lazy_static! {
     static ref KEY_MAP: HashMap<char, u8> = [
@@ -23,60 +25,145 @@ lazy_static! {
         ('j', 14),
         ('k', 15),
         (EXIT_CHAR, EXIT_VAL),
+        (SCREENSHOT_CHAR, SCREENSHOT_VAL),
     ].iter()
         .cloned()
         .collect();
 }
 
+// Which terminal characters stand for which CHIP-8 keys. Every keymap also
+// has the exit and screenshot characters.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Keymap(HashMap<char, u8>);
+
+impl Keymap {
+    // Keys 0 to 15 are given in order of the rows of a 4x4 block on the
+    // keyboard, so `1234qwerasdfzxcv` is the usual QWERTY layout.
+    pub fn from_layout(layout: &str) -> Option<Keymap> {
+        let chars: Vec<char> = layout.chars().collect();
+        let mut map = HashMap::new();
+        map.insert(EXIT_CHAR, EXIT_VAL);
+        map.insert(SCREENSHOT_CHAR, SCREENSHOT_VAL);
+        for (key, &c) in chars.iter().enumerate() {
+            if map.insert(c, key as u8).is_some() {
+                return None;
+            }
+        }
+        if chars.len() == 16 {
+            Some(Keymap(map))
+        } else {
+            None
+        }
+    }
+
+    // `dvorak` (the default), `qwerty`, or a layout for `from_layout`.
+    pub fn parse(spec: &str) -> Option<Keymap> {
+        match spec {
+            "dvorak" => Some(Keymap::default()),
+            "qwerty" => Keymap::from_layout("1234qwerasdfzxcv"),
+            _ => Keymap::from_layout(spec),
+        }
+    }
+
+    // The 16 characters for keys 0 to 15, as `from_layout` takes them.
+    pub fn layout(&self) -> String {
+        let mut layout = [' '; 16];
+        for (&c, &key) in self.0.iter() {
+            if let Some(slot) = layout.get_mut(key as usize) {
+                *slot = c;
+            }
+        }
+        layout.iter().collect()
+    }
+
+    fn get(&self, c: char) -> Option<u8> {
+        self.0.get(&c).cloned()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap(KEY_MAP.clone())
+    }
+}
+
 pub struct Keyboard {
     pub keys: [bool; 16],
+    keymap: Keymap,
     input: Receiver<u8>,
     exit_flag: bool,
+    screenshot_flag: bool,
+    closed: bool,
     pub last_key: Option<u8>,
 }
 
 impl Keyboard {
     pub fn init(input: Receiver<u8>) -> Keyboard {
+        Keyboard::with_keymap(input, Keymap::default())
+    }
+
+    pub fn with_keymap(input: Receiver<u8>, keymap: Keymap) -> Keyboard {
         Keyboard {
             keys: [false; 16],
+            keymap,
             input,
             exit_flag: false,
+            screenshot_flag: false,
+            closed: false,
             last_key: None,
         }
     }
 
     pub fn read_input(&mut self) {
-        let former_key = self.last_key.clone();
+        let former_key = self.last_key;
 
         match self.input.try_recv() {
             Ok(key) => {
-                let res = KEY_MAP.get(&(key.into())).cloned();
+                let res = self.keymap.get(key.into());
                 if res == Some(EXIT_VAL) {
                     self.exit_flag = true;
                     self.last_key = None;
+                } else if res == Some(SCREENSHOT_VAL) {
+                    self.screenshot_flag = true;
+                    self.last_key = None;
                 } else {
                     self.last_key = res;
                 }
             }
-            _ => self.last_key = None,
+            Err(TryRecvError::Disconnected) => {
+                self.closed = true;
+                self.last_key = None;
+            }
+            Err(TryRecvError::Empty) => self.last_key = None,
         }
 
+        // Terminals don't report key releases, so a key is held until
+        // another is pressed. Releases every other key rather than just
+        // `former_key`, which FX0A clears.
         if self.last_key != former_key {
-            for fk in former_key {
-                if self.last_key != None {
-                    self.release_key(fk.into());
-                }
-            }
-            for lk in self.last_key {
+            if let Some(lk) = self.last_key {
+                self.keys = [false; 16];
                 self.push_key(lk.into());
             }
         }
     }
 
+    // True once nothing can send any more input, as when running headless.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
     pub fn exit_key(&self) -> bool {
         self.exit_flag
     }
 
+    // Reports a pending screenshot request once, clearing it.
+    pub fn take_screenshot_request(&mut self) -> bool {
+        let requested = self.screenshot_flag;
+        self.screenshot_flag = false;
+        requested
+    }
+
     pub fn reset_last_key(&mut self) {
         self.last_key = None;
     }
@@ -96,3 +183,36 @@ impl Keyboard {
         self.keys[key]
     }
 }
+
+#[test]
+fn test_keymaps() {
+    let qwerty = Keymap::parse("qwerty").unwrap();
+    assert_eq!(qwerty.get('q'), Some(4));
+    assert_eq!(qwerty.get('v'), Some(15));
+    assert_eq!(qwerty.get(EXIT_CHAR), Some(EXIT_VAL));
+    assert_eq!(Keymap::parse("dvorak").unwrap().get('\''), Some(4));
+    assert_eq!(qwerty.layout(), "1234qwerasdfzxcv");
+    assert!(Keymap::parse("1234").is_none());
+    assert!(Keymap::parse("1234qwerasdfzxcl").is_none());
+}
+
+#[test]
+fn test_new_key_releases_held_keys() {
+    use std::sync::mpsc::channel;
+
+    let (sender, receiver) = channel();
+    let mut keyboard = Keyboard::init(receiver);
+    sender.send(b'1').unwrap();
+    keyboard.read_input();
+    assert!(keyboard.is_pressed(0));
+
+    // Held with no new input, even once forgotten as the last key.
+    keyboard.read_input();
+    keyboard.reset_last_key();
+    assert!(keyboard.is_pressed(0));
+
+    sender.send(b'2').unwrap();
+    keyboard.read_input();
+    assert!(!keyboard.is_pressed(0));
+    assert!(keyboard.is_pressed(1));
+}
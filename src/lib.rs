@@ -1,16 +1,55 @@
 #[macro_use]
 extern crate lazy_static;
 
+pub mod asm;
+pub mod blocks;
+pub mod cartridge;
+pub mod cli;
+pub mod config;
 pub mod cpu;
+pub mod disasm;
 pub mod display;
 pub mod displayimpl;
+pub mod framebuffer;
+pub mod fuzz;
+pub mod gif;
+pub mod harness;
+pub mod instruction;
+pub mod json;
 pub mod keyboard;
+pub mod observer;
+pub mod octo;
 pub mod opcode;
+pub mod palette;
+pub mod phosphor;
+pub mod platform;
+pub mod quirks;
+pub mod png;
 pub mod ram;
+pub mod recompile;
+pub mod recorder;
+pub mod regress;
+pub mod rom;
+pub mod rombuilder;
+pub mod romdb;
+pub mod sandbox;
+pub mod screenshot;
+pub mod sha1;
+pub mod sixel;
+pub mod swapchain;
+pub mod trace;
 
 pub use cpu::CPU;
-pub use display::Display;
+pub use display::{Display, DrawMode};
 pub use displayimpl::DisplayImpl;
+pub use framebuffer::Framebuffer;
+pub use instruction::Instruction;
 pub use keyboard::Keyboard;
+pub use observer::Observer;
 pub use opcode::Opcode;
+pub use palette::Palette;
+pub use platform::Platform;
+pub use quirks::Quirks;
 pub use ram::RAM;
+pub use rom::Rom;
+pub use rombuilder::RomBuilder;
@@ -0,0 +1,32 @@
+use opcode::Opcode;
+
+// Callbacks for things that happen inside `CPU::run_cycle`. Every method
+// does nothing by default, so observers only implement the events they care
+// about. Observers run on the emulation thread, in the order they were added.
+pub trait Observer {
+    // `pc` is the address the instruction was fetched from.
+    fn instruction(&mut self, _pc: u16, _opcode: &Opcode) {}
+
+    fn memory_read(&mut self, _addr: usize, _value: u8) {}
+
+    fn memory_write(&mut self, _addr: usize, _value: u8) {}
+
+    // `x` and `y` are the sprite's position as given by the registers.
+    fn sprite_drawn(&mut self, _x: u8, _y: u8, _height: u8, _collision: bool) {}
+
+    fn screen_cleared(&mut self) {}
+
+    fn subroutine_call(&mut self, _from: u16, _to: u16) {}
+
+    fn subroutine_return(&mut self, _from: u16, _to: u16) {}
+
+    fn sound(&mut self, _on: bool) {}
+
+    // `reg` is the register the pressed key will be stored in.
+    fn key_wait_started(&mut self, _reg: usize) {}
+
+    fn key_wait_finished(&mut self, _key: u8) {}
+
+    // Called just before the CPU gives up on an instruction it can't run.
+    fn error(&mut self, _pc: u16, _message: &str) {}
+}
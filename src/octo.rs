@@ -0,0 +1,806 @@
+use std::collections::HashMap;
+
+use asm::AsmError;
+use instruction::Instruction;
+use ram::ROM_START;
+
+// Assembles Octo source, which is how Octo cartridges store their programs.
+// Covers the language bar its compile-time metaprogramming (`:macro`,
+// `:calc`, `:stringmode` and the like): labels, `:const`, `:alias`, `:org`,
+// `:unpack`, every CHIP-8, SUPER-CHIP and XO-CHIP statement, `if ... then`,
+// `if ... begin ... else ... end`, `loop ... while ... again`, and bare
+// numbers as bytes of data. A bare name calls the label it names. Tokens
+// are separated by whitespace and `#` starts a comment.
+//
+// As in Octo, the program starts with a jump to `main` unless `: main` is
+// the first thing in it.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let tokens = tokenize(source);
+    let mut assembler = Assembler {
+        tokens: &tokens,
+        pos: 0,
+        line: 1,
+        rom: Vec::new(),
+        here: ROM_START,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+    };
+    assembler.program()?;
+    Ok(assembler.rom)
+}
+
+struct Token<'a> {
+    line: usize,
+    text: &'a str,
+}
+
+fn tokenize<'a>(source: &'a str) -> Vec<Token<'a>> {
+    source
+        .lines()
+        .enumerate()
+        .flat_map(|(i, line)| {
+            let code = line.split('#').next().unwrap_or("");
+            code.split_whitespace().map(move |text| Token { line: i + 1, text })
+        })
+        .collect()
+}
+
+// Where a label's address goes once it is known.
+#[derive(Clone, Copy)]
+enum Field {
+    // The low 12 bits of the instruction at the fixup.
+    Addr,
+    // The word after an `i := long`.
+    Long,
+    // The `v0 := ` and `v1 := ` pair of an `:unpack`, with its high nibble.
+    Unpack(u8),
+}
+
+struct Fixup {
+    at: usize,
+    name: String,
+    line: usize,
+    field: Field,
+}
+
+// An open `begin` or `else` with its jump to patch, or a `loop` with its
+// start and the jumps out of it from `while`s.
+enum Block {
+    If(usize),
+    Else(usize),
+    Loop(usize, Vec<usize>),
+}
+
+struct Assembler<'a> {
+    tokens: &'a [Token<'a>],
+    pos: usize,
+    line: usize,
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<(usize, Block)>,
+}
+
+impl<'a> Assembler<'a> {
+    fn program(&mut self) -> Result<(), AsmError> {
+        let main_first = self.tokens.len() >= 2 && self.tokens[0].text == ":" && self.tokens[1].text == "main";
+        if !main_first {
+            self.word(0)?;
+        }
+        while self.pos < self.tokens.len() {
+            self.statement()?;
+        }
+        if let Some(&(line, ref block)) = self.blocks.last() {
+            let what = match *block {
+                Block::Loop(..) => "loop without again",
+                _ => "begin without end",
+            };
+            return Err(error(line, what.to_string()));
+        }
+        if !main_first {
+            let main = *self
+                .labels
+                .get("main")
+                .ok_or_else(|| error(1, "no main label".to_string()))?;
+            self.patch(ROM_START, Instruction::Jp(main as u16).encode())?;
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let addr = *self
+                .labels
+                .get(&fixup.name)
+                .ok_or_else(|| self.error(format!("unknown label \"{}\"", fixup.name)))?;
+            match fixup.field {
+                Field::Addr => {
+                    let op = self.read(fixup.at) | self.fit(addr as i64, 0, 0xFFF)? as u16;
+                    self.patch(fixup.at, op)?;
+                }
+                Field::Long => self.patch(fixup.at, addr as u16)?,
+                Field::Unpack(nibble) => {
+                    let addr = self.fit(addr as i64, 0, 0xFFF)? as u16;
+                    let hi = (nibble as u16) << 4 | addr >> 8;
+                    self.patch(fixup.at, Instruction::LdByte(0, hi as u8).encode())?;
+                    self.patch(fixup.at + 2, Instruction::LdByte(1, addr as u8).encode())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        use self::Instruction::*;
+
+        let token = self.next()?;
+        match token {
+            ":" => {
+                let name = self.next()?;
+                if self.labels.insert(name.to_string(), self.here).is_some() {
+                    return Err(self.error(format!("label \"{}\" defined twice", name)));
+                }
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.next()?;
+                let value = self.value(value)?;
+                self.constants.insert(name.to_string(), value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let reg = self.register()?;
+                self.aliases.insert(name.to_string(), reg);
+            }
+            ":org" => {
+                let addr = self.next()?;
+                self.here = self.fit(self.value(addr)?, ROM_START as i64, 0xFFFF)? as usize;
+            }
+            ":unpack" => {
+                let nibble = self.nibble()?;
+                let name = self.next()?;
+                match self.known(name) {
+                    Some(addr) => {
+                        let addr = self.fit(addr, 0, 0xFFF)? as u16;
+                        self.op(LdByte(0, ((nibble as u16) << 4 | addr >> 8) as u8))?;
+                        self.op(LdByte(1, addr as u8))?;
+                    }
+                    None => {
+                        self.fixup(name, Field::Unpack(nibble));
+                        self.word(0)?;
+                        self.word(0)?;
+                    }
+                }
+            }
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit(byte)?;
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "clear" => self.op(Cls)?,
+            "return" | ";" => self.op(Ret)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.word(0x00C0 | n as u16)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.word(0x00D0 | n as u16)?;
+            }
+            "scroll-right" => self.word(0x00FB)?,
+            "scroll-left" => self.word(0x00FC)?,
+            "exit" => self.word(0x00FD)?,
+            "lores" => self.word(0x00FE)?,
+            "hires" => self.word(0x00FF)?,
+            "audio" => self.word(0xF002)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.word(0xF001 | (n as u16) << 8)?;
+            }
+            "jump" => self.addr(Jp(0))?,
+            "jump0" => self.addr(JpV0(0))?,
+            "bcd" => {
+                let x = self.register()?;
+                self.op(LdBVx(x))?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    let n = if token == "save" { 2 } else { 3 };
+                    self.word(0x5000 | (x as u16) << 8 | (y as u16) << 4 | n)?;
+                } else if token == "save" {
+                    self.op(LdIVx(x))?;
+                } else {
+                    self.op(LdVxI(x))?;
+                }
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.word(0xF075 | (x as u16) << 8)?;
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.word(0xF085 | (x as u16) << 8)?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.op(Drw(x, y, n))?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                match token {
+                    "delay" => self.op(LdDtVx(x))?,
+                    "buzzer" => self.op(LdStVx(x))?,
+                    _ => self.word(0xF03A | (x as u16) << 8)?,
+                }
+            }
+            "i" => self.i()?,
+            "if" => self.if_statement()?,
+            "else" => match self.blocks.pop() {
+                Some((line, Block::If(at))) => {
+                    let jump = self.here;
+                    self.op(Jp(0))?;
+                    self.jump_here(at)?;
+                    self.blocks.push((line, Block::Else(jump)));
+                }
+                _ => return Err(self.error("else without if ... begin".to_string())),
+            },
+            "end" => match self.blocks.pop() {
+                Some((_, Block::If(at))) | Some((_, Block::Else(at))) => self.jump_here(at)?,
+                _ => return Err(self.error("end without begin".to_string())),
+            },
+            "loop" => {
+                let line = self.line;
+                self.blocks.push((line, Block::Loop(self.here, Vec::new())));
+            }
+            "while" => {
+                self.condition(true)?;
+                let jump = self.here;
+                self.op(Jp(0))?;
+                let exits = self.blocks.iter_mut().rev().find_map(|&mut (_, ref mut block)| match *block {
+                    Block::Loop(_, ref mut exits) => Some(exits),
+                    _ => None,
+                });
+                match exits {
+                    Some(exits) => exits.push(jump),
+                    None => return Err(self.error("while outside a loop".to_string())),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some((_, Block::Loop(start, exits))) => {
+                    self.op(Jp(self.fit(start as i64, 0, 0xFFF)? as u16))?;
+                    for exit in exits {
+                        self.jump_here(exit)?;
+                    }
+                }
+                _ => return Err(self.error("again without loop".to_string())),
+            },
+            _ if self.register_of(token).is_some() => self.register_statement(token)?,
+            _ if token.starts_with(':') => {
+                return Err(self.error(format!("{} isn't supported", token)));
+            }
+            _ => match self.known(token) {
+                // Numbers and constants are bytes of data.
+                Some(_) if !self.labels.contains_key(token) => {
+                    let byte = self.byte_of(token)?;
+                    self.emit(byte)?;
+                }
+                _ if self.peek().is_some_and(is_operator) => {
+                    return Err(self.error(format!("{} isn't a register", token)));
+                }
+                _ => {
+                    self.pos -= 1;
+                    self.addr(Call(0))?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn register_statement(&mut self, dest: &str) -> Result<(), AsmError> {
+        use self::Instruction::*;
+
+        let x = self.register_of(dest).unwrap();
+        let operator = self.next()?;
+        let source = self.next()?;
+        let y = self.register_of(source);
+        let instruction = match (operator, y) {
+            (":=", Some(y)) => LdReg(x, y),
+            (":=", None) => match source {
+                "random" => Rnd(x, self.byte()?),
+                "delay" => LdVxDt(x),
+                "key" => LdVxK(x),
+                _ => LdByte(x, self.byte_of(source)?),
+            },
+            ("+=", Some(y)) => AddReg(x, y),
+            ("+=", None) => AddByte(x, self.byte_of(source)?),
+            ("-=", Some(y)) => Sub(x, y),
+            ("-=", None) => AddByte(x, self.byte_of(source)?.wrapping_neg()),
+            ("=-", Some(y)) => Subn(x, y),
+            ("|=", Some(y)) => Or(x, y),
+            ("&=", Some(y)) => And(x, y),
+            ("^=", Some(y)) => Xor(x, y),
+            (">>=", Some(y)) => Shr(x, y),
+            ("<<=", Some(y)) => Shl(x, y),
+            _ => return Err(self.error(format!("can't do {} {} {}", dest, operator, source))),
+        };
+        self.op(instruction)
+    }
+
+    fn i(&mut self) -> Result<(), AsmError> {
+        use self::Instruction::*;
+
+        match self.next()? {
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.next()?;
+                    let x = self.register()?;
+                    self.op(LdFVx(x))
+                }
+                Some("bighex") => {
+                    self.next()?;
+                    let x = self.register()?;
+                    self.word(0xF030 | (x as u16) << 8)
+                }
+                Some("long") => {
+                    self.next()?;
+                    self.word(0xF000)?;
+                    let name = self.next()?;
+                    match self.known(name) {
+                        Some(addr) => {
+                            let addr = self.fit(addr, 0, 0xFFFF)? as u16;
+                            self.word(addr)
+                        }
+                        None => {
+                            self.fixup(name, Field::Long);
+                            self.word(0)
+                        }
+                    }
+                }
+                _ => self.addr(LdI(0)),
+            },
+            "+=" => {
+                let x = self.register()?;
+                self.op(AddIVx(x))
+            }
+            other => Err(self.error(format!("can't do i {}", other))),
+        }
+    }
+
+    fn if_statement(&mut self) -> Result<(), AsmError> {
+        let (pos, here) = (self.pos, self.here);
+        self.condition(false)?;
+        match self.next()? {
+            "then" => Ok(()),
+            "begin" => {
+                // The block is jumped over unless the condition holds, so the
+                // skip has to go the other way.
+                self.pos = pos;
+                self.here = here;
+                self.condition(true)?;
+                self.next()?;
+                let line = self.line;
+                self.blocks.push((line, Block::If(self.here)));
+                self.op(Instruction::Jp(0))
+            }
+            other => Err(self.error(format!("expected then or begin, not {}", other))),
+        }
+    }
+
+    // Emits the skip that lets the next instruction run only when the
+    // condition holds, or with `negated`, only when it doesn't. `<`, `>`,
+    // `<=` and `>=` subtract in VF, or the register aliased `compare-temp`,
+    // and test the borrow flag the subtraction leaves in VF.
+    fn condition(&mut self, negated: bool) -> Result<(), AsmError> {
+        use self::Instruction::*;
+
+        let x = self.register()?;
+        let mut comparison = self.next()?;
+        if negated {
+            comparison = match comparison {
+                "==" => "!=",
+                "!=" => "==",
+                "key" => "-key",
+                "-key" => "key",
+                "<" => ">=",
+                ">=" => "<",
+                ">" => "<=",
+                "<=" => ">",
+                other => other,
+            };
+        }
+        match comparison {
+            "key" => self.op(Sknp(x)),
+            "-key" => self.op(Skp(x)),
+            "==" | "!=" => {
+                let rhs = self.next()?;
+                let instruction = match (comparison, self.register_of(rhs)) {
+                    ("==", Some(y)) => SneReg(x, y),
+                    ("==", None) => SneByte(x, self.byte_of(rhs)?),
+                    (_, Some(y)) => SeReg(x, y),
+                    (_, None) => SeByte(x, self.byte_of(rhs)?),
+                };
+                self.op(instruction)
+            }
+            "<" | ">" | "<=" | ">=" => {
+                let temp = self.aliases.get("compare-temp").cloned().unwrap_or(0xF);
+                let rhs = self.next()?;
+                match self.register_of(rhs) {
+                    Some(y) => self.op(LdReg(temp, y))?,
+                    None => {
+                        let byte = self.byte_of(rhs)?;
+                        self.op(LdByte(temp, byte))?;
+                    }
+                }
+                match comparison {
+                    ">" | "<=" => self.op(Sub(temp, x))?,
+                    _ => self.op(Subn(temp, x))?,
+                }
+                match comparison {
+                    ">" | "<" => self.op(SeByte(0xF, 1)),
+                    _ => self.op(SneByte(0xF, 1)),
+                }
+            }
+            other => Err(self.error(format!("unknown comparison {}", other))),
+        }
+    }
+
+    // An instruction taking an address: a number or constant now, or a label
+    // now or once it is defined.
+    fn addr(&mut self, instruction: Instruction) -> Result<(), AsmError> {
+        let name = self.next()?;
+        let op = instruction.encode();
+        match self.known(name) {
+            Some(addr) => {
+                let addr = self.fit(addr, 0, 0xFFF)? as u16;
+                self.word(op | addr)
+            }
+            None if self.register_of(name).is_some() || name.starts_with(':') => {
+                Err(self.error(format!("expected an address, not {}", name)))
+            }
+            None => {
+                self.fixup(name, Field::Addr);
+                self.word(op)
+            }
+        }
+    }
+
+    fn fixup(&mut self, name: &str, field: Field) {
+        let (at, line) = (self.here, self.line);
+        self.fixups.push(Fixup { at, name: name.to_string(), line, field });
+    }
+
+    fn jump_here(&mut self, at: usize) -> Result<(), AsmError> {
+        let target = self.fit(self.here as i64, 0, 0xFFF)? as u16;
+        self.patch(at, Instruction::Jp(target).encode())
+    }
+
+    fn next(&mut self) -> Result<&'a str, AsmError> {
+        let tokens = self.tokens;
+        match tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => Err(self.error("unexpected end of program".to_string())),
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        let tokens = self.tokens;
+        tokens.get(self.pos).map(|token| token.text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AsmError> {
+        match self.next()? {
+            token if token == text => Ok(()),
+            token => Err(self.error(format!("expected {}, not {}", text, token))),
+        }
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        self.register_of(token)
+            .ok_or_else(|| self.error(format!("expected a register, not {}", token)))
+    }
+
+    fn register_of(&self, token: &str) -> Option<u8> {
+        if let Some(&reg) = self.aliases.get(token) {
+            return Some(reg);
+        }
+        match token.strip_prefix('v').or_else(|| token.strip_prefix('V')) {
+            Some(digit) if digit.len() == 1 => u8::from_str_radix(digit, 16).ok(),
+            _ => None,
+        }
+    }
+
+    // A number, constant or label defined so far.
+    fn known(&self, token: &str) -> Option<i64> {
+        parse_number(token)
+            .or_else(|| self.constants.get(token).cloned())
+            .or_else(|| self.labels.get(token).map(|&addr| addr as i64))
+    }
+
+    fn value(&self, token: &str) -> Result<i64, AsmError> {
+        self.known(token)
+            .ok_or_else(|| self.error(format!("expected a number, not {}", token)))
+    }
+
+    fn byte(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        self.byte_of(token)
+    }
+
+    // Negative numbers are bytes too, in two's complement.
+    fn byte_of(&self, token: &str) -> Result<u8, AsmError> {
+        Ok(self.fit(self.value(token)?, -128, 0xFF)? as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        Ok(self.fit(self.value(token)?, 0, 0xF)? as u8)
+    }
+
+    fn fit(&self, value: i64, min: i64, max: i64) -> Result<i64, AsmError> {
+        if value >= min && value <= max {
+            Ok(value & 0xFFFF)
+        } else {
+            Err(self.error(format!("{} doesn't fit in 0x{:X}", value, max)))
+        }
+    }
+
+    fn op(&mut self, instruction: Instruction) -> Result<(), AsmError> {
+        self.word(instruction.encode())
+    }
+
+    fn word(&mut self, word: u16) -> Result<(), AsmError> {
+        self.emit((word >> 8) as u8)?;
+        self.emit(word as u8)
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), AsmError> {
+        let here = self.here;
+        self.write(here, byte)?;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, addr: usize, byte: u8) -> Result<(), AsmError> {
+        if addr > 0xFFFF {
+            return Err(self.error("program runs past 0xFFFF".to_string()));
+        }
+        let index = addr - ROM_START;
+        if index >= self.rom.len() {
+            self.rom.resize(index + 1, 0);
+        }
+        self.rom[index] = byte;
+        Ok(())
+    }
+
+    fn read(&self, addr: usize) -> u16 {
+        let index = addr - ROM_START;
+        (self.rom[index] as u16) << 8 | self.rom[index + 1] as u16
+    }
+
+    fn patch(&mut self, addr: usize, word: u16) -> Result<(), AsmError> {
+        self.write(addr, (word >> 8) as u8)?;
+        self.write(addr + 1, word as u8)
+    }
+
+    fn error(&self, message: String) -> AsmError {
+        error(self.line, message)
+    }
+}
+
+fn is_operator(token: &str) -> bool {
+    [":=", "+=", "-=", "=-", "|=", "&=", "^=", ">>=", "<<="].contains(&token)
+}
+
+// Decimal, `0x` hex or `0b` binary, any of them negative.
+fn parse_number(token: &str) -> Option<i64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn error(line: usize, message: String) -> AsmError {
+    AsmError { line, message }
+}
+
+#[test]
+fn test_statements() {
+    let source = "
+        : main              # main first, so no jump to it
+            clear
+            v0 := 5
+            va := v0
+            v1 += 1
+            v1 += v0
+            v2 -= 1
+            v2 -= v3
+            v2 =- v3
+            v4 |= v5  v4 &= v5  v4 ^= v5  v4 >>= v5  v4 <<= v5
+            v6 := random 0x1F
+            v7 := delay
+            v8 := key
+            delay := v9
+            buzzer := v9
+            i := sprite
+            i += v0
+            i := hex v0
+            bcd v1
+            save v2
+            load v3
+            sprite v0 v1 5
+            jump0 sprite
+            sub
+        : sub
+            return
+        : sprite
+            0xF0 0b1001 -1
+    ";
+    let expected: Vec<u16> = vec![
+        0x00E0, 0x6005, 0x8A00, 0x7101, 0x8104, 0x72FF, 0x8235, 0x8237, 0x8451, 0x8452, 0x8453, 0x8456, 0x845E,
+        0xC61F, 0xF707, 0xF80A, 0xF915, 0xF918, 0xA238, 0xF01E, 0xF029, 0xF133, 0xF255, 0xF365, 0xD015, 0xB238,
+        0x2236, 0x00EE,
+    ];
+    let mut bytes: Vec<u8> = expected.iter().flat_map(|op| op.to_be_bytes().to_vec()).collect();
+    bytes.extend_from_slice(&[0xF0, 0x09, 0xFF]);
+    assert_eq!(assemble(source), Ok(bytes));
+}
+
+#[test]
+fn test_jump_to_main_and_directives() {
+    let source = "
+        :const SPEED 3
+        :alias x v4
+        : data 1 2
+        : main
+            x := SPEED
+            :unpack 0xA later
+            i := long later
+            save v1 - v2
+            plane 3
+            hires
+        :org 0x300
+        : later
+    ";
+    let bytes = assemble(source).unwrap();
+    // `:org` moves on without writing anything, so the ROM ends before it.
+    assert_eq!(
+        bytes,
+        vec![
+            0x12, 0x04, 0x01, 0x02, 0x64, 0x03, 0x60, 0xA3, 0x61, 0x00, 0xF0, 0x00, 0x03, 0x00, 0x51, 0x22, 0xF3,
+            0x01, 0x00, 0xFF
+        ]
+    );
+}
+
+// Runs the program `source` assembles to for a thousand instructions, for
+// its registers.
+#[cfg(test)]
+fn run(source: &str) -> Vec<u8> {
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+
+    use cpu::CPU;
+    use display::Display;
+    use keyboard::Keyboard;
+    use ram::RAM;
+
+    let (_, receiver) = channel();
+    let mut display = Display::init();
+    let mut keyboard = Arc::new(Mutex::new(Keyboard::init(receiver)));
+    let mut ram = RAM::init();
+    let mut cpu = CPU::init(&mut ram, &mut display, &mut keyboard, None);
+    cpu.load_rom(&assemble(source).unwrap());
+    for _ in 0..1000 {
+        cpu.run_cycle();
+    }
+    (0..16).map(|x| cpu.get_reg(x)).collect()
+}
+
+#[test]
+fn test_control_flow() {
+    // Counts V0 up to 10, with V1 the sum of the odd numbers and V2 of the
+    // even ones, V3 how many were 7 or more, and V4 how many less than 3.
+    let registers = run("
+        : main
+            loop
+                v0 += 1
+                v5 := v0
+                v6 := 1
+                v5 &= v6
+                if v5 == 1 begin
+                    v1 += v0
+                else
+                    v2 += v0
+                end
+                if v0 >= 7 then v3 += 1
+                if v0 < 3 then v4 += 1
+                if v0 > 10 then v7 := 1
+                if v0 <= 0 then v7 := 1
+                if v0 != 10 then
+            again
+        : halt
+            jump halt
+    ");
+    assert_eq!(registers[0], 10);
+    assert_eq!(registers[1], 1 + 3 + 5 + 7 + 9);
+    assert_eq!(registers[2], 2 + 4 + 6 + 8 + 10);
+    assert_eq!(registers[3], 4);
+    assert_eq!(registers[4], 2);
+    assert_eq!(registers[7], 0);
+
+    let registers = run("
+        : main
+            loop
+                v0 += 1
+                while v0 != 5
+                if v0 key then v1 := 1
+                v2 += 2
+            again
+        : halt
+            jump halt
+    ");
+    assert_eq!(&registers[..3], &[5, 0, 8]);
+}
+
+#[test]
+fn test_compare_temp() {
+    // The subtraction goes into the alias, but the borrow is still in VF.
+    let source = "
+        :alias compare-temp v0
+        : main
+            v1 := 5
+            if v1 > 3 then v2 := 1
+            if v1 < 3 then v3 := 1
+            if v1 >= 5 then v4 := 1
+            if v1 <= 4 then v5 := 1
+        : halt
+            jump halt
+    ";
+    let bytes = assemble(source).unwrap();
+    assert_eq!(&bytes[4..12], &[0x60, 0x03, 0x80, 0x15, 0x3F, 0x01, 0x62, 0x01]);
+    assert_eq!(&run(source)[2..6], &[1, 0, 1, 0]);
+}
+
+#[test]
+fn test_errors() {
+    let message = |source: &str| assemble(source).unwrap_err().message;
+    assert_eq!(message(": start clear"), "no main label");
+    assert_eq!(message(": main jump nowhere"), "unknown label \"nowhere\"");
+    assert_eq!(message(": main v0 := 256"), "256 doesn't fit in 0xFF");
+    assert_eq!(message(": main if v0 == 1 begin clear"), "begin without end");
+    assert_eq!(message(": main loop clear"), "loop without again");
+    assert_eq!(message(": main end"), "end without begin");
+    assert_eq!(message(": main vg := 1"), "vg isn't a register");
+    assert_eq!(message(": main :macro foo { }"), ":macro isn't supported");
+    assert_eq!(assemble(": main\n\n  sprite v0 v1").unwrap_err().line, 3);
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(PartialEq, Debug)]
 pub struct Opcode(u16);
 
@@ -13,9 +15,11 @@ impl Opcode {
     pub fn data(&self) -> u16 {
         self.0 & 0x0FFF
     }
+}
 
-    pub fn to_string(&self) -> String {
-        format!("0x{:X}", self.0)
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:X}", self.0)
     }
 }
 
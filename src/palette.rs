@@ -0,0 +1,175 @@
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b }
+    }
+
+    fn from_u32(rgb: u32) -> Color {
+        Color::rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+    }
+
+    // Accepts `RRGGBB` or `#RRGGBB`.
+    pub fn from_hex(hex: &str) -> Option<Color> {
+        let hex = hex.trim_start_matches('#');
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        Some(Color::rgb(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    pub fn to_hex(&self) -> String {
+        format!("#{:02X}{:02X}{:02X}", self.r, self.g, self.b)
+    }
+
+    // Linear interpolation towards `other`; `t` of 255 gives `other`.
+    pub fn mix(&self, other: &Color, t: u8) -> Color {
+        let lerp = |a: u8, b: u8| ((a as u32 * (255 - t as u32) + b as u32 * t as u32 + 127) / 255) as u8;
+        Color::rgb(lerp(self.r, other.r), lerp(self.g, other.g), lerp(self.b, other.b))
+    }
+
+    // Sixel color registers are specified in percent rather than 0-255.
+    pub fn to_percent(&self) -> (u8, u8, u8) {
+        let pct = |c: u8| ((c as u16 * 100 + 127) / 255) as u8;
+        (pct(self.r), pct(self.g), pct(self.b))
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Palette {
+    colors: Vec<Color>,
+}
+
+impl Palette {
+    pub fn new(background: Color, foreground: Color) -> Palette {
+        Palette {
+            colors: vec![background, foreground],
+        }
+    }
+
+    pub fn from_colors(colors: Vec<Color>) -> Palette {
+        assert!(colors.len() >= 2, "a palette needs at least two colors");
+        Palette { colors }
+    }
+
+    pub fn named(name: &str) -> Option<Palette> {
+        let (bg, fg) = match name {
+            "mono" => (0x000000, 0xFFFFFF),
+            "inverted" => (0xFFFFFF, 0x000000),
+            "amber" => (0x1A0F00, 0xFFB000),
+            "green" => (0x001A00, 0x33FF33),
+            "octo" => (0x996600, 0xFFCC00),
+            _ => return None,
+        };
+        Some(Palette::new(Color::from_u32(bg), Color::from_u32(fg)))
+    }
+
+    // A palette name, or comma separated hex colors starting with the
+    // background, e.g. `#000000,#FFCC00`.
+    pub fn parse(spec: &str) -> Option<Palette> {
+        if let Some(palette) = Palette::named(spec) {
+            return Some(palette);
+        }
+        let colors: Vec<Color> = spec
+            .split(',')
+            .map(|hex| Color::from_hex(hex.trim()))
+            .collect::<Option<_>>()?;
+        if colors.len() >= 2 {
+            Some(Palette::from_colors(colors))
+        } else {
+            None
+        }
+    }
+
+    // The colors in the form `parse` reads back.
+    pub fn to_spec(&self) -> String {
+        let hexes: Vec<String> = self.colors.iter().map(|color| color.to_hex()).collect();
+        hexes.join(",")
+    }
+
+    pub fn names() -> &'static [&'static str] {
+        &["mono", "inverted", "amber", "green", "octo"]
+    }
+
+    pub fn background(&self) -> Color {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> Color {
+        self.colors[1]
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    // Out-of-range indices fall back to the foreground, so a renderer never
+    // has to care how many colors a palette was built with.
+    pub fn color(&self, index: usize) -> Color {
+        *self.colors.get(index).unwrap_or(&self.colors[1])
+    }
+
+    pub fn colors(&self) -> &[Color] {
+        &self.colors
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::named("mono").unwrap()
+    }
+}
+
+#[test]
+fn test_from_hex() {
+    assert_eq!(Color::from_hex("#FFCC00"), Some(Color::rgb(0xFF, 0xCC, 0x00)));
+    assert_eq!(Color::from_hex("996600"), Some(Color::rgb(0x99, 0x66, 0x00)));
+    assert_eq!(Color::from_hex("#FFF"), None);
+    assert_eq!(Color::from_hex("#GG0000"), None);
+}
+
+#[test]
+fn test_to_percent() {
+    assert_eq!(Color::rgb(0, 0, 0).to_percent(), (0, 0, 0));
+    assert_eq!(Color::rgb(255, 255, 255).to_percent(), (100, 100, 100));
+    assert_eq!(Color::rgb(0x80, 0, 0).to_percent(), (50, 0, 0));
+}
+
+#[test]
+fn test_mix() {
+    let black = Color::rgb(0, 0, 0);
+    let white = Color::rgb(255, 255, 255);
+    assert_eq!(black.mix(&white, 0), black);
+    assert_eq!(black.mix(&white, 255), white);
+    assert_eq!(black.mix(&white, 128), Color::rgb(128, 128, 128));
+}
+
+#[test]
+fn test_named_palettes() {
+    for name in Palette::names() {
+        assert!(Palette::named(name).is_some());
+    }
+    assert_eq!(Palette::named("nope"), None);
+    assert_eq!(Palette::default().foreground(), Color::rgb(255, 255, 255));
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(Palette::parse("amber"), Palette::named("amber"));
+    let palette = Palette::parse("#000000, #FF0000,#00FF00").unwrap();
+    assert_eq!(palette.len(), 3);
+    assert_eq!(palette.foreground(), Color::rgb(255, 0, 0));
+    assert_eq!(Palette::parse("#000000"), None);
+    assert_eq!(Palette::parse("nope"), None);
+    assert_eq!(Palette::parse(&palette.to_spec()), Some(palette));
+}
@@ -0,0 +1,265 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use displayimpl::DisplayImpl;
+use framebuffer::{Frame, Framebuffer};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PhosphorMode {
+    // Average of the last N frames.
+    Blend(usize),
+    // Pixels fade out over the given number of frames (1/60s each) after
+    // they were last lit.
+    Decay(u32),
+    // A pixel is lit if it was lit in either of the last two frames. Keeps
+    // output binary, which suits character renderers.
+    OrLastTwo,
+}
+
+impl PhosphorMode {
+    // `blend:N`, `decay:N` or `last-two`, as `to_spec` writes them.
+    pub fn parse(spec: &str) -> Option<PhosphorMode> {
+        if spec == "last-two" {
+            return Some(PhosphorMode::OrLastTwo);
+        }
+        let colon = spec.find(':')?;
+        let n = spec[colon + 1..].parse().ok().filter(|&n| n > 0)?;
+        match &spec[..colon] {
+            "blend" => Some(PhosphorMode::Blend(n as usize)),
+            "decay" => Some(PhosphorMode::Decay(n)),
+            _ => None,
+        }
+    }
+
+    pub fn to_spec(&self) -> String {
+        match *self {
+            PhosphorMode::Blend(n) => format!("blend:{}", n),
+            PhosphorMode::Decay(frames) => format!("decay:{}", frames),
+            PhosphorMode::OrLastTwo => "last-two".to_string(),
+        }
+    }
+}
+
+// Per-pixel intensities, 0 for off through 255 for fully lit.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Shades {
+    width: usize,
+    height: usize,
+    values: Vec<u8>,
+}
+
+impl Shades {
+    fn new(width: usize, height: usize) -> Shades {
+        Shades {
+            width,
+            height,
+            values: vec![0; width * height],
+        }
+    }
+
+    // Lit pixels of any color are fully bright.
+    pub fn from_framebuffer<F: Framebuffer + ?Sized>(screen: &F) -> Shades {
+        let mut shades = Shades::new(screen.width(), screen.height());
+        for y in 0..screen.height() {
+            for x in 0..screen.width() {
+                if screen.pixel(x, y) {
+                    shades.values[y * shades.width + x] = 255;
+                }
+            }
+        }
+        shades
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> u8 {
+        self.values[row * self.width + col]
+    }
+
+    // Pixels at or above `threshold` are lit, for renderers without shades.
+    pub fn to_frame(&self, threshold: u8) -> Frame {
+        let mut frame = Frame::new(self.width, self.height, 1);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let shade = self.get(y, x);
+                if shade > 0 && shade >= threshold {
+                    frame.set_color_index(x, y, 1);
+                }
+            }
+        }
+        frame
+    }
+}
+
+pub struct Phosphor {
+    mode: PhosphorMode,
+    history: VecDeque<Frame>,
+    ages: Vec<u32>,
+}
+
+impl Phosphor {
+    pub fn new(mode: PhosphorMode) -> Phosphor {
+        Phosphor {
+            mode,
+            history: VecDeque::new(),
+            ages: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> PhosphorMode {
+        self.mode
+    }
+
+    // Feeds the next frame through the filter and returns what to show.
+    // Changing resolution starts the filter over.
+    pub fn push<F: Framebuffer + ?Sized>(&mut self, frame: &F) -> Shades {
+        let frame = Frame::capture(frame);
+        if self.ages.len() != frame.width() * frame.height() {
+            self.history.clear();
+            self.ages = vec![u32::MAX; frame.width() * frame.height()];
+        }
+        match self.mode {
+            PhosphorMode::Blend(n) => self.blend(frame, n.max(1)),
+            PhosphorMode::Decay(frames) => self.decay(&frame, frames.max(1)),
+            PhosphorMode::OrLastTwo => Shades::from_framebuffer(&self.blend(frame, 2).to_frame(1)),
+        }
+    }
+
+    fn blend(&mut self, frame: Frame, n: usize) -> Shades {
+        let (width, height) = (frame.width(), frame.height());
+        self.history.push_back(frame);
+        while self.history.len() > n {
+            self.history.pop_front();
+        }
+        let mut shades = Shades::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let count = self.history.iter().filter(|past| past.pixel(x, y)).count();
+                shades.values[y * width + x] = (count * 255 / n) as u8;
+            }
+        }
+        shades
+    }
+
+    fn decay(&mut self, frame: &Frame, frames: u32) -> Shades {
+        let mut shades = Shades::new(frame.width(), frame.height());
+        for y in 0..frame.height() {
+            for x in 0..frame.width() {
+                let i = y * frame.width() + x;
+                let age = &mut self.ages[i];
+                if frame.pixel(x, y) {
+                    *age = 0;
+                } else {
+                    *age = age.saturating_add(1);
+                }
+                if *age < frames {
+                    shades.values[i] = (255 - *age as u64 * 255 / frames as u64) as u8;
+                }
+            }
+        }
+        shades
+    }
+}
+
+// Runs every frame through a `Phosphor` filter before handing it to the
+// wrapped renderer.
+pub struct PhosphorDisplay<D: DisplayImpl> {
+    inner: D,
+    filter: RefCell<Phosphor>,
+}
+
+impl<D: DisplayImpl> PhosphorDisplay<D> {
+    pub fn new(inner: D, mode: PhosphorMode) -> PhosphorDisplay<D> {
+        PhosphorDisplay {
+            inner,
+            filter: RefCell::new(Phosphor::new(mode)),
+        }
+    }
+}
+
+impl<D: DisplayImpl> DisplayImpl for PhosphorDisplay<D> {
+    fn draw(&self, screen: &dyn Framebuffer, keys: &[bool; 16]) {
+        let shades = self.filter.borrow_mut().push(screen);
+        self.inner.draw_shades(&shades, keys);
+    }
+}
+
+#[cfg(test)]
+use display::Display;
+
+#[test]
+fn test_parse_modes() {
+    for spec in ["blend:3", "decay:8", "last-two"].iter() {
+        assert_eq!(PhosphorMode::parse(spec).unwrap().to_spec(), *spec);
+    }
+    assert_eq!(PhosphorMode::parse("decay:8"), Some(PhosphorMode::Decay(8)));
+    assert!(PhosphorMode::parse("blend:0").is_none());
+    assert!(PhosphorMode::parse("blend").is_none());
+    assert!(PhosphorMode::parse("glow:2").is_none());
+}
+
+#[test]
+fn test_or_last_two() {
+    let mut phosphor = Phosphor::new(PhosphorMode::OrLastTwo);
+    let mut display = Display::init();
+    display.set_sprite(0, 0, &[0x80]);
+    assert_eq!(phosphor.push(&display).get(0, 0), 255);
+    display.clear();
+    assert_eq!(phosphor.push(&display).get(0, 0), 255);
+    assert_eq!(phosphor.push(&display).get(0, 0), 0);
+}
+
+#[test]
+fn test_blend() {
+    let mut phosphor = Phosphor::new(PhosphorMode::Blend(4));
+    let mut display = Display::init();
+    display.set_sprite(0, 0, &[0x80]);
+    phosphor.push(&display);
+    phosphor.push(&display);
+    display.clear();
+    let shades = phosphor.push(&display);
+    assert_eq!(shades.get(0, 0), 127);
+    assert_eq!(shades.get(0, 1), 0);
+}
+
+#[test]
+fn test_decay() {
+    let mut phosphor = Phosphor::new(PhosphorMode::Decay(4));
+    let mut display = Display::init();
+    display.set_sprite(0, 0, &[0x80]);
+    assert_eq!(phosphor.push(&display).get(0, 0), 255);
+    display.clear();
+    let fades: Vec<u8> = (0..4).map(|_| phosphor.push(&display).get(0, 0)).collect();
+    assert_eq!(fades, vec![192, 128, 64, 0]);
+}
+
+#[test]
+fn test_shades_threshold() {
+    let mut display = Display::init();
+    display.set_sprite(0, 0, &[0xC0]);
+    let mut shades = Shades::from_framebuffer(&display);
+    shades.values[1] = 100;
+    assert!(shades.to_frame(128).pixel(0, 0));
+    assert!(!shades.to_frame(128).pixel(1, 0));
+    assert!(shades.to_frame(1).pixel(1, 0));
+}
+
+#[test]
+fn test_resolution_change_resets() {
+    let mut phosphor = Phosphor::new(PhosphorMode::Decay(4));
+    let mut display = Display::init();
+    display.set_sprite(0, 0, &[0x80]);
+    phosphor.push(&display);
+    let mut large = Frame::new(128, 64, 1);
+    large.set_color_index(127, 63, 1);
+    let shades = phosphor.push(&large);
+    assert_eq!((shades.width(), shades.height()), (128, 64));
+    assert_eq!(shades.get(0, 0), 0);
+    assert_eq!(shades.get(63, 127), 255);
+}
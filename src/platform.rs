@@ -0,0 +1,72 @@
+use std::fmt;
+
+// The CHIP-8 variants a ROM can be written for.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn all() -> &'static [Platform] {
+        &[Platform::Chip8, Platform::SuperChip, Platform::XoChip]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Platform> {
+        match name.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Some(Platform::Chip8),
+            "schip" | "superchip" | "super-chip" => Some(Platform::SuperChip),
+            "xochip" | "xo-chip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    // The usual file extension for ROMs of this platform.
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            Platform::Chip8 => "ch8",
+            Platform::SuperChip => "sc8",
+            Platform::XoChip => "xo8",
+        }
+    }
+
+    pub fn from_extension(ext: &str) -> Option<Platform> {
+        Platform::all()
+            .iter()
+            .cloned()
+            .find(|platform| ext.eq_ignore_ascii_case(platform.extension()))
+    }
+
+    pub fn memory_size(&self) -> usize {
+        match *self {
+            Platform::Chip8 | Platform::SuperChip => 0x1000,
+            Platform::XoChip => 0x10000,
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[test]
+fn test_names_round_trip() {
+    for &platform in Platform::all() {
+        assert_eq!(Platform::from_name(platform.name()), Some(platform));
+        assert_eq!(Platform::from_extension(platform.extension()), Some(platform));
+    }
+    assert_eq!(Platform::from_name("XO-CHIP"), Some(Platform::XoChip));
+    assert_eq!(Platform::from_extension("CH8"), Some(Platform::Chip8));
+    assert_eq!(Platform::from_name("gameboy"), None);
+}
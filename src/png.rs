@@ -0,0 +1,103 @@
+use palette::Color;
+
+// A minimal PNG writer for indexed images. Image data is stored with
+// uncompressed deflate blocks, which keeps the encoder dependency-free at the
+// cost of file size; CHIP-8 screenshots are small either way.
+pub fn encode_indexed(width: usize, height: usize, colors: &[Color], pixels: &[u8]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height);
+    assert!(!colors.is_empty() && colors.len() <= 256);
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"\x89PNG\r\n\x1a\n");
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth 8, color type 3 (indexed), default compression/filter/interlace.
+    ihdr.extend_from_slice(&[8, 3, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &ihdr);
+
+    let plte: Vec<u8> = colors.iter().flat_map(|c| vec![c.r, c.g, c.b]).collect();
+    write_chunk(&mut out, b"PLTE", &plte);
+
+    let mut raw = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(if last { 1 } else { 0 });
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn test_adler32() {
+    assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+}
+
+#[test]
+fn test_encode_layout() {
+    let colors = [Color::rgb(0, 0, 0), Color::rgb(255, 255, 255)];
+    let png = encode_indexed(2, 1, &colors, &[0, 1]);
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[16..24], &[0, 0, 0, 2, 0, 0, 0, 1]);
+    assert_eq!(&png[png.len() - 12..], b"\0\0\0\0IEND\xAE\x42\x60\x82");
+}
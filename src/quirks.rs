@@ -0,0 +1,219 @@
+use std::fmt;
+
+use json::Json;
+use platform::Platform;
+
+// Behaviours that differ between CHIP-8 interpreters. The names follow the
+// community CHIP-8 database, so presets and per-ROM overrides can be read
+// straight from it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VX in place instead of copying VY.
+    pub shift: bool,
+    // FX55/FX65 leave I at I + X instead of I + X + 1.
+    pub memory_increment_by_x: bool,
+    // FX55/FX65 don't change I at all.
+    pub memory_leave_i_unchanged: bool,
+    // Sprites wrap around the screen edges instead of being clipped.
+    pub wrap: bool,
+    // BNNN jumps to XNN + VX instead of NNN + V0.
+    pub jump: bool,
+    // Drawing waits for the next frame.
+    pub vblank: bool,
+    // 8XY1/8XY2/8XY3 reset VF.
+    pub logic: bool,
+}
+
+impl Quirks {
+    // Looks up a preset by its community database platform id.
+    pub fn preset(id: &str) -> Option<Quirks> {
+        let original = Quirks {
+            shift: false,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: false,
+            wrap: false,
+            jump: false,
+            vblank: true,
+            logic: true,
+        };
+        let superchip = Quirks {
+            shift: true,
+            memory_leave_i_unchanged: true,
+            jump: true,
+            vblank: false,
+            logic: false,
+            ..original
+        };
+        match id {
+            "originalChip8" | "hybridVIP" => Some(original),
+            "modernChip8" => Some(Quirks {
+                vblank: false,
+                logic: false,
+                ..original
+            }),
+            "chip48" => Some(Quirks {
+                memory_increment_by_x: true,
+                memory_leave_i_unchanged: false,
+                ..superchip
+            }),
+            "superchip1" | "superchip" | "megachip8" => Some(superchip),
+            "xochip" => Some(Quirks {
+                wrap: true,
+                vblank: false,
+                logic: false,
+                ..original
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn preset_ids() -> &'static [&'static str] {
+        &[
+            "originalChip8",
+            "hybridVIP",
+            "modernChip8",
+            "chip48",
+            "superchip1",
+            "superchip",
+            "megachip8",
+            "xochip",
+        ]
+    }
+
+    // The platform whose instruction set a preset runs.
+    pub fn preset_platform(id: &str) -> Option<Platform> {
+        match id {
+            "originalChip8" | "hybridVIP" | "modernChip8" => Some(Platform::Chip8),
+            "chip48" | "superchip1" | "superchip" | "megachip8" => Some(Platform::SuperChip),
+            "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn for_platform(platform: Platform) -> Quirks {
+        let id = match platform {
+            Platform::Chip8 => "originalChip8",
+            Platform::SuperChip => "superchip",
+            Platform::XoChip => "xochip",
+        };
+        Quirks::preset(id).unwrap()
+    }
+
+    // Parses a comma separated list: optionally a preset id first, then
+    // quirk names to turn on, or to turn off when prefixed with `no-`, e.g.
+    // `superchip,no-jump,wrap`. Starts from the defaults if there's no preset.
+    pub fn parse(spec: &str) -> Option<Quirks> {
+        let mut parts = spec.split(',').map(|part| part.trim()).filter(|part| !part.is_empty()).peekable();
+        let mut quirks = match parts.peek().and_then(|id| Quirks::preset(id)) {
+            Some(preset) => {
+                parts.next();
+                preset
+            }
+            None => Quirks::default(),
+        };
+        for part in parts {
+            let (name, value) = match part.strip_prefix("no-") {
+                Some(name) => (name, false),
+                None => (part, true),
+            };
+            *quirks.flag_mut(name)? = value;
+        }
+        Some(quirks)
+    }
+
+    fn flags(&self) -> [(&'static str, bool); 7] {
+        [
+            ("shift", self.shift),
+            ("memoryIncrementByX", self.memory_increment_by_x),
+            ("memoryLeaveIUnchanged", self.memory_leave_i_unchanged),
+            ("wrap", self.wrap),
+            ("jump", self.jump),
+            ("vblank", self.vblank),
+            ("logic", self.logic),
+        ]
+    }
+
+    fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "shift" => Some(&mut self.shift),
+            "memoryIncrementByX" => Some(&mut self.memory_increment_by_x),
+            "memoryLeaveIUnchanged" => Some(&mut self.memory_leave_i_unchanged),
+            "wrap" => Some(&mut self.wrap),
+            "jump" => Some(&mut self.jump),
+            "vblank" => Some(&mut self.vblank),
+            "logic" => Some(&mut self.logic),
+            _ => None,
+        }
+    }
+
+    // Copies any quirks set in `json`, an object with the database's quirk
+    // names as keys. Unknown keys are ignored.
+    pub fn apply_json(&mut self, json: &Json) {
+        let members = match json.as_object() {
+            Some(members) => members,
+            None => return,
+        };
+        for (key, value) in members {
+            let value = match value.as_bool() {
+                Some(value) => value,
+                None => continue,
+            };
+            if let Some(flag) = self.flag_mut(key) {
+                *flag = value;
+            }
+        }
+    }
+}
+
+// Every quirk, in the form `parse` reads back.
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags: Vec<String> = self
+            .flags()
+            .iter()
+            .map(|&(name, on)| if on { name.to_string() } else { format!("no-{}", name) })
+            .collect();
+        write!(f, "{}", flags.join(","))
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::for_platform(Platform::Chip8)
+    }
+}
+
+#[test]
+fn test_presets() {
+    for id in Quirks::preset_ids() {
+        assert!(Quirks::preset(id).is_some(), "{}", id);
+        assert!(Quirks::preset_platform(id).is_some(), "{}", id);
+    }
+    assert!(Quirks::preset("gameboy").is_none());
+    assert!(Quirks::for_platform(Platform::SuperChip).shift);
+    assert!(Quirks::for_platform(Platform::XoChip).wrap);
+    assert!(Quirks::default().logic);
+}
+
+#[test]
+fn test_apply_json() {
+    let mut quirks = Quirks::default();
+    quirks.apply_json(&Json::parse(r#"{"shift": true, "logic": false, "other": true, "jump": 1}"#).unwrap());
+    assert!(quirks.shift);
+    assert!(!quirks.logic);
+    assert!(!quirks.jump);
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(Quirks::parse("superchip"), Quirks::preset("superchip"));
+    let quirks = Quirks::parse("superchip, no-jump,wrap").unwrap();
+    assert!(!quirks.jump && quirks.wrap && quirks.shift);
+    assert_eq!(Quirks::parse("shift").unwrap(), Quirks { shift: true, ..Quirks::default() });
+    assert_eq!(Quirks::parse(""), Some(Quirks::default()));
+    assert_eq!(Quirks::parse("superchip,bogus"), None);
+    for id in Quirks::preset_ids() {
+        let quirks = Quirks::preset(id).unwrap();
+        assert_eq!(Quirks::parse(&quirks.to_string()), Some(quirks));
+    }
+}
@@ -1,11 +1,26 @@
-pub struct RAM([u8; 4000]);
+// Programs are loaded here; everything below is the interpreter's.
+pub const ROM_START: usize = 0x200;
+
+pub struct RAM(Vec<u8>);
 
 impl RAM {
     pub fn init() -> RAM {
-        RAM([0; 4000])
+        RAM::with_size(4096)
+    }
+
+    pub fn with_size(size: usize) -> RAM {
+        RAM(vec![0; size])
+    }
+
+    pub fn size(&self) -> usize {
+        self.0.len()
     }
 
-    #[cfg_attr(rustfmt, rustfmt_skip)]
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    #[rustfmt::skip]
     pub fn load_fontset(&mut self) {
         let fontset: [u8; 80] = [
             0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -25,45 +40,58 @@ impl RAM {
             0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
             0xF0, 0x80, 0xF0, 0x80, 0x80  // F
         ];
-        for (i, &f) in fontset.into_iter().enumerate() {
+        for (i, &f) in fontset.iter().enumerate() {
             self.0[i] = f;
         }
     }
 
+    // Addresses wrap around the end of memory, so no address is out of
+    // range.
+    fn wrap(&self, pos: usize) -> usize {
+        if pos < self.0.len() {
+            pos
+        } else {
+            pos % self.0.len()
+        }
+    }
+
     pub fn set_mem8(&mut self, pos: usize, val: u8) {
+        let pos = self.wrap(pos);
         self.0[pos] = val;
     }
 
     pub fn set_mem16(&mut self, pos: usize, val: u16) {
-        self.0[pos] = (val >> 8) as u8;
-        self.0[pos + 1] = (val & 0xFF) as u8;
+        self.set_mem8(pos, (val >> 8) as u8);
+        self.set_mem8(pos + 1, (val & 0xFF) as u8);
     }
 
     pub fn get_mem8(&self, pos: usize) -> u8 {
-        self.0[pos]
+        self.0[self.wrap(pos)]
     }
 
     pub fn get_mem16(&self, pos: usize) -> u16 {
-        let a = (self.0[pos] as u16) << 8;
-        let b = self.0[pos + 1] as u16;
+        let a = (self.get_mem8(pos) as u16) << 8;
+        let b = self.get_mem8(pos + 1) as u16;
         a | b
     }
 
     pub fn set_regs(&mut self, pos: usize, regs: &[u8; 16], to_reg: u8) {
-        for (i, &data) in regs.into_iter().take(1 + to_reg as usize).enumerate() {
-            self.0[pos + i] = data;
+        for (i, &data) in regs.iter().take(1 + to_reg as usize).enumerate() {
+            self.set_mem8(pos + i, data);
         }
     }
 
     pub fn get_regs(&self, pos: usize, regs: &mut [u8; 16], to_reg: u8) {
-        for (i, reg) in regs.into_iter().take(1 + to_reg as usize).enumerate() {
-            *reg = self.0[pos + i];
+        for (i, reg) in regs.iter_mut().take(1 + to_reg as usize).enumerate() {
+            *reg = self.get_mem8(pos + i);
         }
     }
 
+    // Whatever doesn't fit after ROM_START is left out.
     pub fn load_rom(&mut self, rom: &[u8]) {
-        for (i, &data) in rom.into_iter().enumerate() {
-            self.set_mem8(0x200 + i, data);
+        let room = self.0.len().saturating_sub(ROM_START);
+        for (i, &data) in rom.iter().take(room).enumerate() {
+            self.set_mem8(ROM_START + i, data);
         }
     }
 }
@@ -73,6 +101,15 @@ fn test_empty() {
     assert_eq!(RAM::init().get_mem8(0), 0);
     assert_eq!(RAM::init().get_mem8(2000), 0);
     assert_eq!(RAM::init().get_mem8(3999), 0);
+    assert_eq!(RAM::init().get_mem8(4095), 0);
+}
+
+#[test]
+fn test_with_size() {
+    let mut mem = RAM::with_size(0x10000);
+    assert_eq!(mem.size(), 0x10000);
+    mem.set_mem16(0xFFFE, 0x1234);
+    assert_eq!(mem.get_mem16(0xFFFE), 0x1234);
 }
 
 #[test]
@@ -149,3 +186,15 @@ fn test_get_regs() {
         assert_eq!(reg, 0x00);
     }
 }
+
+#[test]
+fn test_addresses_wrap() {
+    let mut mem = RAM::init();
+    mem.set_mem16(0xFFF, 0x1234);
+    assert_eq!((mem.get_mem8(0xFFF), mem.get_mem8(0x000)), (0x12, 0x34));
+    assert_eq!(mem.get_mem16(0x1FFF), 0x1234);
+    mem.set_regs(0xFFE, &[7; 16], 3);
+    assert_eq!(mem.get_mem16(0x000), 0x0707);
+    mem.load_rom(&[1; 0x1000]);
+    assert_eq!((mem.get_mem8(0xFFF), mem.get_mem8(0x001)), (1, 7));
+}
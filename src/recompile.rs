@@ -0,0 +1,287 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use blocks::ends_block;
+use instruction::Instruction;
+use ram::ROM_START;
+
+// The code reachable from the start of a ROM, split into basic blocks: runs
+// of instructions entered only at the top, keyed by address. Jumps, calls,
+// returns and skips are followed; computed jumps (BNNN) and anything that
+// doesn't decode as an instruction end the search.
+pub fn recover(rom: &[u8]) -> BTreeMap<u16, Vec<u16>> {
+    let end = ROM_START + rom.len();
+    let fetch = |addr: usize| {
+        if addr >= ROM_START && addr + 1 < end {
+            let op = (rom[addr - ROM_START] as u16) << 8 | rom[addr + 1 - ROM_START] as u16;
+            match Instruction::decode(op) {
+                Instruction::Data(_) => None,
+                _ => Some(op),
+            }
+        } else {
+            None
+        }
+    };
+
+    let mut code = BTreeSet::new();
+    let mut leaders = BTreeSet::new();
+    let mut pending = vec![ROM_START];
+    leaders.insert(ROM_START);
+    while let Some(mut addr) = pending.pop() {
+        while let Some(op) = fetch(addr) {
+            if !code.insert(addr) {
+                break;
+            }
+            let next = addr + 2;
+            let targets: &[usize] = match Instruction::decode(op) {
+                Instruction::Jp(target) => &[target as usize],
+                Instruction::Call(target) => &[target as usize, next],
+                Instruction::SeByte(..)
+                | Instruction::SneByte(..)
+                | Instruction::SeReg(..)
+                | Instruction::SneReg(..)
+                | Instruction::Skp(..)
+                | Instruction::Sknp(..) => &[next, next + 2],
+                Instruction::Ret | Instruction::JpV0(_) => &[],
+                _ if ends_block(op) => &[next],
+                _ => {
+                    addr = next;
+                    continue;
+                }
+            };
+            for &target in targets {
+                leaders.insert(target);
+                pending.push(target);
+            }
+            break;
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+    for &start in leaders.iter().filter(|start| code.contains(start)) {
+        let mut block = Vec::new();
+        let mut addr = start;
+        loop {
+            let op = fetch(addr).unwrap();
+            block.push(op);
+            addr += 2;
+            if ends_block(op) || !code.contains(&addr) || leaders.contains(&addr) {
+                break;
+            }
+        }
+        blocks.insert(start as u16, block);
+    }
+    blocks
+}
+
+// Rust source for a module that runs `rom` on a `CPU`: a loop dispatching on
+// PC to the ROM's blocks, each a straight run of its instructions, decoded
+// here and handed to `CPU::run`, with the interpreter left to run anything
+// else.
+// Once the ROM writes over any of its recovered code, it is all left to the
+// interpreter. `name` only goes in a comment.
+pub fn recompile(rom: &[u8], name: &str) -> String {
+    let blocks = recover(rom);
+    let mut out = String::new();
+    let _ = write_module(&mut out, rom, name, &blocks);
+    out
+}
+
+fn write_module(out: &mut String, rom: &[u8], name: &str, blocks: &BTreeMap<u16, Vec<u16>>) -> ::std::fmt::Result {
+    writeln!(out, "// Recompiled from {} by `rust8 recompile`. `run` leaves a CPU with", name)?;
+    writeln!(out, "// ROM loaded exactly as the interpreter would, and hands anything not")?;
+    writeln!(out, "// recovered here (computed jumps, code the ROM rewrites) to it.")?;
+    writeln!(out)?;
+    writeln!(out, "use rust8::cpu::CPU;")?;
+    writeln!(out, "use rust8::instruction::Instruction::*;")?;
+    writeln!(out, "use rust8::ram::ROM_START;")?;
+    writeln!(out)?;
+    writeln!(out, "#[rustfmt::skip]")?;
+    writeln!(out, "pub const ROM: [u8; {}] = [", rom.len())?;
+    for line in rom.chunks(12) {
+        let bytes: Vec<String> = line.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+        writeln!(out, "    {},", bytes.join(", "))?;
+    }
+    writeln!(out, "];")?;
+    writeln!(out)?;
+
+    writeln!(out, "// The recovered code, as start and end addresses.")?;
+    writeln!(out, "const CODE: [(usize, usize); {}] = [", code_ranges(blocks).len())?;
+    for (start, end) in code_ranges(blocks) {
+        writeln!(out, "    (0x{:03X}, 0x{:03X}),", start, end)?;
+    }
+    writeln!(out, "];")?;
+    writeln!(out)?;
+
+    writeln!(out, "// Runs `n` instructions, as `n` calls to `cpu.run_cycle()` would.")?;
+    writeln!(out, "pub fn run(cpu: &mut CPU, n: u64) {{")?;
+    writeln!(out, "    // Only the interpreter logs instructions and tells observers of them.")?;
+    writeln!(out, "    if cpu.is_traced() {{")?;
+    writeln!(out, "        for _ in 0..n {{")?;
+    writeln!(out, "            cpu.run_cycle();")?;
+    writeln!(out, "        }}")?;
+    writeln!(out, "        return;")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "    let mut left = n;")?;
+    writeln!(out, "    let mut intact = code_intact(cpu, 0, usize::MAX);")?;
+    writeln!(out, "    while left > 0 {{")?;
+    writeln!(out, "        // Stopped, the CPU runs nothing for the rest of the instructions.")?;
+    writeln!(out, "        if cpu.fault().is_some() || cpu.is_waiting_for_vblank() {{")?;
+    writeln!(out, "            return;")?;
+    writeln!(out, "        }}")?;
+    writeln!(out, "        match cpu.get_pc() {{")?;
+    for (&start, block) in blocks.iter() {
+        writeln!(out, "            0x{:03X} if intact && left >= {} => {{", start, block.len())?;
+        for (index, &op) in block.iter().enumerate() {
+            let instruction = Instruction::decode(op);
+            let call = format!("cpu.run({}); // {}", expression(instruction), instruction);
+            // Writes always end a block, so only its last instruction can
+            // change code.
+            match writes(op) {
+                Some(len) => {
+                    writeln!(out, "                let i = cpu.get_i() as usize;")?;
+                    writeln!(out, "                {}", call)?;
+                    writeln!(out, "                intact = code_intact(cpu, i, i + {});", len)?;
+                }
+                None => writeln!(out, "                {}", call)?,
+            }
+            // A draw may wait for the frame to end part way through.
+            if op >> 12 == 0xD && index + 1 < block.len() {
+                writeln!(out, "                if cpu.is_waiting_for_vblank() {{")?;
+                writeln!(out, "                    return;")?;
+                writeln!(out, "                }}")?;
+            }
+        }
+        writeln!(out, "                left -= {};", block.len())?;
+        writeln!(out, "            }}")?;
+    }
+    writeln!(out, "            _ => {{")?;
+    writeln!(out, "                let written = writes(cpu);")?;
+    writeln!(out, "                cpu.run_cycle();")?;
+    writeln!(out, "                left -= 1;")?;
+    writeln!(out, "                if let Some((from, to)) = written {{")?;
+    writeln!(out, "                    intact = intact && code_intact(cpu, from, to);")?;
+    writeln!(out, "                }}")?;
+    writeln!(out, "            }}")?;
+    writeln!(out, "        }}")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(out, "// The memory the instruction at PC writes, if it is FX33 or FX55.")?;
+    writeln!(out, "fn writes(cpu: &CPU) -> Option<(usize, usize)> {{")?;
+    writeln!(out, "    let pc = cpu.get_pc() as usize;")?;
+    writeln!(out, "    let op = (cpu.get_mem8(pc) as u16) << 8 | cpu.get_mem8(pc + 1) as u16;")?;
+    writeln!(out, "    let i = cpu.get_i() as usize;")?;
+    writeln!(out, "    match op & 0xF0FF {{")?;
+    writeln!(out, "        0xF033 => Some((i, i + 3)),")?;
+    writeln!(out, "        0xF055 => Some((i, i + ((op >> 8) & 0xF) as usize + 1)),")?;
+    writeln!(out, "        _ => None,")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(out, "// Whether the recovered code between `from` and `to` is as recompiled.")?;
+    writeln!(out, "fn code_intact(cpu: &CPU, from: usize, to: usize) -> bool {{")?;
+    writeln!(out, "    CODE.iter().all(|&(start, end)| {{")?;
+    writeln!(out, "        (start.max(from)..end.min(to)).all(|addr| cpu.get_mem8(addr) == ROM[addr - ROM_START])")?;
+    writeln!(out, "    }})")?;
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+// `instruction` as Rust source, with its operands in hex.
+fn expression(instruction: Instruction) -> String {
+    let debug = format!("{:?}", instruction);
+    match debug.split_once('(') {
+        Some((name, operands)) => {
+            let operands: Vec<String> = operands
+                .trim_end_matches(')')
+                .split(", ")
+                .map(|operand| format!("0x{:X}", operand.parse::<u16>().unwrap()))
+                .collect();
+            format!("{}({})", name, operands.join(", "))
+        }
+        None => debug,
+    }
+}
+
+// How many bytes `op` writes at I, if it is FX33 or FX55.
+fn writes(op: u16) -> Option<u16> {
+    match op & 0xF0FF {
+        0xF033 => Some(3),
+        0xF055 => Some(((op >> 8) & 0xF) + 1),
+        _ => None,
+    }
+}
+
+// The addresses the blocks cover, merged into ranges.
+fn code_ranges(blocks: &BTreeMap<u16, Vec<u16>>) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (&start, block) in blocks.iter() {
+        let (start, end) = (start as usize, start as usize + block.len() * 2);
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => ranges.push((start, end)),
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+fn recover_asm(source: &str) -> Vec<(u16, Vec<u16>)> {
+    use asm::assemble;
+
+    recover(&assemble(source).unwrap()).into_iter().collect()
+}
+
+#[test]
+fn test_recover_follows_control_flow() {
+    let blocks = recover_asm(
+        "
+        start:
+            LD V0, 1
+            CALL sub
+            SE V0, 2
+            JP start
+            JP end
+        sub:
+            ADD V0, 1
+            RET
+        end:
+            JP end
+            DW 0x0123
+        ",
+    );
+    assert_eq!(
+        blocks,
+        [
+            (0x200, vec![0x6001, 0x220A]),
+            (0x204, vec![0x3002]),
+            (0x206, vec![0x1200]),
+            (0x208, vec![0x120E]),
+            (0x20A, vec![0x7001, 0x00EE]),
+            (0x20E, vec![0x120E]),
+        ]
+    );
+}
+
+#[test]
+fn test_recover_stops_at_computed_jumps_and_data() {
+    let blocks = recover_asm(
+        "
+            LD I, 0x300
+            LD [I], V1
+            JP V0, 0x208
+            DW 0x0123
+            LD V0, 1
+        ",
+    );
+    assert_eq!(blocks, [(0x200, vec![0xA300, 0xF155]), (0x204, vec![0xB208])]);
+}
+
+#[test]
+fn test_code_ranges() {
+    let blocks = recover(&[0x60, 0x01, 0x12, 0x06, 0x00, 0x00, 0x12, 0x06]);
+    assert_eq!(code_ranges(&blocks), [(0x200, 0x204), (0x206, 0x208)]);
+}
@@ -0,0 +1,230 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use framebuffer::{Frame, Framebuffer};
+use gif::GifEncoder;
+use palette::{Color, Palette};
+use screenshot::Screenshot;
+
+const FRAME_RATE: usize = 60;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum VideoFormat {
+    Y4m,
+    Gif,
+}
+
+impl VideoFormat {
+    pub fn from_path(path: &Path) -> Option<VideoFormat> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "y4m" => Some(VideoFormat::Y4m),
+            "gif" => Some(VideoFormat::Gif),
+            _ => None,
+        }
+    }
+}
+
+// Collects one framebuffer per emulated frame. Capturing is meant to be
+// driven by the emulation's 60Hz frame loop, so recordings are independent
+// of how fast (or whether) anything is drawn to a terminal.
+//
+// A video has a single resolution: frames that don't match the size of the
+// first captured frame are dropped.
+pub struct Recorder {
+    pub scale: usize,
+    pub palette: Palette,
+    frames: Vec<Frame>,
+}
+
+impl Recorder {
+    pub fn new(scale: usize, palette: Palette) -> Recorder {
+        Recorder {
+            scale,
+            palette,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn capture(&mut self, screen: &dyn Framebuffer) {
+        if let Some(first) = self.frames.first() {
+            if (first.width(), first.height()) != (screen.width(), screen.height()) {
+                return;
+            }
+        }
+        self.frames.push(Frame::capture(screen));
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    fn size(&self) -> (usize, usize, usize) {
+        let scale = self.scale.max(1);
+        match self.frames.first() {
+            Some(frame) => (frame.width() * scale, frame.height() * scale, frame.colors()),
+            None => (64 * scale, 32 * scale, 2),
+        }
+    }
+
+    fn renderer(&self) -> Screenshot {
+        Screenshot {
+            scale: self.scale,
+            palette: self.palette.clone(),
+            grid: None,
+        }
+    }
+
+    // Every captured frame is written, keeping the stream at a constant 60fps.
+    pub fn write_y4m<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let renderer = self.renderer();
+        let (width, height, colors) = self.size();
+        writeln!(out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg", width, height, FRAME_RATE)?;
+
+        let yuv: Vec<(u8, u8, u8)> = renderer.colors(colors).iter().map(to_yuv).collect();
+        let mut u_plane = Vec::with_capacity(width * height / 4);
+        let mut v_plane = Vec::with_capacity(width * height / 4);
+        for frame in self.frames.iter() {
+            let image = renderer.render(frame);
+            out.write_all(b"FRAME\n")?;
+            let y_plane: Vec<u8> = image.pixels.iter().map(|&p| yuv[p as usize].0).collect();
+            out.write_all(&y_plane)?;
+
+            // Chroma is subsampled 2x2; average each block.
+            u_plane.clear();
+            v_plane.clear();
+            for by in 0..height / 2 {
+                for bx in 0..width / 2 {
+                    let (mut u, mut v) = (0u32, 0u32);
+                    for &(dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let p = image.pixels[(by * 2 + dy) * width + bx * 2 + dx] as usize;
+                        u += yuv[p].1 as u32;
+                        v += yuv[p].2 as u32;
+                    }
+                    u_plane.push(((u + 2) / 4) as u8);
+                    v_plane.push(((v + 2) / 4) as u8);
+                }
+            }
+            out.write_all(&u_plane)?;
+            out.write_all(&v_plane)?;
+        }
+        out.flush()
+    }
+
+    // Runs of identical frames are merged into one GIF frame with a longer
+    // delay. Delays are rounded against the running total so the animation
+    // doesn't drift from 60fps.
+    pub fn write_gif<W: Write>(&self, out: W) -> io::Result<()> {
+        let renderer = self.renderer();
+        let (width, height, colors) = self.size();
+        let mut encoder = GifEncoder::new(out, width, height, &renderer.colors(colors))?;
+
+        let mut start = 0;
+        let mut elapsed_cs = 0;
+        while start < self.frames.len() {
+            let mut end = start + 1;
+            while end < self.frames.len() && self.frames[end] == self.frames[start] {
+                end += 1;
+            }
+            let end_cs = end * 100 / FRAME_RATE;
+            let delay = end_cs.saturating_sub(elapsed_cs).max(1);
+            elapsed_cs += delay;
+            let image = renderer.render(&self.frames[start]);
+            encoder.write_frame(&image.pixels, delay as u16)?;
+            start = end;
+        }
+        encoder.finish()?.flush()
+    }
+
+    // Writes the recording to `path`, picking the format from the extension.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let format = VideoFormat::from_path(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown video format for {}", path.display()),
+            )
+        })?;
+        let out = BufWriter::new(File::create(path)?);
+        match format {
+            VideoFormat::Y4m => self.write_y4m(out),
+            VideoFormat::Gif => self.write_gif(out),
+        }
+    }
+}
+
+// Full-range BT.601, as implied by the `C420jpeg` colorspace tag.
+fn to_yuv(color: &Color) -> (u8, u8, u8) {
+    let (r, g, b) = (color.r as f64, color.g as f64, color.b as f64);
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    let u = 128.0 - 0.168_736 * r - 0.331_264 * g + 0.5 * b;
+    let v = 128.0 + 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+    let clamp = |x: f64| x.round().clamp(0.0, 255.0) as u8;
+    (clamp(y), clamp(u), clamp(v))
+}
+
+#[cfg(test)]
+use display::Display;
+
+#[test]
+fn test_y4m_layout() {
+    let mut recorder = Recorder::new(1, Palette::default());
+    let mut display = Display::init();
+    recorder.capture(&display);
+    display.set_sprite(0, 0, &[0x80]);
+    recorder.capture(&display);
+
+    let mut out = Vec::new();
+    recorder.write_y4m(&mut out).unwrap();
+    let header = b"YUV4MPEG2 W64 H32 F60:1 Ip A1:1 C420jpeg\n";
+    assert_eq!(&out[..header.len()], &header[..]);
+    let frame_size = b"FRAME\n".len() + 64 * 32 * 3 / 2;
+    assert_eq!(out.len(), header.len() + 2 * frame_size);
+
+    let second = header.len() + frame_size + b"FRAME\n".len();
+    assert_eq!(out[header.len() + b"FRAME\n".len()], 0);
+    assert_eq!(out[second], 255);
+    assert_eq!(out[second + 1], 0);
+}
+
+#[test]
+fn test_gif_deduplicates_frames() {
+    let mut recorder = Recorder::new(1, Palette::default());
+    let mut display = Display::init();
+    for _ in 0..30 {
+        recorder.capture(&display);
+    }
+    display.set_sprite(0, 0, &[0x80]);
+    for _ in 0..30 {
+        recorder.capture(&display);
+    }
+
+    let mut out = Vec::new();
+    recorder.write_gif(&mut out).unwrap();
+    // One graphic control extension per distinct frame, half a second each.
+    let delays: Vec<u16> = out.windows(4)
+        .enumerate()
+        .filter(|&(_, w)| w == [0x21, 0xF9, 0x04, 0x00])
+        .map(|(i, _)| u16::from_le_bytes([out[i + 4], out[i + 5]]))
+        .collect();
+    assert_eq!(delays, vec![50, 50]);
+}
+
+#[test]
+fn test_video_format_from_path() {
+    assert_eq!(VideoFormat::from_path(Path::new("run.y4m")), Some(VideoFormat::Y4m));
+    assert_eq!(VideoFormat::from_path(Path::new("run.GIF")), Some(VideoFormat::Gif));
+    assert_eq!(VideoFormat::from_path(Path::new("run.mp4")), None);
+}
+
+#[test]
+fn test_mismatched_resolution_dropped() {
+    let mut recorder = Recorder::new(1, Palette::default());
+    recorder.capture(&Frame::new(128, 64, 1));
+    recorder.capture(&Display::init());
+    recorder.capture(&Frame::new(128, 64, 1));
+    assert_eq!(recorder.frame_count(), 2);
+    assert_eq!(recorder.size(), (128, 64, 2));
+}
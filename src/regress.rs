@@ -0,0 +1,376 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use config::{self, ConfigError, Line, Settings};
+use displayimpl::{ascii_to_screen, screen_to_ascii};
+use framebuffer::{Frame, Framebuffer};
+use harness::Harness;
+use palette::Color;
+use png;
+use quirks::Quirks;
+use rom::Rom;
+use romdb::RomDatabase;
+
+// Instructions per frame when neither the entry nor the ROM database says.
+const DEFAULT_IPF: u32 = 15;
+
+// Something to do at the start of a frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyEvent {
+    Press(u8),
+    Release(u8),
+}
+
+// One ROM to check: where it is, how to run it and for how long.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Entry {
+    // The section name, which names its baseline.
+    pub name: String,
+    pub rom: PathBuf,
+    pub frames: u64,
+    pub keys: Vec<(u64, KeyEvent)>,
+    pub seed: u64,
+    pub settings: Settings,
+}
+
+#[derive(Debug)]
+pub enum ManifestError {
+    Io(PathBuf, io::Error),
+    Syntax { line: usize, message: String },
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ManifestError::Io(ref path, ref err) => write!(f, "couldn't read {}: {}", path.display(), err),
+            ManifestError::Syntax { line, ref message } => write!(f, "manifest line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for ManifestError {}
+
+impl From<ConfigError> for ManifestError {
+    fn from(err: ConfigError) -> ManifestError {
+        match err {
+            ConfigError::Io(path, err) => ManifestError::Io(path, err),
+            ConfigError::Syntax { line, message } => ManifestError::Syntax { line, message },
+        }
+    }
+}
+
+// The ROMs `rust8 regress` checks, in the config file's format: a section
+// per ROM, named for its baseline, giving the ROM, the frames to run it for
+// and optionally a seed, key presses and any of the config file's settings.
+//
+//     baseline = baselines
+//
+//     [pong]
+//     rom = roms/pong.ch8
+//     frames = 600
+//     keys = 60:1 120:-1 130:4
+//     quirks = superchip,no-jump
+//
+// `keys` lists FRAME:KEY to press a key (0-F) as that frame starts, and
+// FRAME:-KEY to release it. Paths are relative to the manifest, and
+// `baseline`, the directory baselines are kept in, defaults to `baseline`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Manifest {
+    pub baseline: PathBuf,
+    pub entries: Vec<Entry>,
+}
+
+impl Manifest {
+    pub fn parse(text: &str, dir: &Path) -> Result<Manifest, ManifestError> {
+        let mut manifest = Manifest {
+            baseline: dir.join("baseline"),
+            entries: Vec::new(),
+        };
+        // The line each entry's section starts on, to report what's missing.
+        let mut headers = Vec::new();
+        for (number, line) in config::parse_lines(text)? {
+            let syntax = |message: String| ManifestError::Syntax { line: number, message };
+            let (key, value) = match line {
+                Line::Section(name) => {
+                    // Names become baseline file names.
+                    if name.contains(['/', '\\']) {
+                        return Err(syntax(format!("bad section name {}", name)));
+                    }
+                    if manifest.entries.iter().any(|entry| entry.name == name) {
+                        return Err(syntax(format!("{} is listed twice", name)));
+                    }
+                    manifest.entries.push(Entry {
+                        name: name.to_string(),
+                        rom: PathBuf::new(),
+                        frames: 0,
+                        keys: Vec::new(),
+                        seed: 0,
+                        settings: Settings::default(),
+                    });
+                    headers.push(number);
+                    continue;
+                }
+                Line::Setting(key, value) => (key, value),
+            };
+            let bad = || syntax(format!("bad value for {}: {}", key, value));
+            let entry = match manifest.entries.last_mut() {
+                Some(entry) => entry,
+                None if key == "baseline" => {
+                    manifest.baseline = dir.join(value);
+                    continue;
+                }
+                None => return Err(syntax(format!("{} needs to be in a ROM's section", key))),
+            };
+            match key {
+                "rom" => entry.rom = dir.join(value),
+                "frames" => entry.frames = value.parse().map_err(|_| bad())?,
+                "seed" => entry.seed = value.parse().map_err(|_| bad())?,
+                "keys" => entry.keys = parse_keys(value).ok_or_else(bad)?,
+                _ if Settings::keys().contains(&key) => {
+                    if !entry.settings.set(key, value) {
+                        return Err(bad());
+                    }
+                }
+                _ => return Err(syntax(format!("unknown setting {}", key))),
+            }
+        }
+        for (entry, &line) in manifest.entries.iter().zip(headers.iter()) {
+            let missing = if entry.rom.as_os_str().is_empty() {
+                "rom"
+            } else if entry.frames == 0 {
+                "frames"
+            } else {
+                continue;
+            };
+            return Err(ManifestError::Syntax {
+                line,
+                message: format!("{} needs {}", entry.name, missing),
+            });
+        }
+        Ok(manifest)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Manifest, ManifestError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| ManifestError::Io(path.to_path_buf(), err))?;
+        Manifest::parse(&text, path.parent().unwrap_or_else(|| Path::new("")))
+    }
+
+    pub fn baseline_path(&self, entry: &Entry) -> PathBuf {
+        self.baseline.join(format!("{}.txt", entry.name))
+    }
+}
+
+fn parse_keys(text: &str) -> Option<Vec<(u64, KeyEvent)>> {
+    let mut keys = Vec::new();
+    for event in text.split_whitespace() {
+        let colon = event.find(':')?;
+        let frame = event[..colon].parse().ok()?;
+        let (release, key) = match event[colon + 1..].strip_prefix('-') {
+            Some(key) => (true, key),
+            None => (false, &event[colon + 1..]),
+        };
+        let key = u8::from_str_radix(key, 16).ok().filter(|&key| key < 16)?;
+        keys.push((frame, if release { KeyEvent::Release(key) } else { KeyEvent::Press(key) }));
+    }
+    keys.sort_by_key(|&(frame, _)| frame);
+    Some(keys)
+}
+
+// Runs an entry's ROM headless, returning the screen it ends on. Settings
+// come from the ROM's cartridge or the bundled ROM database, then the entry,
+// but never the user's config file or database, so every checkout gets the
+// same screens. A ROM waiting for a key nobody presses just waits.
+pub fn run(entry: &Entry) -> Result<Frame, String> {
+    let mut rom = Rom::load(&entry.rom).map_err(|err| format!("{}: {}", entry.rom.display(), err))?;
+    let mut settings = Settings::default();
+    let info = rom
+        .info()
+        .cloned()
+        .or_else(|| RomDatabase::builtin().lookup(&rom.sha1_hex()).cloned());
+    if let Some(ref info) = info {
+        settings.merge(&Settings::from_info(info));
+    }
+    settings.merge(&entry.settings);
+    if let Some(platform) = settings.platform {
+        rom = rom.with_platform(platform).map_err(|err| format!("{}: {}", entry.rom.display(), err))?;
+    }
+    let harness = Harness::new(rom.bytes())
+        .platform(rom.platform())
+        .quirks(settings.quirks.unwrap_or_else(|| Quirks::for_platform(rom.platform())))
+        .ipf(settings.ipf.unwrap_or(DEFAULT_IPF) as u64)
+        .seed(entry.seed);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        harness.run(|machine| {
+            let mut keys = entry.keys.iter().peekable();
+            for frame in 0..entry.frames {
+                while let Some(&&(_, event)) = keys.peek().filter(|&&&(at, _)| at <= frame) {
+                    match event {
+                        KeyEvent::Press(key) => machine.press(key),
+                        KeyEvent::Release(key) => machine.release(key),
+                    }
+                    keys.next();
+                }
+                machine.run_frame();
+            }
+            Frame::capture(machine.cpu().display())
+        })
+    }));
+    result.map_err(|err| match err.downcast_ref::<String>() {
+        Some(message) => format!("crashed: {}", message),
+        None => format!("crashed: {}", err.downcast_ref::<&str>().unwrap_or(&"unknown error")),
+    })
+}
+
+// The stored screen, or `None` if there isn't one yet.
+pub fn load_baseline(path: &Path) -> io::Result<Option<Frame>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(Some(ascii_to_screen(&text))),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn save_baseline(path: &Path, screen: &Frame) -> io::Result<()> {
+    fs::write(path, screen_to_ascii(screen))
+}
+
+// How many pixels differ in color. Screens of different sizes differ
+// everywhere.
+pub fn differing_pixels(baseline: &Frame, screen: &Frame) -> usize {
+    if (baseline.width(), baseline.height()) != (screen.width(), screen.height()) {
+        return baseline.width().max(screen.width()) * baseline.height().max(screen.height());
+    }
+    let mut count = 0;
+    for y in 0..screen.height() {
+        for x in 0..screen.width() {
+            if baseline.color_index(x, y) != screen.color_index(x, y) {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+const DIFF_COLORS: [(u8, u8, u8); 7] = [
+    (0x00, 0x00, 0x00), // dark
+    (0xFF, 0xFF, 0xFF), // lit
+    (0x50, 0x50, 0x50), // lit in both, in the diff
+    (0xE0, 0x30, 0x30), // only lit in the baseline
+    (0x30, 0xE0, 0x30), // only lit now
+    (0xE0, 0xE0, 0x30), // lit in both, in different colors
+    (0x30, 0x30, 0xA0), // between panels
+];
+
+// A PNG of the baseline, the new screen and the difference side by side,
+// each pixel `scale` pixels square. The difference shows pixels only the
+// baseline had lit in red, ones only the new screen has in green.
+pub fn diff_png(baseline: &Frame, screen: &Frame, scale: usize) -> Vec<u8> {
+    let panel = baseline.width().max(screen.width());
+    let (width, height) = (panel * 3 + 2, baseline.height().max(screen.height()));
+    let index = |fb: &Frame, x: usize, y: usize| {
+        if x < fb.width() && y < fb.height() {
+            fb.color_index(x, y)
+        } else {
+            0
+        }
+    };
+    let mut pixels = vec![6; width * height];
+    for y in 0..height {
+        for x in 0..panel {
+            let (old, new) = (index(baseline, x, y), index(screen, x, y));
+            let diff = match (old, new) {
+                (0, 0) => 0,
+                (_, 0) => 3,
+                (0, _) => 4,
+                _ if old == new => 2,
+                _ => 5,
+            };
+            pixels[y * width + x] = old.min(1);
+            pixels[y * width + panel + 1 + x] = new.min(1);
+            pixels[y * width + 2 * panel + 2 + x] = diff;
+        }
+    }
+
+    let scale = scale.max(1);
+    let mut scaled = Vec::with_capacity(pixels.len() * scale * scale);
+    for row in pixels.chunks(width) {
+        let line: Vec<u8> = row.iter().flat_map(|&pixel| vec![pixel; scale]).collect();
+        for _ in 0..scale {
+            scaled.extend_from_slice(&line);
+        }
+    }
+    let colors: Vec<Color> = DIFF_COLORS.iter().map(|&(r, g, b)| Color::rgb(r, g, b)).collect();
+    png::encode_indexed(width * scale, height * scale, &colors, &scaled)
+}
+
+#[cfg(test)]
+const SAMPLE: &str = "
+baseline = golden
+
+[pong]
+rom = roms/pong.ch8
+frames = 600
+keys = 130:4 60:1 120:-1
+seed = 7
+quirks = superchip,no-jump
+
+# A second ROM
+[maze]
+rom = maze.ch8
+frames = 10
+platform = schip
+";
+
+#[test]
+fn test_parse_manifest() {
+    let manifest = Manifest::parse(SAMPLE, Path::new("tests")).unwrap();
+    assert_eq!(manifest.baseline, PathBuf::from("tests/golden"));
+    assert_eq!(manifest.entries.len(), 2);
+    let pong = &manifest.entries[0];
+    assert_eq!(pong.rom, PathBuf::from("tests/roms/pong.ch8"));
+    assert_eq!((pong.frames, pong.seed), (600, 7));
+    assert_eq!(
+        pong.keys,
+        [(60, KeyEvent::Press(1)), (120, KeyEvent::Release(1)), (130, KeyEvent::Press(4))]
+    );
+    assert_eq!(pong.settings.quirks, Quirks::parse("superchip,no-jump"));
+    assert_eq!(manifest.baseline_path(&manifest.entries[1]), PathBuf::from("tests/golden/maze.txt"));
+    assert_eq!(Manifest::parse("", Path::new("")).unwrap().baseline, PathBuf::from("baseline"));
+}
+
+#[test]
+fn test_manifest_errors() {
+    let line = |text: &str| match Manifest::parse(text, Path::new("")) {
+        Err(ManifestError::Syntax { line, .. }) => line,
+        other => panic!("{:?}", other),
+    };
+    assert_eq!(line("frames = 10"), 1);
+    assert_eq!(line("[a]\nrom = a.ch8\nframes = ten"), 3);
+    assert_eq!(line("[a]\nrom = a.ch8\nkeys = 1:G"), 3);
+    assert_eq!(line("[a]\nrom = a.ch8\nframes = 1\n[a]"), 4);
+    assert_eq!(line("[a]\nrom = a.ch8\nframes = 1\n\n[b]\nframes = 1"), 5);
+    assert_eq!(line("[a]\nrom = a.ch8"), 1);
+    assert_eq!(line("[../a]"), 1);
+}
+
+#[test]
+fn test_differing_pixels_and_diff() {
+    let mut baseline = Frame::new(4, 2, 1);
+    baseline.set_color_index(0, 0, 1);
+    baseline.set_color_index(1, 0, 1);
+    let mut screen = baseline.clone();
+    assert_eq!(differing_pixels(&baseline, &screen), 0);
+    screen.set_color_index(1, 0, 0);
+    screen.set_color_index(3, 1, 1);
+    assert_eq!(differing_pixels(&baseline, &screen), 2);
+    assert_eq!(differing_pixels(&baseline, &Frame::new(8, 2, 1)), 16);
+
+    let image = diff_png(&baseline, &screen, 2);
+    // Three 4 pixel panels and two separators, doubled.
+    assert_eq!(&image[16..24], &[0, 0, 0, 28, 0, 0, 0, 4]);
+}
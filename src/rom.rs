@@ -0,0 +1,281 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use cartridge::{Cartridge, CartridgeError};
+use gif;
+use platform::Platform;
+use ram::ROM_START;
+use romdb::RomInfo;
+use sha1;
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    Cartridge(CartridgeError),
+    Empty,
+    TooLarge {
+        size: usize,
+        max: usize,
+        platform: Platform,
+    },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::Io(ref err) => write!(f, "couldn't read ROM: {}", err),
+            RomError::Cartridge(ref err) => write!(f, "{}", err),
+            RomError::Empty => write!(f, "ROM is empty"),
+            RomError::TooLarge {
+                size,
+                max,
+                platform,
+            } => write!(
+                f,
+                "ROM is {} bytes, but {} only has room for {} bytes",
+                size, platform, max
+            ),
+        }
+    }
+}
+
+impl Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> RomError {
+        RomError::Io(err)
+    }
+}
+
+impl From<CartridgeError> for RomError {
+    fn from(err: CartridgeError) -> RomError {
+        RomError::Cartridge(err)
+    }
+}
+
+// A program image, checked to fit in the memory of the platform it runs on.
+#[derive(Clone, Debug)]
+pub struct Rom {
+    name: String,
+    bytes: Vec<u8>,
+    platform: Platform,
+    sha1: [u8; 20],
+    info: Option<RomInfo>,
+}
+
+impl Rom {
+    // The platform is guessed from the program's contents.
+    pub fn from_bytes(name: &str, bytes: Vec<u8>) -> Result<Rom, RomError> {
+        let platform = detect_platform(&bytes);
+        Rom::new(name, bytes, platform)
+    }
+
+    // Unpacks the program and settings from an Octo cartridge GIF.
+    pub fn from_cartridge(name: &str, gif: &[u8]) -> Result<Rom, RomError> {
+        let cartridge = Cartridge::decode(name, gif)?;
+        let rom = Rom::new(name, cartridge.program, cartridge.platform)?;
+        Ok(Rom {
+            info: Some(cartridge.info),
+            ..rom
+        })
+    }
+
+    // GIFs are read as Octo cartridges. Otherwise a `.ch8`, `.sc8` or `.xo8`
+    // extension decides the platform, or failing that it is guessed from the
+    // contents.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Rom, RomError> {
+        let path = path.as_ref();
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if gif::is_gif(&bytes) {
+            return Rom::from_cartridge(&name, &bytes);
+        }
+        let platform = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Platform::from_extension)
+            .unwrap_or_else(|| detect_platform(&bytes));
+        Rom::new(&name, bytes, platform)
+    }
+
+    fn new(name: &str, bytes: Vec<u8>, platform: Platform) -> Result<Rom, RomError> {
+        check_size(bytes.len(), platform)?;
+        Ok(Rom {
+            name: name.to_string(),
+            sha1: sha1::sha1(&bytes),
+            bytes,
+            platform,
+            info: None,
+        })
+    }
+
+    // Runs the ROM on `platform` instead of the detected one.
+    pub fn with_platform(self, platform: Platform) -> Result<Rom, RomError> {
+        check_size(self.bytes.len(), platform)?;
+        Ok(Rom { platform, ..self })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn sha1(&self) -> [u8; 20] {
+        self.sha1
+    }
+
+    pub fn sha1_hex(&self) -> String {
+        sha1::to_hex(&self.sha1)
+    }
+
+    // Settings that came with the ROM itself, as they do in cartridges.
+    pub fn info(&self) -> Option<&RomInfo> {
+        self.info.as_ref()
+    }
+}
+
+// The most bytes a ROM can have and still fit in `platform`'s memory.
+pub fn max_size(platform: Platform) -> usize {
+    platform.memory_size() - ROM_START
+}
+
+fn check_size(size: usize, platform: Platform) -> Result<(), RomError> {
+    let max = max_size(platform);
+    if size == 0 {
+        Err(RomError::Empty)
+    } else if size > max {
+        Err(RomError::TooLarge {
+            size,
+            max,
+            platform,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+// Looks for instructions only later platforms have. ROMs mix code and data,
+// so this is a guess: a ROM is taken to be for the most capable platform
+// whose instructions appear anywhere in it.
+pub fn detect_platform(bytes: &[u8]) -> Platform {
+    if bytes.len() > max_size(Platform::SuperChip) {
+        return Platform::XoChip;
+    }
+    let mut platform = Platform::Chip8;
+    for word in bytes.chunks(2).filter(|word| word.len() == 2) {
+        let op = (word[0] as u16) << 8 | word[1] as u16;
+        if is_xo_chip_only(op) {
+            return Platform::XoChip;
+        }
+        if is_super_chip_only(op) {
+            platform = Platform::SuperChip;
+        }
+    }
+    platform
+}
+
+fn is_super_chip_only(op: u16) -> bool {
+    match op {
+        0x00FB..=0x00FF => true,
+        0x00C1..=0x00CF => true,
+        _ => match (op & 0xF000, op & 0x00FF) {
+            (0xD000, _) => op & 0x000F == 0,
+            (0xF000, 0x30) | (0xF000, 0x75) | (0xF000, 0x85) => true,
+            _ => false,
+        },
+    }
+}
+
+fn is_xo_chip_only(op: u16) -> bool {
+    match op {
+        0xF000 | 0xF002 => true,
+        0x00D1..=0x00DF => true,
+        _ => matches!(
+            (op & 0xF000, op & 0x000F, op & 0x00FF),
+            (0x5000, 2, _) | (0x5000, 3, _) | (0xF000, _, 0x01) | (0xF000, _, 0x3A)
+        ),
+    }
+}
+
+#[test]
+fn test_from_bytes() {
+    let rom = Rom::from_bytes("abc", b"abc".to_vec()).unwrap();
+    assert_eq!(rom.name(), "abc");
+    assert_eq!(rom.size(), 3);
+    assert_eq!(rom.platform(), Platform::Chip8);
+    assert_eq!(rom.sha1_hex(), "a9993e364706816aba3e25717850c26c9cd0d89d");
+}
+
+#[test]
+fn test_size_limits() {
+    match Rom::from_bytes("empty", Vec::new()) {
+        Err(RomError::Empty) => {}
+        other => panic!("{:?}", other),
+    }
+    assert!(Rom::from_bytes("full", vec![0; 0xE00]).is_ok());
+    match Rom::from_bytes("big", vec![0; 0xE01]).unwrap().with_platform(Platform::Chip8) {
+        Err(RomError::TooLarge { size, max, .. }) => assert_eq!((size, max), (0xE01, 0xE00)),
+        other => panic!("{:?}", other),
+    }
+    assert!(Rom::from_bytes("huge", vec![0; 0x10000]).is_err());
+}
+
+#[test]
+fn test_detect_platform() {
+    assert_eq!(detect_platform(&[0x00, 0xE0, 0x12, 0x00]), Platform::Chip8);
+    assert_eq!(detect_platform(&[0x00, 0xFF, 0xD0, 0x10]), Platform::SuperChip);
+    assert_eq!(detect_platform(&[0xD0, 0x10, 0xF0, 0x00, 0x12, 0x34]), Platform::XoChip);
+    assert_eq!(detect_platform(&[0xF1, 0x01]), Platform::XoChip);
+    assert_eq!(detect_platform(&vec![0; 0xE01]), Platform::XoChip);
+}
+
+#[test]
+fn test_load_uses_extension() {
+    use std::env;
+    use std::io::Write;
+
+    let path = env::temp_dir().join("rust8_test_rom.sc8");
+    File::create(&path).unwrap().write_all(&[0x00, 0xE0]).unwrap();
+    let rom = Rom::load(&path).unwrap();
+    assert_eq!(rom.name(), "rust8_test_rom");
+    assert_eq!(rom.platform(), Platform::SuperChip);
+
+    match Rom::load(env::temp_dir().join("rust8_no_such_rom.ch8")) {
+        Err(RomError::Io(_)) => {}
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn test_load_cartridge() {
+    use std::env;
+    use std::io::Write;
+
+    let path = env::temp_dir().join("rust8_test_cart.gif");
+    let gif = ::cartridge::build_cartridge(r#"{"program": [0, 224], "options": {"tickrate": 7}}"#);
+    File::create(&path).unwrap().write_all(&gif).unwrap();
+    let rom = Rom::load(&path).unwrap();
+    assert_eq!(rom.bytes(), &[0x00, 0xE0]);
+    assert_eq!(rom.name(), "rust8_test_cart");
+    assert_eq!(rom.info().unwrap().tickrate, Some(7));
+    assert!(Rom::from_bytes("plain", vec![0x00, 0xE0]).unwrap().info().is_none());
+}
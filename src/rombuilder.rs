@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use instruction::Instruction;
+use ram::ROM_START;
+
+// A register operand, V0 to VF.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Reg(pub u8);
+
+pub const V0: Reg = Reg(0x0);
+pub const V1: Reg = Reg(0x1);
+pub const V2: Reg = Reg(0x2);
+pub const V3: Reg = Reg(0x3);
+pub const V4: Reg = Reg(0x4);
+pub const V5: Reg = Reg(0x5);
+pub const V6: Reg = Reg(0x6);
+pub const V7: Reg = Reg(0x7);
+pub const V8: Reg = Reg(0x8);
+pub const V9: Reg = Reg(0x9);
+pub const VA: Reg = Reg(0xA);
+pub const VB: Reg = Reg(0xB);
+pub const VC: Reg = Reg(0xC);
+pub const VD: Reg = Reg(0xD);
+pub const VE: Reg = Reg(0xE);
+pub const VF: Reg = Reg(0xF);
+
+// An address operand: a number, or a label defined anywhere in the ROM.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Target {
+    Addr(u16),
+    Label(String),
+}
+
+impl From<u16> for Target {
+    fn from(addr: u16) -> Target {
+        Target::Addr(addr)
+    }
+}
+
+impl<'a> From<&'a str> for Target {
+    fn from(label: &'a str) -> Target {
+        Target::Label(label.to_string())
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum BuildError {
+    UnknownLabel(String),
+    DuplicateLabel(String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::UnknownLabel(ref label) => write!(f, "unknown label \"{}\"", label),
+            BuildError::DuplicateLabel(ref label) => write!(f, "label \"{}\" defined twice", label),
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+enum Item {
+    Op(Instruction),
+    // An instruction taking an address not known until the ROM is built.
+    Ref(fn(u16) -> Instruction, Target),
+    Bytes(Vec<u8>),
+}
+
+// Builds ROM images in Rust, for tests: the assembler's instructions as
+// chained method calls, encoded through `Instruction`, which the assembler
+// encodes with and the CPU decodes opcodes into. Methods are named after
+// the mnemonics, with a suffix where the operands tell forms apart (`ld` and
+// `ld_reg`), or after the `Instruction` variant for the LD forms naming DT,
+// ST, K, F, B and [I].
+//
+//     let rom = RomBuilder::new()
+//         .ld(V0, 5)
+//         .label("loop")
+//         .add(V0, 0xFF)
+//         .se(V0, 0)
+//         .jp("loop")
+//         .build()
+//         .unwrap();
+#[derive(Default)]
+pub struct RomBuilder {
+    items: Vec<Item>,
+    labels: HashMap<String, u16>,
+    duplicate: Option<String>,
+    len: usize,
+}
+
+impl RomBuilder {
+    pub fn new() -> RomBuilder {
+        RomBuilder::default()
+    }
+
+    // The address the next instruction or data will be at.
+    pub fn here(&self) -> u16 {
+        (ROM_START + self.len) as u16
+    }
+
+    pub fn label(mut self, name: &str) -> RomBuilder {
+        let here = self.here();
+        if self.labels.insert(name.to_string(), here).is_some() && self.duplicate.is_none() {
+            self.duplicate = Some(name.to_string());
+        }
+        self
+    }
+
+    pub fn op(mut self, instruction: Instruction) -> RomBuilder {
+        self.items.push(Item::Op(instruction));
+        self.len += 2;
+        self
+    }
+
+    fn op_to<T: Into<Target>>(mut self, instruction: fn(u16) -> Instruction, target: T) -> RomBuilder {
+        self.items.push(Item::Ref(instruction, target.into()));
+        self.len += 2;
+        self
+    }
+
+    pub fn db(mut self, bytes: &[u8]) -> RomBuilder {
+        self.items.push(Item::Bytes(bytes.to_vec()));
+        self.len += bytes.len();
+        self
+    }
+
+    pub fn dw(mut self, words: &[u16]) -> RomBuilder {
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_be_bytes().to_vec()).collect();
+        self.len += bytes.len();
+        self.items.push(Item::Bytes(bytes));
+        self
+    }
+
+    pub fn cls(self) -> RomBuilder {
+        self.op(Instruction::Cls)
+    }
+
+    pub fn ret(self) -> RomBuilder {
+        self.op(Instruction::Ret)
+    }
+
+    pub fn jp<T: Into<Target>>(self, target: T) -> RomBuilder {
+        self.op_to(Instruction::Jp, target)
+    }
+
+    pub fn jp_v0<T: Into<Target>>(self, target: T) -> RomBuilder {
+        self.op_to(Instruction::JpV0, target)
+    }
+
+    pub fn call<T: Into<Target>>(self, target: T) -> RomBuilder {
+        self.op_to(Instruction::Call, target)
+    }
+
+    pub fn se(self, x: Reg, nn: u8) -> RomBuilder {
+        self.op(Instruction::SeByte(x.0, nn))
+    }
+
+    pub fn se_reg(self, x: Reg, y: Reg) -> RomBuilder {
+        self.op(Instruction::SeReg(x.0, y.0))
+    }
+
+    pub fn sne(self, x: Reg, nn: u8) -> RomBuilder {
+        self.op(Instruction::SneByte(x.0, nn))
+    }
+
+    pub fn sne_reg(self, x: Reg, y: Reg) -> RomBuilder {
+        self.op(Instruction::SneReg(x.0, y.0))
+    }
+
+    pub fn ld(self, x: Reg, nn: u8) -> RomBuilder {
+        self.op(Instruction::LdByte(x.0, nn))
+    }
+
+    pub fn ld_reg(self, x: Reg, y: Reg) -> RomBuilder {
+        self.op(Instruction::LdReg(x.0, y.0))
+    }
+
+    pub fn ld_i<T: Into<Target>>(self, target: T) -> RomBuilder {
+        self.op_to(Instruction::LdI, target)
+    }
+
+    pub fn add(self, x: Reg, nn: u8) -> RomBuilder {
+        self.op(Instruction::AddByte(x.0, nn))
+    }
+
+    pub fn add_reg(self, x: Reg, y: Reg) -> RomBuilder {
+        self.op(Instruction::AddReg(x.0, y.0))
+    }
+
+    pub fn add_i(self, x: Reg) -> RomBuilder {
+        self.op(Instruction::AddIVx(x.0))
+    }
+
+    pub fn or(self, x: Reg, y: Reg) -> RomBuilder {
+        self.op(Instruction::Or(x.0, y.0))
+    }
+
+    pub fn and(self, x: Reg, y: Reg) -> RomBuilder {
+        self.op(Instruction::And(x.0, y.0))
+    }
+
+    pub fn xor(self, x: Reg, y: Reg) -> RomBuilder {
+        self.op(Instruction::Xor(x.0, y.0))
+    }
+
+    pub fn sub(self, x: Reg, y: Reg) -> RomBuilder {
+        self.op(Instruction::Sub(x.0, y.0))
+    }
+
+    pub fn subn(self, x: Reg, y: Reg) -> RomBuilder {
+        self.op(Instruction::Subn(x.0, y.0))
+    }
+
+    pub fn shr(self, x: Reg, y: Reg) -> RomBuilder {
+        self.op(Instruction::Shr(x.0, y.0))
+    }
+
+    pub fn shl(self, x: Reg, y: Reg) -> RomBuilder {
+        self.op(Instruction::Shl(x.0, y.0))
+    }
+
+    pub fn rnd(self, x: Reg, nn: u8) -> RomBuilder {
+        self.op(Instruction::Rnd(x.0, nn))
+    }
+
+    pub fn draw(self, x: Reg, y: Reg, n: u8) -> RomBuilder {
+        self.op(Instruction::Drw(x.0, y.0, n))
+    }
+
+    pub fn skp(self, x: Reg) -> RomBuilder {
+        self.op(Instruction::Skp(x.0))
+    }
+
+    pub fn sknp(self, x: Reg) -> RomBuilder {
+        self.op(Instruction::Sknp(x.0))
+    }
+
+    // VX = delay timer.
+    pub fn ld_vx_dt(self, x: Reg) -> RomBuilder {
+        self.op(Instruction::LdVxDt(x.0))
+    }
+
+    // VX = next key pressed.
+    pub fn ld_vx_k(self, x: Reg) -> RomBuilder {
+        self.op(Instruction::LdVxK(x.0))
+    }
+
+    pub fn ld_dt_vx(self, x: Reg) -> RomBuilder {
+        self.op(Instruction::LdDtVx(x.0))
+    }
+
+    pub fn ld_st_vx(self, x: Reg) -> RomBuilder {
+        self.op(Instruction::LdStVx(x.0))
+    }
+
+    // I = address of the font sprite for the digit in VX.
+    pub fn ld_f_vx(self, x: Reg) -> RomBuilder {
+        self.op(Instruction::LdFVx(x.0))
+    }
+
+    // Stores the decimal digits of VX at I, I + 1 and I + 2.
+    pub fn ld_b_vx(self, x: Reg) -> RomBuilder {
+        self.op(Instruction::LdBVx(x.0))
+    }
+
+    // Stores V0 to VX at I.
+    pub fn ld_i_vx(self, x: Reg) -> RomBuilder {
+        self.op(Instruction::LdIVx(x.0))
+    }
+
+    // Loads V0 to VX from I.
+    pub fn ld_vx_i(self, x: Reg) -> RomBuilder {
+        self.op(Instruction::LdVxI(x.0))
+    }
+
+    pub fn build(&self) -> Result<Vec<u8>, BuildError> {
+        if let Some(ref label) = self.duplicate {
+            return Err(BuildError::DuplicateLabel(label.clone()));
+        }
+        let mut out = Vec::with_capacity(self.len);
+        for item in self.items.iter() {
+            let instruction = match *item {
+                Item::Op(instruction) => instruction,
+                Item::Ref(instruction, Target::Addr(addr)) => instruction(addr),
+                Item::Ref(instruction, Target::Label(ref label)) => match self.labels.get(label) {
+                    Some(&addr) => instruction(addr),
+                    None => return Err(BuildError::UnknownLabel(label.clone())),
+                },
+                Item::Bytes(ref bytes) => {
+                    out.extend_from_slice(bytes);
+                    continue;
+                }
+            };
+            out.extend_from_slice(&instruction.encode().to_be_bytes());
+        }
+        Ok(out)
+    }
+}
+
+#[test]
+fn test_matches_assembler() {
+    use asm::assemble;
+
+    let rom = RomBuilder::new()
+        .label("start")
+        .ld(V0, 0x0A)
+        .ld_i("sprite")
+        .label("loop")
+        .draw(V0, V1, 5)
+        .add(V0, 0xFF)
+        .se(V0, 0)
+        .jp("loop")
+        .shr(V3, V3)
+        .call(0x300)
+        .jp("start")
+        .label("sprite")
+        .db(&[0xF0, 0x90, 0xF0])
+        .dw(&[0x1234])
+        .build()
+        .unwrap();
+    let source = "
+        start:  LD V0, 0x0A
+                LD I, sprite
+        loop:   DRW V0, V1, 5
+                ADD V0, 0xFF
+                SE V0, 0
+                JP loop
+                SHR V3, V3
+                CALL 0x300
+                JP start
+        sprite: DB 0xF0, 0x90, 0xF0
+                DW 0x1234
+    ";
+    assert_eq!(rom, assemble(source).unwrap());
+}
+
+#[test]
+fn test_label_errors() {
+    assert_eq!(
+        RomBuilder::new().jp("nowhere").build(),
+        Err(BuildError::UnknownLabel("nowhere".to_string()))
+    );
+    assert_eq!(
+        RomBuilder::new().label("a").cls().label("a").build(),
+        Err(BuildError::DuplicateLabel("a".to_string()))
+    );
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use json::{Json, JsonError};
+use palette::{Color, Palette};
+use platform::Platform;
+use quirks::Quirks;
+
+// Programs known to the emulator, in the community CHIP-8 database's
+// `programs.json` format. Users can add to or override it with their own
+// file of the same format.
+const BUILTIN: &str = include_str!("../data/programs.json");
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    Io(PathBuf, io::Error),
+    Json(JsonError),
+    Format(String),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DatabaseError::Io(ref path, ref err) => write!(f, "couldn't read {}: {}", path.display(), err),
+            DatabaseError::Json(ref err) => write!(f, "{}", err),
+            DatabaseError::Format(ref message) => write!(f, "unexpected database layout: {}", message),
+        }
+    }
+}
+
+impl Error for DatabaseError {}
+
+impl From<JsonError> for DatabaseError {
+    fn from(err: JsonError) -> DatabaseError {
+        DatabaseError::Json(err)
+    }
+}
+
+// What the database knows about one ROM. Anything it doesn't know is `None`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct RomInfo {
+    pub title: String,
+    // The database id of the preferred platform, e.g. "superchip".
+    pub platform_id: Option<String>,
+    pub quirks: Option<Quirks>,
+    // Instructions per frame.
+    pub tickrate: Option<u32>,
+    pub palette: Option<Palette>,
+    // Names of in-game actions and the CHIP-8 keys they are on.
+    pub keys: Vec<(String, u8)>,
+}
+
+impl RomInfo {
+    pub fn platform(&self) -> Option<Platform> {
+        self.platform_id.as_ref().and_then(|id| Quirks::preset_platform(id))
+    }
+
+    // Parses one entry of a program's `roms` object.
+    fn from_json(title: &str, rom: &Json) -> RomInfo {
+        let platform_id = rom
+            .get("platforms")
+            .and_then(|platforms| platforms.as_array())
+            .and_then(|platforms| platforms.first())
+            .and_then(|platform| platform.as_str())
+            .map(|platform| platform.to_string());
+        let quirks = platform_id.as_ref().and_then(|id| {
+            let mut quirks = Quirks::preset(id)?;
+            if let Some(overrides) = rom.get("quirkyPlatforms").and_then(|platforms| platforms.get(id)) {
+                quirks.apply_json(overrides);
+            }
+            Some(quirks)
+        });
+        let tickrate = rom.get("tickrate").and_then(|rate| rate.as_u64()).map(|rate| rate as u32);
+        let palette = rom
+            .get("colors")
+            .and_then(|colors| colors.get("pixels"))
+            .and_then(|pixels| pixels.as_array())
+            .and_then(|pixels| pixels.iter().map(|hex| hex.as_str().and_then(Color::from_hex)).collect())
+            .filter(|colors: &Vec<Color>| colors.len() >= 2)
+            .map(Palette::from_colors);
+        let keys = rom
+            .get("keys")
+            .and_then(|keys| keys.as_object())
+            .map(|keys| {
+                keys.iter()
+                    .filter_map(|(name, key)| {
+                        key.as_u64().filter(|&key| key < 16).map(|key| (name.clone(), key as u8))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        RomInfo {
+            title: title.to_string(),
+            platform_id,
+            quirks,
+            tickrate,
+            palette,
+            keys,
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct RomDatabase {
+    roms: HashMap<String, RomInfo>,
+}
+
+impl RomDatabase {
+    pub fn parse(text: &str) -> Result<RomDatabase, DatabaseError> {
+        let json = Json::parse(text)?;
+        let programs = json
+            .as_array()
+            .ok_or_else(|| DatabaseError::Format("expected an array of programs".to_string()))?;
+        let mut roms = HashMap::new();
+        for program in programs {
+            let title = program.get("title").and_then(|title| title.as_str()).unwrap_or("");
+            let entries = program
+                .get("roms")
+                .and_then(|roms| roms.as_object())
+                .ok_or_else(|| DatabaseError::Format(format!("program \"{}\" has no roms", title)))?;
+            for (hash, rom) in entries {
+                roms.insert(hash.to_ascii_lowercase(), RomInfo::from_json(title, rom));
+            }
+        }
+        Ok(RomDatabase { roms })
+    }
+
+    pub fn builtin() -> RomDatabase {
+        RomDatabase::parse(BUILTIN).expect("built-in ROM database is invalid")
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<RomDatabase, DatabaseError> {
+        let path = path.as_ref();
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|err| DatabaseError::Io(path.to_path_buf(), err))?;
+        RomDatabase::parse(&text)
+    }
+
+    // The built-in database with the user's file, if there is one, on top.
+    pub fn standard() -> Result<RomDatabase, DatabaseError> {
+        let mut database = RomDatabase::builtin();
+        if let Some(path) = user_database_path() {
+            if path.exists() {
+                database.merge(RomDatabase::load(path)?);
+            }
+        }
+        Ok(database)
+    }
+
+    // Entries from `other` replace ones for the same ROM.
+    pub fn merge(&mut self, other: RomDatabase) {
+        self.roms.extend(other.roms);
+    }
+
+    pub fn lookup(&self, sha1_hex: &str) -> Option<&RomInfo> {
+        self.roms.get(&sha1_hex.to_ascii_lowercase())
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+}
+
+// `$RUST8_DATABASE`, or `~/.config/rust8/programs.json`.
+pub fn user_database_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("RUST8_DATABASE") {
+        return Some(PathBuf::from(path));
+    }
+    env::var_os("HOME").map(|home| Path::new(&home).join(".config/rust8/programs.json"))
+}
+
+#[cfg(test)]
+const SAMPLE: &str = r##"[
+  {
+    "title": "Sample",
+    "roms": {
+      "A9993E364706816ABA3E25717850C26C9CD0D89D": {
+        "file": "sample.ch8",
+        "platforms": ["superchip", "xochip"],
+        "tickrate": 30,
+        "quirkyPlatforms": { "superchip": { "jump": false } },
+        "colors": { "pixels": ["#000000", "#ff0000"], "buzzer": "#990000" },
+        "keys": { "left": 7, "right": 9, "bogus": 99 }
+      },
+      "da39a3ee5e6b4b0d3255bfef95601890afd80709": { "platforms": ["gameboy"] }
+    }
+  }
+]"##;
+
+#[test]
+fn test_builtin_lookup() {
+    use sha1;
+
+    let ibm_logo = [
+        0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08, 0xD0, 0x1F, 0x70, 0x09, 0xA2, 0x39, 0xD0, 0x1F, 0xA2, 0x48,
+        0x70, 0x08, 0xD0, 0x1F, 0x70, 0x04, 0xA2, 0x57, 0xD0, 0x1F, 0x70, 0x08, 0xA2, 0x66, 0xD0, 0x1F, 0x70, 0x08,
+        0xA2, 0x75, 0xD0, 0x1F, 0x12, 0x28, 0xFF, 0x00, 0xFF, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00,
+        0xFF, 0x00, 0xFF, 0xFF, 0x00, 0xFF, 0x00, 0x38, 0x00, 0x3F, 0x00, 0x3F, 0x00, 0x38, 0x00, 0xFF, 0x00, 0xFF,
+        0x80, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0x00, 0x80, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0xF8, 0x00, 0xFC,
+        0x00, 0x3E, 0x00, 0x3F, 0x00, 0x3B, 0x00, 0x39, 0x00, 0xF8, 0x00, 0xF8, 0x03, 0x00, 0x07, 0x00, 0x0F, 0x00,
+        0xBF, 0x00, 0xFB, 0x00, 0xF3, 0x00, 0xE3, 0x00, 0x43, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80,
+        0x00, 0x80, 0x00, 0xE0, 0x00, 0xE0,
+    ];
+    let database = RomDatabase::builtin();
+    let info = database.lookup(&sha1::to_hex(&sha1::sha1(&ibm_logo))).unwrap();
+    assert_eq!(info.title, "IBM Logo");
+    assert_eq!(info.platform(), Some(Platform::Chip8));
+    assert_eq!(info.quirks, Quirks::preset("originalChip8"));
+}
+
+#[test]
+fn test_lookup() {
+    let database = RomDatabase::parse(SAMPLE).unwrap();
+    assert_eq!(database.len(), 2);
+    let info = database.lookup("a9993e364706816aba3e25717850c26c9cd0d89d").unwrap();
+    assert_eq!(info.title, "Sample");
+    assert_eq!(info.platform(), Some(Platform::SuperChip));
+    assert_eq!(info.tickrate, Some(30));
+    let quirks = info.quirks.unwrap();
+    assert!(quirks.shift && !quirks.jump);
+    assert_eq!(info.palette.as_ref().unwrap().foreground(), Color::rgb(255, 0, 0));
+    assert_eq!(info.keys, vec![("left".to_string(), 7), ("right".to_string(), 9)]);
+
+    let unknown = database.lookup("da39a3ee5e6b4b0d3255bfef95601890afd80709").unwrap();
+    assert_eq!(unknown.platform(), None);
+    assert_eq!(unknown.quirks, None);
+    assert!(database.lookup("0000").is_none());
+}
+
+#[test]
+fn test_merge_overrides() {
+    let mut database = RomDatabase::parse(SAMPLE).unwrap();
+    let user = r#"[{"title": "Mine", "roms": {"a9993e364706816aba3e25717850c26c9cd0d89d": {"tickrate": 200}}}]"#;
+    database.merge(RomDatabase::parse(user).unwrap());
+    let info = database.lookup("a9993e364706816aba3e25717850c26c9cd0d89d").unwrap();
+    assert_eq!(info.title, "Mine");
+    assert_eq!(info.tickrate, Some(200));
+    assert_eq!(database.len(), 2);
+}
+
+#[test]
+fn test_format_errors() {
+    assert!(RomDatabase::parse("{}").is_err());
+    assert!(RomDatabase::parse(r#"[{"title": "x"}]"#).is_err());
+    assert!(RomDatabase::parse("[").is_err());
+}
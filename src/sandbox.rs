@@ -0,0 +1,248 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use cpu::CPU;
+
+// Runs a ROM unattended until it finishes, gets stuck or uses up its budget,
+// for automated runs that have nobody watching the screen:
+//
+//     let outcome = Sandbox::new(15).frames(600).timeout(Duration::from_secs(5))
+//         .run(&mut cpu, |cpu| recorder.capture(cpu.display()));
+//
+// The CPU's keyboard should be closed, as it is headless, so that a ROM
+// waiting for a key waits forever.
+pub struct Sandbox {
+    ipf: u64,
+    instructions: Option<u64>,
+    frames: Option<u64>,
+    timeout: Option<Duration>,
+    ran: u64,
+    frames_run: u64,
+}
+
+// What stopped a run.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RunOutcome {
+    // Jumps to itself at the address, or keeps coming back to the same state
+    // there, so nothing new will ever happen.
+    Halted(u16),
+    // Waits at the address (FX0A) for a key that nothing will press.
+    WaitingForKey(u16),
+    // Still going when one of the budgets ran out.
+    BudgetExhausted(Limit),
+    // Faulted at the address.
+    Error(u16, String),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Limit {
+    Instructions,
+    Frames,
+    Time,
+}
+
+impl fmt::Display for RunOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RunOutcome::Halted(pc) => write!(f, "halted at 0x{:03X}", pc),
+            RunOutcome::WaitingForKey(pc) => write!(f, "waiting for a key at 0x{:03X}", pc),
+            RunOutcome::BudgetExhausted(Limit::Instructions) => write!(f, "ran out of instructions"),
+            RunOutcome::BudgetExhausted(Limit::Frames) => write!(f, "ran out of frames"),
+            RunOutcome::BudgetExhausted(Limit::Time) => write!(f, "timed out"),
+            RunOutcome::Error(pc, ref message) => write!(f, "{} at 0x{:03X}", message, pc),
+        }
+    }
+}
+
+// Everything the next frame depends on, bar the random number generator
+// and the keyboard.
+#[derive(PartialEq)]
+struct State {
+    pc: u16,
+    i: u16,
+    regs: [u8; 16],
+    stack: Vec<u16>,
+    timers: (u8, u8),
+    display: [u64; 32],
+    memory: Vec<u8>,
+}
+
+impl State {
+    fn capture(cpu: &CPU) -> State {
+        let mut regs = [0; 16];
+        for (x, reg) in regs.iter_mut().enumerate() {
+            *reg = cpu.get_reg(x);
+        }
+        State {
+            pc: cpu.get_pc(),
+            i: cpu.get_i(),
+            regs,
+            stack: cpu.get_stack().to_vec(),
+            timers: (cpu.get_delay(), cpu.get_sound()),
+            display: cpu.get_display(),
+            memory: cpu.memory().to_vec(),
+        }
+    }
+
+    // Compares without copying memory, cheapest checks first.
+    fn matches(&self, cpu: &CPU) -> bool {
+        self.pc == cpu.get_pc()
+            && self.i == cpu.get_i()
+            && (0..16).all(|x| self.regs[x] == cpu.get_reg(x))
+            && self.timers == (cpu.get_delay(), cpu.get_sound())
+            && self.stack == cpu.get_stack()
+            && self.display == cpu.get_display()
+            && self.memory == cpu.memory()
+    }
+}
+
+// Spots a run going round in circles, from the state at the end of each
+// frame. Brent's algorithm: keep one state to compare the later ones with,
+// and replace it after a power of two frames, doubling each time, which
+// finds a cycle of any length in time proportional to it.
+struct LoopDetector {
+    saved: Option<State>,
+    draws: u64,
+    power: u64,
+    length: u64,
+}
+
+impl LoopDetector {
+    fn new() -> LoopDetector {
+        LoopDetector { saved: None, draws: 0, power: 1, length: 0 }
+    }
+
+    // Whether the CPU is back in a state it was in before.
+    fn check(&mut self, cpu: &CPU) -> bool {
+        // Once random numbers are drawn, the same state needn't lead to the
+        // same place, so start over.
+        if cpu.random_draws() != self.draws {
+            self.draws = cpu.random_draws();
+            self.saved = None;
+            self.power = 1;
+        }
+        match self.saved {
+            Some(ref saved) if saved.matches(cpu) => return true,
+            Some(_) if self.length < self.power => {}
+            Some(_) => {
+                self.power *= 2;
+                self.saved = Some(State::capture(cpu));
+                self.length = 0;
+            }
+            None => {
+                self.saved = Some(State::capture(cpu));
+                self.length = 0;
+            }
+        }
+        self.length += 1;
+        false
+    }
+}
+
+// Whether the instruction at PC is a jump to itself, as ROMs do once they're
+// done.
+pub fn is_halted(cpu: &CPU) -> bool {
+    let pc = cpu.get_pc();
+    pc < 0x1000 && opcode(cpu) == 0x1000 | pc
+}
+
+// Whether the instruction at PC waits for a key.
+pub fn is_key_wait(cpu: &CPU) -> bool {
+    opcode(cpu) & 0xF0FF == 0xF00A
+}
+
+// Whether the instruction at PC checks a key (EX9E/EXA1).
+pub fn is_key_read(cpu: &CPU) -> bool {
+    let opcode = opcode(cpu) & 0xF0FF;
+    opcode == 0xE09E || opcode == 0xE0A1
+}
+
+fn opcode(cpu: &CPU) -> u16 {
+    let pc = cpu.get_pc() as usize;
+    (cpu.get_mem8(pc) as u16) << 8 | cpu.get_mem8(pc + 1) as u16
+}
+
+impl Sandbox {
+    // Unlimited, running `ipf` instructions per frame.
+    pub fn new(ipf: u64) -> Sandbox {
+        Sandbox {
+            ipf: ipf.max(1),
+            instructions: None,
+            frames: None,
+            timeout: None,
+            ran: 0,
+            frames_run: 0,
+        }
+    }
+
+    pub fn instructions(mut self, instructions: u64) -> Sandbox {
+        self.instructions = Some(instructions);
+        self
+    }
+
+    pub fn frames(mut self, frames: u64) -> Sandbox {
+        self.frames = Some(frames);
+        self
+    }
+
+    // Wall-clock time, checked at the end of every frame.
+    pub fn timeout(mut self, timeout: Duration) -> Sandbox {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    // Instructions run so far, counting a frame that faulted as run in full.
+    pub fn instructions_run(&self) -> u64 {
+        self.ran
+    }
+
+    pub fn frames_run(&self) -> u64 {
+        self.frames_run
+    }
+
+    // Runs `cpu` a frame at a time, calling `frame` at the end of each,
+    // until it stops or a budget runs out. Runs from the block cache, so
+    // halts and key waits are noticed at the end of the frame they start
+    // in.
+    pub fn run<F>(&mut self, cpu: &mut CPU, mut frame: F) -> RunOutcome
+    where
+        F: FnMut(&CPU),
+    {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut detector = LoopDetector::new();
+        loop {
+            if let Some(fault) = cpu.fault() {
+                return RunOutcome::Error(cpu.get_pc(), fault.to_string());
+            }
+            if is_halted(cpu) {
+                return RunOutcome::Halted(cpu.get_pc());
+            }
+            if is_key_wait(cpu) {
+                return RunOutcome::WaitingForKey(cpu.get_pc());
+            }
+            if self.frames.is_some_and(|frames| self.frames_run >= frames) {
+                return RunOutcome::BudgetExhausted(Limit::Frames);
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return RunOutcome::BudgetExhausted(Limit::Time);
+            }
+            let left = self.instructions.map_or(self.ipf, |instructions| instructions - self.ran);
+            if left == 0 {
+                return RunOutcome::BudgetExhausted(Limit::Instructions);
+            }
+            let count = left.min(self.ipf);
+            cpu.run_cycles(count);
+            self.ran += count;
+            if count < self.ipf {
+                // A part frame, to use up the budget exactly.
+                continue;
+            }
+            cpu.end_frame();
+            self.frames_run += 1;
+            frame(cpu);
+            if cpu.fault().is_none() && detector.check(cpu) {
+                return RunOutcome::Halted(cpu.get_pc());
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use framebuffer::Framebuffer;
+use palette::{Color, Palette};
+use png;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ImageFormat {
+    Pbm,
+    Ppm,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "pbm" => Some(ImageFormat::Pbm),
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+// Pixels of a rendered screenshot are the screen's color indices, except
+// grid lines, which use the first index past them (`colors`).
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub colors: usize,
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Screenshot {
+    pub scale: usize,
+    pub palette: Palette,
+    pub grid: Option<Color>,
+}
+
+impl Default for Screenshot {
+    fn default() -> Screenshot {
+        Screenshot {
+            scale: 8,
+            palette: Palette::default(),
+            grid: None,
+        }
+    }
+}
+
+impl Screenshot {
+    // Grid lines take the last row and column of every scaled pixel, so they
+    // are only drawn when there is room for them.
+    pub fn render(&self, screen: &dyn Framebuffer) -> Image {
+        let scale = self.scale.max(1);
+        let grid = self.grid.is_some() && scale > 1;
+        let colors = screen.colors();
+        let (width, height) = (screen.width() * scale, screen.height() * scale);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                if grid && (x % scale == scale - 1 || y % scale == scale - 1) {
+                    pixels.push(colors as u8);
+                } else {
+                    pixels.push(screen.color_index(x / scale, y / scale));
+                }
+            }
+        }
+        Image {
+            width,
+            height,
+            colors,
+            pixels,
+        }
+    }
+
+    pub fn encode(&self, screen: &dyn Framebuffer, format: ImageFormat) -> Vec<u8> {
+        let image = self.render(screen);
+        let colors = self.colors(image.colors);
+        match format {
+            ImageFormat::Pbm => encode_pbm(&image),
+            ImageFormat::Ppm => encode_ppm(&image, &colors),
+            ImageFormat::Png => png::encode_indexed(image.width, image.height, &colors, &image.pixels),
+        }
+    }
+
+    // Writes `screen` to `path`, picking the format from the file extension.
+    pub fn save<P: AsRef<Path>>(&self, screen: &dyn Framebuffer, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let format = ImageFormat::from_path(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown image format for {}", path.display()),
+            )
+        })?;
+        let mut file = File::create(path)?;
+        file.write_all(&self.encode(screen, format))
+    }
+
+    // The palette's colors for each screen color, followed by the grid color.
+    pub fn colors(&self, screen_colors: usize) -> Vec<Color> {
+        let mut colors: Vec<Color> = (0..screen_colors).map(|i| self.palette.color(i)).collect();
+        colors.push(self.grid.unwrap_or_else(|| self.palette.background()));
+        colors
+    }
+}
+
+// PBM has no colors: lit pixels of any color are written as black ink on
+// white paper, and grid lines are left out.
+fn encode_pbm(image: &Image) -> Vec<u8> {
+    let mut out = format!("P4\n{} {}\n", image.width, image.height).into_bytes();
+    for row in image.pixels.chunks(image.width) {
+        for byte in row.chunks(8) {
+            let mut bits = 0u8;
+            for (i, &pixel) in byte.iter().enumerate() {
+                if pixel != 0 && (pixel as usize) < image.colors {
+                    bits |= 0x80 >> i;
+                }
+            }
+            out.push(bits);
+        }
+    }
+    out
+}
+
+fn encode_ppm(image: &Image, colors: &[Color]) -> Vec<u8> {
+    let mut out = format!("P6\n{} {}\n255\n", image.width, image.height).into_bytes();
+    for &pixel in image.pixels.iter() {
+        let color = colors[pixel as usize];
+        out.extend_from_slice(&[color.r, color.g, color.b]);
+    }
+    out
+}
+
+// Finds the first `<prefix>-<n>.<ext>` in `dir` that doesn't exist yet.
+pub fn next_free_path(dir: &Path, prefix: &str, format: ImageFormat) -> PathBuf {
+    let ext = match format {
+        ImageFormat::Pbm => "pbm",
+        ImageFormat::Ppm => "ppm",
+        ImageFormat::Png => "png",
+    };
+    (0..)
+        .map(|n| dir.join(format!("{}-{}.{}", prefix, n, ext)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+use display::Display;
+
+#[test]
+fn test_format_from_path() {
+    assert_eq!(ImageFormat::from_path(Path::new("a.PNG")), Some(ImageFormat::Png));
+    assert_eq!(ImageFormat::from_path(Path::new("a.pbm")), Some(ImageFormat::Pbm));
+    assert_eq!(ImageFormat::from_path(Path::new("a.ppm")), Some(ImageFormat::Ppm));
+    assert_eq!(ImageFormat::from_path(Path::new("a.gif")), None);
+    assert_eq!(ImageFormat::from_path(Path::new("a")), None);
+}
+
+#[test]
+fn test_pbm() {
+    let mut display = Display::init();
+    display.set_sprite(0, 0, &[0xA0]);
+    let shot = Screenshot {
+        scale: 1,
+        ..Screenshot::default()
+    };
+    let pbm = shot.encode(&display, ImageFormat::Pbm);
+    let header = b"P4\n64 32\n";
+    assert_eq!(&pbm[..header.len()], header);
+    assert_eq!(pbm.len(), header.len() + 8 * 32);
+    assert_eq!(pbm[header.len()], 0xA0);
+    assert_eq!(pbm[header.len() + 8], 0x00);
+}
+
+#[test]
+fn test_ppm_scale_and_grid() {
+    let mut display = Display::init();
+    display.set_sprite(0, 0, &[0x80]);
+    let shot = Screenshot {
+        scale: 2,
+        palette: Palette::new(Color::rgb(1, 2, 3), Color::rgb(4, 5, 6)),
+        grid: Some(Color::rgb(7, 8, 9)),
+    };
+    let image = shot.render(&display);
+    assert_eq!((image.width, image.height), (128, 64));
+    assert_eq!(&image.pixels[..4], &[1, 2, 0, 2]);
+    assert!(image.pixels[128..256].iter().all(|&p| p == 2));
+
+    let ppm = shot.encode(&display, ImageFormat::Ppm);
+    let header = b"P6\n128 64\n255\n";
+    assert_eq!(&ppm[header.len()..header.len() + 9], &[4, 5, 6, 7, 8, 9, 1, 2, 3]);
+}
+
+#[test]
+fn test_multiplane_png_palette() {
+    use framebuffer::Frame;
+
+    let mut frame = Frame::new(128, 64, 2);
+    frame.set_color_index(0, 0, 3);
+    let shot = Screenshot {
+        scale: 1,
+        ..Screenshot::default()
+    };
+    let image = shot.render(&frame);
+    assert_eq!((image.width, image.height, image.colors), (128, 64, 4));
+    assert_eq!(image.pixels[0], 3);
+    assert_eq!(shot.colors(image.colors).len(), 5);
+    let pbm = shot.encode(&frame, ImageFormat::Pbm);
+    assert_eq!(pbm[b"P4\n128 64\n".len()], 0x80);
+}
@@ -0,0 +1,279 @@
+extern crate termios;
+
+use std::cell::Cell;
+use std::io;
+use std::io::{Read, Write};
+
+use self::termios::{tcsetattr, Termios, ECHO, ICANON, TCSANOW, VMIN, VTIME};
+
+use displayimpl::{AsciiDisplay, DisplayImpl};
+use framebuffer::Framebuffer;
+use palette::Palette;
+use phosphor::Shades;
+
+// Sixel images are written in bands of six pixel rows, one character per
+// column, with the low bit of each character being the topmost row.
+const BAND: usize = 6;
+
+// Number of color registers used between background and foreground when
+// drawing shaded frames.
+const SHADE_LEVELS: usize = 8;
+
+pub struct SixelDisplay {
+    scale: usize,
+    palette: Palette,
+    cleared: Cell<bool>,
+}
+
+impl SixelDisplay {
+    pub fn new(scale: usize, palette: Palette) -> SixelDisplay {
+        SixelDisplay {
+            scale: scale.max(1),
+            palette,
+            cleared: Cell::new(false),
+        }
+    }
+
+    // Uses one color register per color index the screen can show.
+    pub fn encode(&self, screen: &dyn Framebuffer) -> String {
+        let palette = Palette::from_colors((0..screen.colors().max(2)).map(|i| self.palette.color(i)).collect());
+        encode_image(screen.width() * self.scale, screen.height() * self.scale, &palette, |x, y| {
+            screen.color_index(x / self.scale, y / self.scale) as usize
+        })
+    }
+
+    pub fn encode_shades(&self, shades: &Shades) -> String {
+        let (bg, fg) = (self.palette.background(), self.palette.foreground());
+        let ramp = Palette::from_colors(
+            (0..SHADE_LEVELS)
+                .map(|level| bg.mix(&fg, (level * 255 / (SHADE_LEVELS - 1)) as u8))
+                .collect(),
+        );
+        encode_image(shades.width() * self.scale, shades.height() * self.scale, &ramp, |x, y| {
+            let shade = shades.get(y / self.scale, x / self.scale) as usize;
+            (shade * (SHADE_LEVELS - 1) + 127) / 255
+        })
+    }
+
+    fn write_frame(&self, image: &str, keys: &[bool; 16]) {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        if !self.cleared.get() {
+            let _ = write!(out, "\x1b[2J");
+            self.cleared.set(true);
+        }
+        let _ = write!(out, "\x1b[H{}\n{}\n", image, self.keys_to_ascii(keys));
+        let _ = out.flush();
+    }
+
+    fn keys_to_ascii(&self, keys: &[bool; 16]) -> String {
+        keys.iter()
+            .map(|&key| if key { '*' } else { '_' })
+            .collect()
+    }
+}
+
+impl DisplayImpl for SixelDisplay {
+    fn draw(&self, screen: &dyn Framebuffer, keys: &[bool; 16]) {
+        self.write_frame(&self.encode(screen), keys);
+    }
+
+    fn draw_shades(&self, shades: &Shades, keys: &[bool; 16]) {
+        self.write_frame(&self.encode_shades(shades), keys);
+    }
+}
+
+// Encodes an image of `width` x `height` pixels whose color indices are given
+// by `pixel(x, y)`, using registers from `palette`.
+pub fn encode_image<F>(width: usize, height: usize, palette: &Palette, pixel: F) -> String
+where
+    F: Fn(usize, usize) -> usize,
+{
+    let mut out = String::new();
+    out.push_str(&format!("\x1bPq\"1;1;{};{}", width, height));
+    for (i, color) in palette.colors().iter().enumerate() {
+        let (r, g, b) = color.to_percent();
+        out.push_str(&format!("#{};2;{};{};{}", i, r, g, b));
+    }
+
+    let mut band = vec![0usize; width * BAND];
+    for top in (0..height).step_by(BAND) {
+        let band_height = BAND.min(height - top);
+        for dy in 0..band_height {
+            for x in 0..width {
+                band[dy * width + x] = pixel(x, top + dy).min(palette.len() - 1);
+            }
+        }
+
+        let mut first = true;
+        for color in 0..palette.len() {
+            let mut line = Vec::with_capacity(width);
+            let mut used = false;
+            for x in 0..width {
+                let mut bits = 0u8;
+                for dy in 0..band_height {
+                    if band[dy * width + x] == color {
+                        bits |= 1 << dy;
+                    }
+                }
+                used |= bits != 0;
+                line.push((63 + bits) as char);
+            }
+            if !used {
+                continue;
+            }
+            if !first {
+                out.push('$');
+            }
+            first = false;
+            out.push_str(&format!("#{}", color));
+            push_run_length(&mut out, &line);
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+fn push_run_length(out: &mut String, line: &[char]) {
+    let mut i = 0;
+    while i < line.len() {
+        let c = line[i];
+        let mut run = 1;
+        while i + run < line.len() && line[i + run] == c {
+            run += 1;
+        }
+        if run > 3 {
+            out.push_str(&format!("!{}{}", run, c));
+        } else {
+            for _ in 0..run {
+                out.push(c);
+            }
+        }
+        i += run;
+    }
+}
+
+// Parses a primary device attributes reply (`ESC [ ? 62 ; 4 ; 22 c`);
+// attribute 4 advertises sixel graphics.
+pub fn parse_device_attributes(reply: &[u8]) -> bool {
+    let reply = String::from_utf8_lossy(reply);
+    let start = match reply.find("\x1b[?") {
+        Some(start) => start + 3,
+        None => return false,
+    };
+    let body = &reply[start..];
+    let end = match body.find('c') {
+        Some(end) => end,
+        None => return false,
+    };
+    body[..end].split(';').any(|attr| attr == "4")
+}
+
+// Asks the terminal on stdin/stdout for its device attributes. Gives up after
+// a short timeout, so terminals that never answer count as unsupported.
+pub fn terminal_supports_sixel() -> bool {
+    let stdin = 0;
+    let original = match Termios::from_fd(stdin) {
+        Ok(termios) => termios,
+        Err(_) => return false,
+    };
+    let mut raw = original;
+    raw.c_lflag &= !(ICANON | ECHO);
+    raw.c_cc[VMIN] = 0;
+    raw.c_cc[VTIME] = 2;
+    if tcsetattr(stdin, TCSANOW, &raw).is_err() {
+        return false;
+    }
+
+    let mut reply = Vec::new();
+    {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        let _ = out.write_all(b"\x1b[c");
+        let _ = out.flush();
+    }
+    let mut buffer = [0u8; 1];
+    let stdin_handle = io::stdin();
+    let mut input = stdin_handle.lock();
+    while let Ok(1) = input.read(&mut buffer) {
+        reply.push(buffer[0]);
+        if buffer[0] == b'c' || reply.len() > 64 {
+            break;
+        }
+    }
+
+    let _ = tcsetattr(stdin, TCSANOW, &original);
+    parse_device_attributes(&reply)
+}
+
+// Picks the sixel renderer when the terminal supports it and falls back to
+// character cells otherwise.
+pub fn detect_renderer(scale: usize, palette: Palette) -> Box<dyn DisplayImpl + Send> {
+    if terminal_supports_sixel() {
+        Box::new(SixelDisplay::new(scale, palette))
+    } else {
+        Box::new(AsciiDisplay())
+    }
+}
+
+#[cfg(test)]
+use display::Display;
+
+#[test]
+fn test_parse_device_attributes() {
+    assert!(parse_device_attributes(b"\x1b[?62;4;22c"));
+    assert!(parse_device_attributes(b"\x1b[?4c"));
+    assert!(!parse_device_attributes(b"\x1b[?62;22c"));
+    assert!(!parse_device_attributes(b"\x1b[?1;2c"));
+    assert!(!parse_device_attributes(b""));
+}
+
+#[test]
+fn test_encode_blank() {
+    let sixel = SixelDisplay::new(1, Palette::default());
+    let out = sixel.encode(&Display::init());
+    assert!(out.starts_with("\x1bPq\"1;1;64;32#0;2;0;0;0#1;2;100;100;100"));
+    assert!(out.ends_with("\x1b\\"));
+    // Six bands, each a single run of background.
+    assert_eq!(out.matches("#0!64~-").count(), 5);
+    assert_eq!(out.matches("#0!64B-").count(), 1);
+}
+
+#[test]
+fn test_encode_shades() {
+    let mut display = Display::init();
+    display.set_sprite(0, 0, &[0x80]);
+    let sixel = SixelDisplay::new(1, Palette::default());
+    let out = sixel.encode_shades(&Shades::from_framebuffer(&display));
+    assert!(out.contains("#7;2;100;100;100"));
+    assert!(out.contains("#0}!63~$#7@!63?-"));
+}
+
+#[test]
+fn test_encode_pixel() {
+    let mut display = Display::init();
+    display.set_sprite(0, 0, &[0x80]);
+    let sixel = SixelDisplay::new(2, Palette::default());
+    let out = sixel.encode(&display);
+    // The top-left 2x2 block is lit; the rest of the first band is not.
+    assert!(out.contains("#0{{!126~$#1BB!126?-"));
+}
+
+#[test]
+fn test_encode_multiplane() {
+    use framebuffer::Frame;
+    use palette::Color;
+
+    let mut frame = Frame::new(2, 1, 2);
+    frame.set_color_index(1, 0, 3);
+    let palette = Palette::from_colors(vec![
+        Color::rgb(0, 0, 0),
+        Color::rgb(255, 0, 0),
+        Color::rgb(0, 255, 0),
+        Color::rgb(0, 0, 255),
+    ]);
+    let out = SixelDisplay::new(1, palette).encode(&frame);
+    assert!(out.starts_with("\x1bPq\"1;1;2;1#0;2;0;0;0#1;2;100;0;0#2;2;0;100;0#3;2;0;0;100"));
+    assert!(out.contains("#0@?$#3?@-"));
+}
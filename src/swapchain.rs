@@ -0,0 +1,138 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use display::Display;
+
+// A triple buffer handing finished frames from the emulation thread to a
+// renderer. The publisher always owns one slot to write into, the reader owns
+// one to read from, and the third ("back") slot is swapped atomically between
+// them, so neither side ever waits for the other.
+//
+// The back index carries a flag saying whether it holds a frame the reader
+// hasn't picked up yet.
+const INDEX_MASK: usize = 0b11;
+const FRESH: usize = 0b100;
+
+struct Shared {
+    slots: [UnsafeCell<Display>; 3],
+    back: AtomicUsize,
+}
+
+// Each slot is only ever accessed by the side currently owning its index.
+unsafe impl Sync for Shared {}
+
+pub struct FramePublisher {
+    shared: Arc<Shared>,
+    write: usize,
+}
+
+pub struct FrameReader {
+    shared: Arc<Shared>,
+    read: usize,
+}
+
+pub fn swap_chain() -> (FramePublisher, FrameReader) {
+    let shared = Arc::new(Shared {
+        slots: [
+            UnsafeCell::new(Display::init()),
+            UnsafeCell::new(Display::init()),
+            UnsafeCell::new(Display::init()),
+        ],
+        back: AtomicUsize::new(1),
+    });
+    let publisher = FramePublisher {
+        shared: shared.clone(),
+        write: 0,
+    };
+    let reader = FrameReader { shared, read: 2 };
+    (publisher, reader)
+}
+
+impl FramePublisher {
+    // Copies `frame` into the write slot and makes it the newest frame,
+    // replacing any the reader hasn't seen yet.
+    pub fn publish(&mut self, frame: &Display) {
+        unsafe {
+            (*self.shared.slots[self.write].get()).clone_from(frame);
+        }
+        let previous = self.shared.back.swap(self.write | FRESH, Ordering::AcqRel);
+        self.write = previous & INDEX_MASK;
+    }
+}
+
+impl FrameReader {
+    pub fn has_new_frame(&self) -> bool {
+        self.shared.back.load(Ordering::Acquire) & FRESH != 0
+    }
+
+    // Returns the newest published frame if there is one the reader hasn't
+    // seen yet.
+    pub fn new_frame(&mut self) -> Option<&Display> {
+        if !self.has_new_frame() {
+            return None;
+        }
+        let previous = self.shared.back.swap(self.read, Ordering::AcqRel);
+        self.read = previous & INDEX_MASK;
+        Some(self.current())
+    }
+
+    // The frame last returned by `new_frame`, or a blank screen before that.
+    pub fn current(&self) -> &Display {
+        unsafe { &*self.shared.slots[self.read].get() }
+    }
+}
+
+#[test]
+fn test_publish_and_read() {
+    let (mut publisher, mut reader) = swap_chain();
+    assert!(reader.new_frame().is_none());
+
+    let mut display = Display::init();
+    display.set_sprite(0, 0, &[0x80]);
+    publisher.publish(&display);
+    assert!(reader.has_new_frame());
+    assert!(reader.new_frame().unwrap().get_pixel(0, 0));
+    assert!(reader.new_frame().is_none());
+    assert!(reader.current().get_pixel(0, 0));
+}
+
+#[test]
+fn test_reader_skips_to_latest() {
+    let (mut publisher, mut reader) = swap_chain();
+    let mut display = Display::init();
+    for col in 0..5 {
+        display.set_sprite(0, col, &[0x80]);
+        publisher.publish(&display);
+    }
+    let frame = reader.new_frame().unwrap();
+    assert_eq!(frame, &display);
+}
+
+#[test]
+fn test_concurrent_frames_are_whole() {
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    let (mut publisher, mut reader) = swap_chain();
+    let done = Arc::new(AtomicBool::new(false));
+    let writer_done = done.clone();
+    let writer = thread::spawn(move || {
+        for n in 0..5000u64 {
+            // Every published frame has all rows equal.
+            publisher.publish(&Display::from_rows([n << 32 | n; 32]));
+        }
+        writer_done.store(true, Ordering::Release);
+    });
+    let mut last = 0;
+    while !done.load(Ordering::Acquire) || reader.has_new_frame() {
+        if let Some(frame) = reader.new_frame() {
+            let rows = frame.get_display();
+            assert!(rows.iter().all(|&row| row == rows[0]));
+            assert!(rows[0] >= last);
+            last = rows[0];
+        }
+    }
+    writer.join().unwrap();
+    assert_eq!(reader.current().get_display()[0], 4999 << 32 | 4999);
+}
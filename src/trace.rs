@@ -0,0 +1,520 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use cpu::CPU;
+use harness::Machine;
+use instruction::Instruction;
+
+// Steps shown in a divergence report, the diverging one included.
+const CONTEXT: usize = 6;
+
+// One instruction from a trace: where it ran and the registers after it.
+// Whatever the trace didn't log is `None` and isn't compared.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Step {
+    pub pc: u16,
+    pub opcode: Option<u16>,
+    pub v: [Option<u8>; 16],
+    pub i: Option<u16>,
+    pub delay: Option<u8>,
+    pub sound: Option<u8>,
+}
+
+impl Step {
+    // The instruction `opcode` at `pc`, with `cpu` as it left things.
+    pub fn capture(pc: u16, opcode: u16, cpu: &CPU) -> Step {
+        let mut v = [None; 16];
+        for (x, reg) in v.iter_mut().enumerate() {
+            *reg = Some(cpu.get_reg(x));
+        }
+        Step {
+            pc,
+            opcode: Some(opcode),
+            v,
+            i: Some(cpu.get_i()),
+            delay: Some(cpu.get_delay()),
+            sound: Some(cpu.get_sound()),
+        }
+    }
+
+    // The registers as `rust8 trace` prints them, `--` where unknown.
+    pub fn registers(&self) -> String {
+        let regs: Vec<String> = self.v.iter().map(|&reg| hex(reg, 2)).collect();
+        format!(
+            "V {} I {} DT {} ST {}",
+            regs.join(" "),
+            hex(self.i, 3),
+            hex(self.delay, 2),
+            hex(self.sound, 2)
+        )
+    }
+
+    // The first register this has that differs from `expected`, if any.
+    fn differs(&self, expected: &Step) -> Option<String> {
+        let fields = (0..16)
+            .map(|x| (format!("V{:X}", x), self.v[x].map(u16::from), expected.v[x].map(u16::from)))
+            .chain(vec![
+                ("I".to_string(), self.i, expected.i),
+                ("DT".to_string(), self.delay.map(u16::from), expected.delay.map(u16::from)),
+                ("ST".to_string(), self.sound.map(u16::from), expected.sound.map(u16::from)),
+            ]);
+        for (name, ours, theirs) in fields {
+            if let (Some(ours), Some(theirs)) = (ours, theirs) {
+                if ours != theirs {
+                    return Some(format!("{} is 0x{:02X} here but 0x{:02X} in the trace", name, ours, theirs));
+                }
+            }
+        }
+        None
+    }
+
+    fn copy_registers(&mut self, from: &Step) {
+        self.v = from.v;
+        self.i = from.i;
+        self.delay = from.delay;
+        self.sound = from.sound;
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:03X} {} {}", self.pc, hex(self.opcode, 4), self.registers())
+    }
+}
+
+fn hex<T: Into<u16>>(value: Option<T>, width: usize) -> String {
+    match value {
+        Some(value) => format!("{:0width$X}", value.into(), width = width),
+        None => "-".repeat(width),
+    }
+}
+
+// A line of `rust8 trace` output.
+pub fn line(pc: u16, opcode: u16, cpu: &CPU) -> String {
+    let registers = Step::capture(pc, opcode, cpu).registers();
+    format!("{:03X} {:04X} {:<18} {}", pc, opcode, Instruction::decode(opcode).to_string(), registers)
+}
+
+// How a trace's lines are laid out.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Layout {
+    // `rust8 trace` output.
+    Rust8,
+    // NAME=VALUE or NAME: VALUE pairs in any order, as most emulators' debug
+    // logs have them. Other words on the line are ignored.
+    Keyed,
+    // Comma-separated, with a header line naming the columns.
+    Csv,
+}
+
+// A trace's layout and whether it logs the registers before each
+// instruction rather than after.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Format {
+    pub layout: Layout,
+    pub before: bool,
+}
+
+impl Format {
+    // `rust8`, `keyed` or `csv`, with `,before` for registers logged before
+    // each instruction.
+    pub fn parse(spec: &str) -> Option<Format> {
+        let (name, before) = match spec.strip_suffix(",before") {
+            Some(name) => (name, true),
+            None => (spec, false),
+        };
+        let layout = match name {
+            "rust8" => Layout::Rust8,
+            "keyed" => Layout::Keyed,
+            "csv" => Layout::Csv,
+            _ => return None,
+        };
+        Some(Format { layout, before })
+    }
+
+    // Guesses the layout from the first line. Whether registers come before
+    // or after can't be told, so they're taken to come after.
+    pub fn detect(text: &str) -> Format {
+        let layout = match lines(text).next() {
+            Some((_, line)) if line.contains(',') && header(line).contains(&Some(Field::Pc)) => Layout::Csv,
+            Some((_, line)) if parse_rust8(line).is_ok() => Layout::Rust8,
+            _ => Layout::Keyed,
+        };
+        Format { layout, before: false }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self.layout {
+            Layout::Rust8 => "rust8",
+            Layout::Keyed => "keyed",
+            Layout::Csv => "csv",
+        };
+        write!(f, "{}{}", name, if self.before { ",before" } else { "" })
+    }
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(PathBuf, io::Error),
+    Syntax { line: usize, message: String },
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TraceError::Io(ref path, ref err) => write!(f, "couldn't read {}: {}", path.display(), err),
+            TraceError::Syntax { line, ref message } => write!(f, "trace line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for TraceError {}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Field {
+    Pc,
+    Opcode,
+    V(usize),
+    I,
+    Delay,
+    Sound,
+}
+
+fn field(name: &str) -> Option<Field> {
+    let name = name.trim().to_ascii_lowercase();
+    match name.as_str() {
+        "pc" => Some(Field::Pc),
+        "op" | "opcode" => Some(Field::Opcode),
+        "i" | "index" => Some(Field::I),
+        "dt" | "delay" => Some(Field::Delay),
+        "st" | "sound" => Some(Field::Sound),
+        _ => match name.strip_prefix('v') {
+            Some(x) if x.len() == 1 => usize::from_str_radix(x, 16).ok().map(Field::V),
+            _ => None,
+        },
+    }
+}
+
+// Traces log numbers in hex, with or without a `0x` or `$`.
+fn parse_hex(text: &str) -> Option<u16> {
+    let text = text.trim();
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('$'))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn set(step: &mut Step, field: Field, text: &str) -> Result<(), String> {
+    let bad = || format!("bad value \"{}\"", text);
+    let value = parse_hex(text).ok_or_else(bad)?;
+    let byte = || if value <= 0xFF { Ok(Some(value as u8)) } else { Err(bad()) };
+    match field {
+        Field::Pc => step.pc = value,
+        Field::Opcode => step.opcode = Some(value),
+        Field::V(x) => step.v[x] = byte()?,
+        Field::I => step.i = Some(value),
+        Field::Delay => step.delay = byte()?,
+        Field::Sound => step.sound = byte()?,
+    }
+    Ok(())
+}
+
+// Numbered lines with blank lines and comments left out.
+fn lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines().enumerate().map(|(n, line)| (n + 1, line.trim())).filter(|&(_, line)| {
+        !line.is_empty() && !line.starts_with('#') && !line.starts_with(';') && !line.starts_with("//")
+    })
+}
+
+fn parse_rust8(line: &str) -> Result<Step, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let bad = || "expected PC, opcode, instruction, then V, I, DT and ST".to_string();
+    let v = words.iter().rposition(|&word| word == "V").ok_or_else(bad)?;
+    if words.len() < 2 || words.len() != v + 23 {
+        return Err(bad());
+    }
+    let mut step = Step::default();
+    set(&mut step, Field::Pc, words[0])?;
+    set(&mut step, Field::Opcode, words[1])?;
+    for x in 0..16 {
+        set(&mut step, Field::V(x), words[v + 1 + x])?;
+    }
+    for pair in words[v + 17..].chunks(2) {
+        match field(pair[0]) {
+            Some(field @ Field::I) | Some(field @ Field::Delay) | Some(field @ Field::Sound) => {
+                set(&mut step, field, pair[1])?
+            }
+            _ => return Err(bad()),
+        }
+    }
+    Ok(step)
+}
+
+// Lines without a PC aren't steps, and are skipped.
+fn parse_keyed(line: &str) -> Result<Option<Step>, String> {
+    let mut step = Step::default();
+    let mut has_pc = false;
+    let mut words = line.split(|c: char| c.is_whitespace() || c == ',').filter(|word| !word.is_empty());
+    while let Some(word) = words.next() {
+        let (name, value) = match word.find(['=', ':']) {
+            Some(at) if at + 1 == word.len() => match words.next() {
+                Some(value) => (&word[..at], value),
+                None => break,
+            },
+            Some(at) => (&word[..at], &word[at + 1..]),
+            None => continue,
+        };
+        if let Some(field) = field(name) {
+            set(&mut step, field, value)?;
+            has_pc |= field == Field::Pc;
+        }
+    }
+    Ok(if has_pc { Some(step) } else { None })
+}
+
+fn header(line: &str) -> Vec<Option<Field>> {
+    line.split(',').map(field).collect()
+}
+
+// The instructions another emulator ran, in order.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Trace {
+    pub steps: Vec<Step>,
+}
+
+impl Trace {
+    pub fn parse(text: &str, format: Format) -> Result<Trace, TraceError> {
+        let mut steps = Vec::new();
+        let mut columns = None;
+        for (n, line) in lines(text) {
+            let step = match format.layout {
+                Layout::Rust8 => parse_rust8(line).map(Some),
+                Layout::Keyed => parse_keyed(line),
+                Layout::Csv => match columns {
+                    None => {
+                        columns = Some(header(line));
+                        continue;
+                    }
+                    Some(ref columns) => parse_csv(line, columns),
+                },
+            };
+            match step {
+                Ok(Some(step)) => steps.push(step),
+                Ok(None) => {}
+                Err(message) => return Err(TraceError::Syntax { line: n, message }),
+            }
+        }
+        // Registers logged before each instruction are the ones the
+        // instruction before it left.
+        if format.before {
+            for n in 0..steps.len() {
+                let next = steps.get(n + 1).cloned().unwrap_or_default();
+                steps[n].copy_registers(&next);
+            }
+        }
+        Ok(Trace { steps })
+    }
+
+    // Reads a trace, guessing its format if not given.
+    pub fn load<P: AsRef<Path>>(path: P, format: Option<Format>) -> Result<Trace, TraceError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|err| TraceError::Io(path.to_path_buf(), err))?;
+        Trace::parse(&text, format.unwrap_or_else(|| Format::detect(&text)))
+    }
+}
+
+fn parse_csv(line: &str, columns: &[Option<Field>]) -> Result<Option<Step>, String> {
+    let cells: Vec<&str> = line.split(',').collect();
+    if cells.len() != columns.len() {
+        return Err(format!("{} columns, but the header has {}", cells.len(), columns.len()));
+    }
+    let mut step = Step::default();
+    for (&column, cell) in columns.iter().zip(cells) {
+        if let Some(field) = column {
+            if !cell.trim().is_empty() {
+                set(&mut step, field, cell)?;
+            }
+        }
+    }
+    Ok(Some(step))
+}
+
+// Where a replay first went differently from its trace.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Divergence {
+    // The step's index in the trace.
+    pub step: usize,
+    pub message: String,
+    // The steps up to the diverging one as (index, ours, the trace's). Ours
+    // is missing where the CPU never got as far as running it.
+    pub context: Vec<(usize, Option<Step>, Step)>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "step {}: {}", self.step, self.message)?;
+        let width = Step::default().to_string().len();
+        writeln!(f, "{:>7} {:<width$} | trace", "", "rust8", width = width)?;
+        for &(n, ref ours, ref theirs) in self.context.iter() {
+            let mark = if n == self.step { '!' } else { ' ' };
+            let ours = ours.map(|step| step.to_string()).unwrap_or_default();
+            writeln!(f, "{}{:>6} {:<width$} | {}", mark, n, ours, theirs, width = width)?;
+        }
+        Ok(())
+    }
+}
+
+// Runs `machine` alongside `trace`, one instruction per step, stopping at
+// the first step where the PC, opcode or any register the trace logged
+// differs. Returns how many steps matched.
+//
+// Random numbers and keys come from the trace: CXNN takes the VX the trace
+// has, EX9E and EXA1 see the key held or not as the trace's next PC says,
+// and FX0A gets the key the trace has in VX, or keeps waiting if the trace
+// does.
+pub fn replay(machine: &mut Machine, trace: &Trace) -> Result<usize, Divergence> {
+    let mut context: Vec<(usize, Option<Step>, Step)> = Vec::new();
+    for (n, expected) in trace.steps.iter().enumerate() {
+        if context.len() == CONTEXT {
+            context.remove(0);
+        }
+        let pc = machine.cpu().get_pc();
+        let diverged = |context: &mut Vec<(usize, Option<Step>, Step)>, ours: Option<Step>, message: String| {
+            context.push((n, ours, *expected));
+            Err(Divergence {
+                step: n,
+                message,
+                context: context.clone(),
+            })
+        };
+        if pc as usize + 1 >= machine.cpu().memory().len() {
+            return diverged(&mut context, None, format!("PC 0x{:03X} is past the end of memory", pc));
+        }
+        let opcode = (machine.cpu().get_mem8(pc as usize) as u16) << 8 | machine.cpu().get_mem8(pc as usize + 1) as u16;
+        if pc != expected.pc {
+            let ours = Some(Step { pc, opcode: Some(opcode), ..Step::default() });
+            return diverged(&mut context, ours, format!("PC is 0x{:03X} here but 0x{:03X} in the trace", pc, expected.pc));
+        }
+        if let Some(theirs) = expected.opcode.filter(|&theirs| theirs != opcode) {
+            let ours = Some(Step { pc, opcode: Some(opcode), ..Step::default() });
+            let message = format!("the opcode at 0x{:03X} is {:04X} here but {:04X} in the trace", pc, opcode, theirs);
+            return diverged(&mut context, ours, message);
+        }
+        let next = trace.steps.get(n + 1).map(|step| step.pc);
+        supply_inputs(machine, pc, opcode, expected, next);
+        if machine.is_waiting_for_key() {
+            machine.idle();
+        } else if let Err(err) = panic::catch_unwind(AssertUnwindSafe(|| machine.step())) {
+            let message = err
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| err.downcast_ref::<&str>().map(|message| message.to_string()))
+                .unwrap_or_else(|| "unknown error".to_string());
+            return diverged(&mut context, None, format!("crashed: {}", message));
+        }
+        let x = ((opcode >> 8) & 0x0F) as usize;
+        if opcode & 0xF000 == 0xC000 {
+            if let Some(random) = expected.v[x] {
+                machine.cpu().set_reg(x, random);
+            }
+        }
+        let ours = Step::capture(pc, opcode, machine.cpu());
+        if let Some(message) = ours.differs(expected) {
+            return diverged(&mut context, Some(ours), message);
+        }
+        context.push((n, Some(ours), *expected));
+    }
+    Ok(trace.steps.len())
+}
+
+// Presses or releases keys so the instruction at `pc` sees what the trace
+// says it saw. `next` is the PC the trace goes on to.
+fn supply_inputs(machine: &mut Machine, pc: u16, opcode: u16, expected: &Step, next: Option<u16>) {
+    let x = ((opcode >> 8) & 0x0F) as usize;
+    match opcode & 0xF0FF {
+        0xE09E | 0xE0A1 => {
+            let key = machine.cpu().get_reg(x);
+            let skipped = match next {
+                Some(next) if next == pc + 4 => true,
+                Some(next) if next == pc + 2 => false,
+                _ => return,
+            };
+            if key > 0x0F {
+                return;
+            }
+            let held = skipped == (opcode & 0xFF == 0x9E);
+            if held && !machine.cpu().get_key(key as usize) {
+                machine.press(key);
+            } else if !held && machine.cpu().get_key(key as usize) {
+                machine.release(key);
+            }
+        }
+        0xF00A if next == Some(pc + 2) => {
+            if let Some(key) = expected.v[x].filter(|&key| key <= 0x0F) {
+                machine.press(key);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[test]
+fn test_parse_rust8() {
+    let text = "\
+200 6005 LD V5, 0x05         V 00 00 00 00 00 05 00 00 00 00 00 00 00 00 00 00 I 000 DT 00 ST 00
+202 A21E LD I, 0x21E         V 00 00 00 00 00 05 00 00 00 00 00 00 00 00 00 00 I 21E DT 00 ST 00
+";
+    assert_eq!(Format::detect(text), Format::parse("rust8").unwrap());
+    let trace = Trace::parse(text, Format::detect(text)).unwrap();
+    assert_eq!(trace.steps.len(), 2);
+    assert_eq!((trace.steps[0].pc, trace.steps[0].opcode), (0x200, Some(0x6005)));
+    assert_eq!((trace.steps[0].v[5], trace.steps[1].i), (Some(5), Some(0x21E)));
+    assert_eq!(trace.steps[1].to_string(), text.lines().nth(1).unwrap().replace("LD I, 0x21E         ", ""));
+}
+
+#[test]
+fn test_parse_keyed_before() {
+    let text = "\
+; registers before each instruction
+PC: 0x200 OP: 0x6005 V0=00 V5=00 I=0000
+PC: 0x202 OP: 0x7501 V0=00 V5=05 I=0000 DT=00
+cycle 3 PC=$204 V5=06
+";
+    let format = Format::parse("keyed,before").unwrap();
+    assert_eq!(Format::detect(text).layout, Layout::Keyed);
+    let trace = Trace::parse(text, format).unwrap();
+    assert_eq!(trace.steps.iter().map(|step| step.pc).collect::<Vec<_>>(), vec![0x200, 0x202, 0x204]);
+    assert_eq!((trace.steps[0].v[5], trace.steps[0].delay), (Some(5), Some(0)));
+    assert_eq!((trace.steps[1].v[5], trace.steps[1].i), (Some(6), None));
+    assert_eq!(trace.steps[2].v[5], None);
+    assert_eq!(format.to_string(), "keyed,before");
+}
+
+#[test]
+fn test_parse_csv() {
+    let text = "pc,opcode,v0,vf,i\n200,6001,01,,000\n0x202,8004,2,0,0\n";
+    assert_eq!(Format::detect(text).layout, Layout::Csv);
+    let trace = Trace::parse(text, Format::detect(text)).unwrap();
+    assert_eq!(trace.steps[0].v[0xF], None);
+    assert_eq!((trace.steps[1].pc, trace.steps[1].v[0]), (0x202, Some(2)));
+}
+
+#[test]
+fn test_trace_errors() {
+    let csv = Format::parse("csv").unwrap();
+    let err = Trace::parse("pc,v0\n200,100\n", csv).unwrap_err().to_string();
+    assert_eq!(err, "trace line 2: bad value \"100\"");
+    let err = Trace::parse("pc,v0\n200\n", csv).unwrap_err().to_string();
+    assert_eq!(err, "trace line 2: 1 columns, but the header has 2");
+    let rust8 = Format::parse("rust8").unwrap();
+    assert!(Trace::parse("200 6005 LD V5, 0x05 V 00\n", rust8).is_err());
+    assert!(Format::parse("bochs").is_none());
+}
@@ -0,0 +1,132 @@
+extern crate rust8;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rust8::asm;
+use rust8::displayimpl::screen_to_ascii;
+use rust8::harness::Harness;
+use rust8::platform::Platform;
+
+// Test ROMs in tests/conformance, as assembly (`.asm`) or ROMs (`.ch8`).
+// Each runs on every platform's quirks for `frames` frames, and must end on
+// the screen its header says it shows when every check passes.
+struct Fixture {
+    file: &'static str,
+    frames: u64,
+    // Pressed in turn as the ROM reads keys.
+    keys: &'static [u8],
+    // Pixels from one mark to the next. Marks run left to right from the
+    // top left corner, starting a new row six pixels down at the right edge.
+    step: usize,
+    // The marks shown when everything passes: hex digits as the font draws
+    // them, and `+` for a tick.
+    pass: fn(Platform) -> &'static str,
+}
+
+const FIXTURES: [Fixture; 4] = [
+    Fixture {
+        file: "opcodes.asm",
+        frames: 60,
+        keys: &[],
+        step: 8,
+        pass: |_| "+++++++++++++++++++++",
+    },
+    Fixture {
+        file: "flags.asm",
+        frames: 60,
+        keys: &[],
+        step: 8,
+        pass: |_| "++++++++++++++",
+    },
+    Fixture {
+        file: "quirks.asm",
+        frames: 60,
+        keys: &[],
+        step: 6,
+        // As the COSMAC VIP's interpreter, SUPER-CHIP 1.1 and Octo behave.
+        pass: |platform| match platform {
+            Platform::Chip8 => "101001",
+            Platform::SuperChip => "01F101",
+            Platform::XoChip => "001010",
+        },
+    },
+    Fixture {
+        file: "keypad.asm",
+        frames: 60,
+        keys: &[0x7, 0xA],
+        step: 8,
+        pass: |_| "7A+++",
+    },
+];
+
+const IPF: u64 = 15;
+
+fn fixture_path(file: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/conformance")
+        .join(file)
+}
+
+fn load(fixture: &Fixture) -> Vec<u8> {
+    let path = fixture_path(fixture.file);
+    if fixture.file.ends_with(".asm") {
+        asm::assemble(&fs::read_to_string(&path).unwrap())
+            .unwrap_or_else(|err| panic!("{}: {}", fixture.file, err))
+    } else {
+        fs::read(&path).unwrap()
+    }
+}
+
+// The marks the fixtures draw.
+fn mark(mark: char) -> [&'static str; 5] {
+    match mark {
+        '0' => ["####", "#  #", "#  #", "#  #", "####"],
+        '1' => ["  # ", " ## ", "  # ", "  # ", " ###"],
+        '7' => ["####", "   #", "  # ", " #  ", " #  "],
+        'A' => ["####", "#  #", "####", "#  #", "#  #"],
+        'F' => ["####", "#   ", "####", "#   ", "#   "],
+        '+' => ["       #", "      # ", "#    #  ", " #  #   ", "  ##    "],
+        _ => panic!("no mark {}", mark),
+    }
+}
+
+// The screen a fixture passes with, as `screen_to_ascii` writes it.
+fn pass_screen(fixture: &Fixture, platform: Platform) -> String {
+    let mut screen = vec![[' '; 64]; 32];
+    for (n, c) in (fixture.pass)(platform).chars().enumerate() {
+        let (x, y) = (n * fixture.step % 64, n * fixture.step / 64 * 6);
+        for (row, pixels) in mark(c).iter().enumerate() {
+            for (col, pixel) in pixels.chars().enumerate() {
+                screen[y + row][x + col] = pixel;
+            }
+        }
+    }
+    screen.iter().map(|row| row.iter().collect::<String>() + "\n").collect()
+}
+
+fn final_screen(rom: &[u8], fixture: &Fixture, platform: Platform) -> String {
+    Harness::new(rom).platform(platform).ipf(IPF).run(|machine| {
+        for &key in fixture.keys {
+            machine.press(key);
+        }
+        machine.run_frames(fixture.frames);
+        screen_to_ascii(machine.cpu().display())
+    })
+}
+
+#[test]
+fn test_conformance() {
+    let mut failures = Vec::new();
+    for fixture in FIXTURES.iter() {
+        let rom = load(fixture);
+        for &platform in Platform::all() {
+            let screen = final_screen(&rom, fixture, platform);
+            let expected = pass_screen(fixture, platform);
+            if screen != expected {
+                failures.push(format!("{} on {}: expected\n{}got\n{}", fixture.file, platform, expected, screen));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
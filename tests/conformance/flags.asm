@@ -0,0 +1,141 @@
+; Results and VF for the arithmetic instructions, one mark per check as in
+; opcodes.asm: a tick if right, a cross if not.
+;
+;  1 8XY4 no carry    2 8XY4 carry       3 8XY5 no borrow   4 8XY5 borrow
+;  5 8XY5 equal       6 8XY7 no borrow   7 8XY7 borrow      8 8XY6 bit out
+;  9 8XY6 no bit      10 8XYE bit out    11 8XYE no bit     12 8XY4 into VF
+; 13 8XY5 into VF     14 8XY6 into VF
+start:
+    CLS
+    LD VC, 0
+    LD VD, 0
+
+    ; 8XY4
+    LD V0, 0x10
+    LD V1, 0x20
+    ADD V0, V1
+    LD V2, 0x30
+    LD V3, 0
+    CALL expect
+    LD V0, 0xF0
+    LD V1, 0x20
+    ADD V0, V1
+    LD V2, 0x10
+    LD V3, 1
+    CALL expect
+
+    ; 8XY5
+    LD V0, 0x30
+    LD V1, 0x10
+    SUB V0, V1
+    LD V2, 0x20
+    LD V3, 1
+    CALL expect
+    LD V0, 0x10
+    LD V1, 0x30
+    SUB V0, V1
+    LD V2, 0xE0
+    LD V3, 0
+    CALL expect
+    LD V0, 0x10
+    LD V1, 0x10
+    SUB V0, V1
+    LD V2, 0
+    LD V3, 1
+    CALL expect
+
+    ; 8XY7
+    LD V0, 0x10
+    LD V1, 0x30
+    SUBN V0, V1
+    LD V2, 0x20
+    LD V3, 1
+    CALL expect
+    LD V0, 0x30
+    LD V1, 0x10
+    SUBN V0, V1
+    LD V2, 0xE0
+    LD V3, 0
+    CALL expect
+
+    ; 8XY6 and 8XYE, with VX = VY so either shift quirk gives the same
+    LD V0, 0x05
+    LD V1, 0x05
+    SHR V0, V1
+    LD V2, 0x02
+    LD V3, 1
+    CALL expect
+    LD V0, 0x04
+    LD V1, 0x04
+    SHR V0, V1
+    LD V2, 0x02
+    LD V3, 0
+    CALL expect
+    LD V0, 0x81
+    LD V1, 0x81
+    SHL V0, V1
+    LD V2, 0x02
+    LD V3, 1
+    CALL expect
+    LD V0, 0x41
+    LD V1, 0x41
+    SHL V0, V1
+    LD V2, 0x82
+    LD V3, 0
+    CALL expect
+
+    ; With VF as VX, the flag wins over the result.
+    LD VF, 0xF0
+    LD V1, 0x20
+    ADD VF, V1
+    LD VE, 1
+    SE VF, 1
+    LD VE, 0
+    CALL report
+    LD VF, 0x10
+    LD V1, 0x30
+    SUB VF, V1
+    LD VE, 1
+    SE VF, 0
+    LD VE, 0
+    CALL report
+    LD VF, 0x03
+    LD V1, 0x03
+    SHR VF, V1
+    LD VE, 1
+    SE VF, 1
+    LD VE, 0
+    CALL report
+
+halt:
+    JP halt
+
+; Reports whether V0 is V2 and VF is V3.
+expect:
+    LD VE, 0
+    SE V0, V2
+    JP report
+    SE VF, V3
+    JP report
+    LD VE, 1
+    JP report
+
+; Draws a tick if VE is 1, otherwise a cross, at VC, VD, and moves along.
+report:
+    LD I, cross
+    SE VE, 1
+    JP report_draw
+    LD I, tick
+report_draw:
+    DRW VC, VD, 5
+    ADD VC, 8
+    SE VC, 64
+    RET
+    LD VC, 0
+    ADD VD, 6
+    RET
+
+tick:
+    DB 0x01, 0x02, 0x84, 0x48, 0x30
+cross:
+    DB 0x88, 0x50, 0x20, 0x50, 0x88
@@ -0,0 +1,59 @@
+; Waits for two keys with FX0A and shows them, then checks EX9E and EXA1
+; against them, as ticks or crosses. The test presses 7, then A.
+;
+;  1 the first key, as a digit    2 the second key, as a digit
+;  3 EX9E skips for the key held  4 EXA1 doesn't skip for it
+;  5 EXA1 skips for the first key, released when the second was pressed
+start:
+    CLS
+    LD VC, 0
+    LD VD, 0
+
+    LD V5, K
+    LD F, V5
+    DRW VC, VD, 5
+    ADD VC, 8
+    LD V6, K
+    LD F, V6
+    DRW VC, VD, 5
+    ADD VC, 8
+
+    LD VE, 0
+    SKP V6
+    JP held
+    LD VE, 1
+held:
+    CALL report
+
+    LD VE, 1
+    SKNP V6
+    JP still_held
+    LD VE, 0
+still_held:
+    CALL report
+
+    LD VE, 0
+    SKNP V5
+    JP released
+    LD VE, 1
+released:
+    CALL report
+
+halt:
+    JP halt
+
+; Draws a tick if VE is 1, otherwise a cross, at VC, VD, and moves along.
+report:
+    LD I, cross
+    SE VE, 1
+    JP report_draw
+    LD I, tick
+report_draw:
+    DRW VC, VD, 5
+    ADD VC, 8
+    RET
+
+tick:
+    DB 0x01, 0x02, 0x84, 0x48, 0x30
+cross:
+    DB 0x88, 0x50, 0x20, 0x50, 0x88
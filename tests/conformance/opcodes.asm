@@ -0,0 +1,261 @@
+; One mark per check, left to right, eight to a row: a tick if the
+; instruction did what it should, a cross if not.
+;
+;  1 3XNN   2 4XNN   3 5XY0   4 9XY0   5 7XNN   6 8XY0   7 8XY1   8 8XY2
+;  9 8XY3  10 8XY4  11 8XY5  12 8XY6  13 8XY7  14 8XYE  15 FX1E  16 2NNN/00EE
+; 17 BNNN  18 FX33  19 FX55/FX65  20 FX29  21 FX15/FX07
+start:
+    CLS
+    LD VC, 0
+    LD VD, 0
+
+    ; 3XNN
+    LD V0, 0x12
+    LD VE, 0
+    SE V0, 0x13
+    LD VE, 1
+    SE V0, 0x12
+    LD VE, 0
+    CALL report
+
+    ; 4XNN
+    LD VE, 0
+    SNE V0, 0x12
+    LD VE, 1
+    SNE V0, 0x13
+    LD VE, 0
+    CALL report
+
+    ; 5XY0
+    LD V1, 0x12
+    LD V2, 0x34
+    LD VE, 0
+    SE V0, V2
+    LD VE, 1
+    SE V0, V1
+    LD VE, 0
+    CALL report
+
+    ; 9XY0
+    LD VE, 0
+    SNE V0, V1
+    LD VE, 1
+    SNE V0, V2
+    LD VE, 0
+    CALL report
+
+    ; 7XNN wraps and leaves VF alone
+    LD V0, 0xFF
+    LD VF, 5
+    ADD V0, 2
+    LD VE, 1
+    SE V0, 1
+    LD VE, 0
+    SE VF, 5
+    LD VE, 0
+    CALL report
+
+    ; 8XY0
+    LD V1, 0x42
+    LD V0, V1
+    LD VE, 1
+    SE V0, 0x42
+    LD VE, 0
+    CALL report
+
+    ; 8XY1
+    LD V0, 0x30
+    LD V1, 0x0C
+    OR V0, V1
+    LD VE, 1
+    SE V0, 0x3C
+    LD VE, 0
+    CALL report
+
+    ; 8XY2
+    LD V0, 0x3C
+    LD V1, 0x0F
+    AND V0, V1
+    LD VE, 1
+    SE V0, 0x0C
+    LD VE, 0
+    CALL report
+
+    ; 8XY3
+    LD V0, 0x3C
+    LD V1, 0x0F
+    XOR V0, V1
+    LD VE, 1
+    SE V0, 0x33
+    LD VE, 0
+    CALL report
+
+    ; 8XY4
+    LD V0, 0xF0
+    LD V1, 0x20
+    ADD V0, V1
+    LD VE, 1
+    SE V0, 0x10
+    LD VE, 0
+    CALL report
+
+    ; 8XY5
+    LD V0, 0x10
+    LD V1, 0x30
+    SUB V0, V1
+    LD VE, 1
+    SE V0, 0xE0
+    LD VE, 0
+    CALL report
+
+    ; 8XY6, with VX = VY so either shift quirk gives the same
+    LD V0, 0x81
+    LD V1, 0x81
+    SHR V0, V1
+    LD VE, 1
+    SE V0, 0x40
+    LD VE, 0
+    CALL report
+
+    ; 8XY7
+    LD V0, 0x30
+    LD V1, 0x10
+    SUBN V0, V1
+    LD VE, 1
+    SE V0, 0xE0
+    LD VE, 0
+    CALL report
+
+    ; 8XYE
+    LD V0, 0x81
+    LD V1, 0x81
+    SHL V0, V1
+    LD VE, 1
+    SE V0, 0x02
+    LD VE, 0
+    CALL report
+
+    ; ANNN and FX1E: read back a byte stored 0x10 past scratch
+    LD I, scratch_end
+    LD V0, 0x5A
+    LD [I], V0
+    LD I, scratch
+    LD V1, 0x10
+    ADD I, V1
+    LD V0, [I]
+    LD VE, 1
+    SE V0, 0x5A
+    LD VE, 0
+    CALL report
+
+    ; 2NNN and 00EE
+    LD V0, 0
+    CALL set_v0
+    LD VE, 1
+    SE V0, 0x77
+    LD VE, 0
+    CALL report
+
+    ; BNNN, with V0 to V3 equal so either jump quirk gives the same
+    LD V0, 4
+    LD V1, 4
+    LD V2, 4
+    LD V3, 4
+    LD VE, 0
+    JP V0, computed
+computed:
+    JP computed_end
+    JP computed_end
+    LD VE, 1
+computed_end:
+    CALL report
+
+    ; FX33
+    LD V0, 137
+    LD I, scratch
+    LD B, V0
+    LD I, scratch
+    LD V2, [I]
+    LD VE, 1
+    SE V0, 1
+    LD VE, 0
+    SE V1, 3
+    LD VE, 0
+    SE V2, 7
+    LD VE, 0
+    CALL report
+
+    ; FX55 and FX65
+    LD V0, 0x11
+    LD V1, 0x22
+    LD V2, 0x33
+    LD I, scratch
+    LD [I], V2
+    LD V0, 0
+    LD V1, 0
+    LD V2, 0
+    LD I, scratch
+    LD V2, [I]
+    LD VE, 1
+    SE V0, 0x11
+    LD VE, 0
+    SE V1, 0x22
+    LD VE, 0
+    SE V2, 0x33
+    LD VE, 0
+    CALL report
+
+    ; FX29: the first rows of the font's B, and of B again from 0x3B
+    LD V0, 0x0B
+    LD F, V0
+    LD V0, [I]
+    LD V1, V0
+    LD V0, 0x3B
+    LD F, V0
+    LD V0, [I]
+    LD VE, 1
+    SE V1, 0xE0
+    LD VE, 0
+    SE V0, 0xE0
+    LD VE, 0
+    CALL report
+
+    ; FX15 and FX07
+    LD V0, 0x40
+    LD DT, V0
+    LD V1, DT
+    LD VE, 1
+    SNE V1, 0
+    LD VE, 0
+    CALL report
+
+halt:
+    JP halt
+
+set_v0:
+    LD V0, 0x77
+    RET
+
+; Draws a tick if VE is 1, otherwise a cross, at VC, VD, and moves along.
+report:
+    LD I, cross
+    SE VE, 1
+    JP report_draw
+    LD I, tick
+report_draw:
+    DRW VC, VD, 5
+    ADD VC, 8
+    SE VC, 64
+    RET
+    LD VC, 0
+    ADD VD, 6
+    RET
+
+tick:
+    DB 0x01, 0x02, 0x84, 0x48, 0x30
+cross:
+    DB 0x88, 0x50, 0x20, 0x50, 0x88
+scratch:
+    DB 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+scratch_end:
+    DB 0
@@ -0,0 +1,86 @@
+; Shows which way the platform's quirks go, one digit each, left to right:
+;
+;  1 logic:  1 if 8XY1/8XY2/8XY3 reset VF, else 0
+;  2 shift:  1 if 8XY6/8XYE shift VX, 0 if they shift VY
+;  3 memory: 1 if FX55/FX65 leave I at I + X + 1, 0 if at I + X, F if
+;            they leave it alone
+;  4 jump:   1 if BXNN adds VX, 0 if it adds V0
+;  5 wrap:   1 if sprites wrap around the screen's edges, 0 if clipped
+;  6 index:  1 if I wraps at 4K (FX1E from 0xFFE), 0 if memory goes on
+start:
+    CLS
+    LD VC, 0
+    LD VD, 0
+
+    ; logic
+    LD VF, 5
+    OR V0, V1
+    LD VE, 1
+    SE VF, 0
+    LD VE, 0
+    CALL report
+
+    ; shift
+    LD V0, 0x10
+    LD V1, 0x04
+    SHR V0, V1
+    LD VE, 1
+    SE V0, 0x08
+    LD VE, 0
+    CALL report
+
+    ; memory: load V1 from marks, then V0 from wherever I was left
+    LD I, marks
+    LD V1, [I]
+    LD V0, [I]
+    LD VE, V0
+    CALL report
+
+    ; jump, with V2 and V3 set in case the target is at 0x2xx or 0x3xx
+    LD V0, 0
+    LD V2, 2
+    LD V3, 2
+    LD VE, 0
+    JP V0, jump_targets
+jump_targets:
+    JP jump_end
+    LD VE, 1
+jump_end:
+    CALL report
+
+    ; wrap: draw across the right edge, then see if the left edge is hit
+    LD V0, 60
+    LD V1, 28
+    LD V2, 0
+    LD I, bar
+    DRW V0, V1, 1
+    DRW V2, V1, 1
+    LD VE, VF
+    DRW V2, V1, 1
+    DRW V0, V1, 1
+    CALL report
+
+    ; index: I = 0xFFE + 3 reads the second byte of the font's 0 at 4K
+    LD I, 0xFFE
+    LD V0, 3
+    ADD I, V0
+    LD V0, [I]
+    LD VE, 1
+    SE V0, 0x90
+    LD VE, 0
+    CALL report
+
+halt:
+    JP halt
+
+; Draws the digit in VE at VC, VD, and moves along.
+report:
+    LD F, VE
+    DRW VC, VD, 5
+    ADD VC, 6
+    RET
+
+marks:
+    DB 0x0F, 0x00, 0x01
+bar:
+    DB 0xFF
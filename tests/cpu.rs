@@ -1,14 +1,26 @@
+extern crate rand;
 extern crate rust8;
 
+use std::cell::RefCell;
+use std::env;
+use std::fs::{self, File};
+use std::rc::Rc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::sync::mpsc::{Sender,channel};
 use std::thread::{spawn,sleep};
 use std::time::Duration;
 
+use rand::{Rng, SeedableRng, XorShiftRng};
+
 use rust8::keyboard::Keyboard;
 use rust8::display::Display;
+use rust8::observer::Observer;
+use rust8::quirks::Quirks;
+use rust8::instruction::Instruction;
+use rust8::opcode::Opcode;
 use rust8::ram::RAM;
+use rust8::rombuilder::*;
 
 use rust8::cpu::*;
 
@@ -19,18 +31,21 @@ where F: FnMut(&mut CPU, &Sender<u8>) {
     let mut display = Display::init();
     let mut keyboard = Arc::new(Mutex::new(Keyboard::init(receiver)));
     let mut ram: RAM = RAM::init();
-    let mut cpu = CPU::init(&mut ram, &mut display, &mut keyboard);
-    (move || test(&mut cpu, &sender))();
+    let mut cpu = CPU::init(&mut ram, &mut display, &mut keyboard, None);
+    test(&mut cpu, &sender);
 }
 
 #[test]
 fn test_00e0() { // Clear Screen
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x00, 0xE0,  // Clear screen
-                   0x60, 0x00,  // Set x0 to 0
-                   0xF0, 0x29,  // Load fontset for 0
-                   0xD0, 0x01,  // Draw image in x0
-                   0x00, 0xE0];  // Clear screen
+        let rom = RomBuilder::new()
+            .cls()  // Clear screen
+            .ld(V0, 0x00)  // Set x0 to 0
+            .ld_f_vx(V0)  // Load fontset for 0
+            .draw(V0, V0, 1)  // Draw image in x0
+            .cls()  // Clear screen
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
         cpu.run_cycle();
         assert_eq!(cpu.get_display()[0], 0);
@@ -40,6 +55,7 @@ fn test_00e0() { // Clear Screen
         cpu.run_cycle();
         assert!(cpu.get_display()[0] != 0);
 
+        cpu.end_frame();  // Drawing waits for the next frame
         cpu.run_cycle();
         assert_eq!(cpu.get_display()[0], 0);
     })
@@ -48,9 +64,12 @@ fn test_00e0() { // Clear Screen
 #[test]
 fn test_6xnn() { // Set Address
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x60, 0xAB,
-                   0x60, 0xCC,
-                   0x6E, 0x42];
+        let rom = RomBuilder::new()
+            .ld(V0, 0xAB)
+            .ld(V0, 0xCC)
+            .ld(VE, 0x42)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0xAB);
@@ -64,9 +83,12 @@ fn test_6xnn() { // Set Address
 #[test]
 fn test_7xnn() { // Add To Reg
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x70, 0x11,
-                   0x70, 0x22,
-                   0x70, 0xCE];
+        let rom = RomBuilder::new()
+            .add(V0, 0x11)
+            .add(V0, 0x22)
+            .add(V0, 0xCE)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x11);
@@ -83,9 +105,12 @@ fn test_7xnn() { // Add To Reg
 #[test]
 fn test_8xy0() { // Set X to Y
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x80, 0x10,
-                   0x61, 0xAB,
-                   0x80, 0x10];
+        let rom = RomBuilder::new()
+            .ld_reg(V0, V1)
+            .ld(V1, 0xAB)
+            .ld_reg(V0, V1)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x00);
@@ -101,10 +126,13 @@ fn test_8xy0() { // Set X to Y
 #[test]
 fn test_8xy1() { // X |= Y
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x80, 0x11,
-                   0x60, 0x01,
-                   0x61, 0x02,
-                   0x80, 0x11];
+        let rom = RomBuilder::new()
+            .or(V0, V1)
+            .ld(V0, 0x01)
+            .ld(V1, 0x02)
+            .or(V0, V1)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x00);
@@ -123,10 +151,13 @@ fn test_8xy1() { // X |= Y
 #[test]
 fn test_8xy2() { // X &= Y
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x80, 0x12,
-                   0x60, 0x06,
-                   0x61, 0x03,
-                   0x80, 0x12];
+        let rom = RomBuilder::new()
+            .and(V0, V1)
+            .ld(V0, 0x06)
+            .ld(V1, 0x03)
+            .and(V0, V1)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x00);
@@ -145,10 +176,13 @@ fn test_8xy2() { // X &= Y
 #[test]
 fn test_8xy3() { // X ^= Y
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x80, 0x13,
-                   0x60, 0x03,
-                   0x61, 0x01,
-                   0x80, 0x13];
+        let rom = RomBuilder::new()
+            .xor(V0, V1)
+            .ld(V0, 0x03)
+            .ld(V1, 0x01)
+            .xor(V0, V1)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x00);
@@ -167,12 +201,15 @@ fn test_8xy3() { // X ^= Y
 #[test]
 fn test_8xy4() { // X += Y (with carry)
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x80, 0x14,
-                   0x60, 0x11,
-                   0x61, 0x22,
-                   0x80, 0x14,
-                   0x61, 0xCE,
-                   0x80, 0x14];
+        let rom = RomBuilder::new()
+            .add_reg(V0, V1)
+            .ld(V0, 0x11)
+            .ld(V1, 0x22)
+            .add_reg(V0, V1)
+            .ld(V1, 0xCE)
+            .add_reg(V0, V1)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x00);
@@ -195,14 +232,17 @@ fn test_8xy4() { // X += Y (with carry)
 }
 
 #[test]
-fn test_8xy5() { // X -= Y (with borrow)
+fn test_8xy5() { // X -= Y (VF = NOT borrow)
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x80, 0x15,
-                   0x60, 0x22,
-                   0x61, 0x11,
-                   0x80, 0x15,
-                   0x61, 0x12,
-                   0x80, 0x15];
+        let rom = RomBuilder::new()
+            .sub(V0, V1)
+            .ld(V0, 0x22)
+            .ld(V1, 0x11)
+            .sub(V0, V1)
+            .ld(V1, 0x12)
+            .sub(V0, V1)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x00);
@@ -215,23 +255,26 @@ fn test_8xy5() { // X -= Y (with borrow)
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x11);
         assert_eq!(cpu.get_reg(1), 0x11);
-        assert_eq!(cpu.get_carry(), 0x00);
+        assert_eq!(cpu.get_carry(), 0x01);
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(1), 0x12);
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0xFF);
-        assert_eq!(cpu.get_carry(), 0x01);
+        assert_eq!(cpu.get_carry(), 0x00);
     })
 }
 
 #[test]
 fn test_8xy6() { // X >>= Y (with spillover)
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x80, 0x16,
-                   0x61, 0x06,
-                   0x80, 0x16,
-                   0x61, 0x03,
-                   0x80, 0x16];
+        let rom = RomBuilder::new()
+            .shr(V0, V1)
+            .ld(V1, 0x06)
+            .shr(V0, V1)
+            .ld(V1, 0x03)
+            .shr(V0, V1)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x00);
@@ -256,14 +299,17 @@ fn test_8xy6() { // X >>= Y (with spillover)
 }
 
 #[test]
-fn test_8xy7() { // X = Y - X (with borrow)
+fn test_8xy7() { // X = Y - X (VF = NOT borrow)
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x80, 0x17,
-                   0x60, 0x11,
-                   0x61, 0x22,
-                   0x80, 0x17,
-                   0x61, 0x10,
-                   0x80, 0x17];
+        let rom = RomBuilder::new()
+            .subn(V0, V1)
+            .ld(V0, 0x11)
+            .ld(V1, 0x22)
+            .subn(V0, V1)
+            .ld(V1, 0x10)
+            .subn(V0, V1)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x00);
@@ -276,24 +322,27 @@ fn test_8xy7() { // X = Y - X (with borrow)
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x11);
         assert_eq!(cpu.get_reg(1), 0x22);
-        assert_eq!(cpu.get_carry(), 0x00);
+        assert_eq!(cpu.get_carry(), 0x01);
 
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(1), 0x10);
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0xFF);
-        assert_eq!(cpu.get_carry(), 0x01);
+        assert_eq!(cpu.get_carry(), 0x00);
     })
 }
 
 #[test]
 fn test_8xye() { // X <<= Y (with spillover)
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x80, 0x1E,
-                   0x61, 0x7F,
-                   0x80, 0x1E,
-                   0x61, 0xFE,
-                   0x80, 0x1E];
+        let rom = RomBuilder::new()
+            .shl(V0, V1)
+            .ld(V1, 0x7F)
+            .shl(V0, V1)
+            .ld(V1, 0xFE)
+            .shl(V0, V1)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x00);
@@ -320,10 +369,13 @@ fn test_8xye() { // X <<= Y (with spillover)
 #[test]
 fn test_delay_timer() { // FX07, FX15
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0xF0, 0x07,
-                   0x60, 0x03,
-                   0xF0, 0x15,
-                   0xF0, 0x07];
+        let rom = RomBuilder::new()
+            .ld_vx_dt(V0)
+            .ld(V0, 0x03)
+            .ld_dt_vx(V0)
+            .ld_vx_dt(V0)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
 
         cpu.run_cycle();
@@ -332,6 +384,8 @@ fn test_delay_timer() { // FX07, FX15
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x03);
         cpu.run_cycle();
+        assert_eq!(cpu.get_delay(), 0x03);
+        cpu.dec_delay();
         assert_eq!(cpu.get_delay(), 0x02);
 
         cpu.run_cycle();
@@ -342,10 +396,14 @@ fn test_delay_timer() { // FX07, FX15
 #[test]
 fn test_basic_subroutine_flow() {
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x22, 0x04,
-                   0x60, 0x01,
-                   0x60, 0x03,
-                   0x00, 0xEE];
+        let rom = RomBuilder::new()
+            .call("sub")
+            .ld(V0, 0x01)
+            .label("sub")
+            .ld(V0, 0x03)
+            .ret()
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
 
         cpu.run_cycle();
@@ -361,11 +419,14 @@ fn test_basic_subroutine_flow() {
 #[test]
 fn test_3xnn() {
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x30, 0x01,
-                   0x60, 0x03,
-                   0x30, 0x03,
-                   0x60, 0x04,
-                   0x60, 0x05];
+        let rom = RomBuilder::new()
+            .se(V0, 0x01)
+            .ld(V0, 0x03)
+            .se(V0, 0x03)
+            .ld(V0, 0x04)
+            .ld(V0, 0x05)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
 
         cpu.run_cycle();
@@ -383,11 +444,14 @@ fn test_3xnn() {
 #[test]
 fn test_4xnn() {
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x40, 0x00,
-                   0x60, 0x03,
-                   0x40, 0x01,
-                   0x60, 0x04,
-                   0x60, 0x05];
+        let rom = RomBuilder::new()
+            .sne(V0, 0x00)
+            .ld(V0, 0x03)
+            .sne(V0, 0x01)
+            .ld(V0, 0x04)
+            .ld(V0, 0x05)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
 
         cpu.run_cycle();
@@ -405,12 +469,15 @@ fn test_4xnn() {
 #[test]
 fn test_5xy0() {
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x60, 0x01,
-                   0x50, 0x10,
-                   0x61, 0x01,
-                   0x50, 0x10,
-                   0x60, 0x04,
-                   0x60, 0x05];
+        let rom = RomBuilder::new()
+            .ld(V0, 0x01)
+            .se_reg(V0, V1)
+            .ld(V1, 0x01)
+            .se_reg(V0, V1)
+            .ld(V0, 0x04)
+            .ld(V0, 0x05)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
 
         cpu.run_cycle();
@@ -429,10 +496,37 @@ fn test_5xy0() {
     });
 }
 
+#[test]
+fn test_9xy0() {
+    cpu_tester(&mut |cpu, _sender| {
+        let rom = RomBuilder::new()
+            .ld(V0, 0x05)
+            .ld(V1, 0x05)
+            .sne_reg(V0, V1)  // V0 == V1: no skip
+            .ld(V2, 0x07)
+            .sne_reg(V0, V2)  // V0 != V2: skip
+            .dw(&[0x0000])
+            .ld(V3, 0x01)
+            .build()
+            .unwrap();
+        cpu.load_rom(&rom);
+        for _ in 0..3 {
+            cpu.run_cycle();
+        }
+        assert_eq!(cpu.get_pc(), 0x206);
+        cpu.run_cycle();
+        cpu.run_cycle();
+        assert_eq!(cpu.get_pc(), 0x20C);
+    })
+}
+
 #[test]
 fn test_annn() {
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0xA0, 0x42];
+        let rom = RomBuilder::new()
+            .ld_i(0x042)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
 
         assert_eq!(cpu.get_i(), 0);
@@ -445,12 +539,15 @@ fn test_annn() {
 #[test]
 fn test_bnnn() {
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0xB2, 0x04,
-                   0x60, 0x01,
-                   0x60, 0x06,
-                   0xB2, 0x04,
-                   0x60, 0x02,
-                   0x60, 0x03];
+        let rom = RomBuilder::new()
+            .jp_v0(0x204)
+            .ld(V0, 0x01)
+            .ld(V0, 0x06)
+            .jp_v0(0x204)
+            .ld(V0, 0x02)
+            .ld(V0, 0x03)
+            .build()
+            .unwrap();
 
         cpu.load_rom(&rom);
 
@@ -467,18 +564,25 @@ fn test_bnnn() {
 #[test]
 fn test_ex9e() {
     cpu_tester(&mut |cpu, sender| {
-        let rom = [0xE0, 0x9E,
-                   0x60, 0x01,
-                   0xE0, 0x9E,
-                   0x60, 0x02,
-                   0x60, 0x03];
+        let rom = RomBuilder::new()
+            .skp(V0)
+            .ld(V0, 0x01)
+            .skp(V0)
+            .ld(V0, 0x02)
+            .ld(V0, 0x03)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
 
         cpu.run_cycle();
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x01);
 
-        let _ = sender.send('2' as u8);
+        // Seen once the frame that checked the key ends.
+        let _ = sender.send(b'2');
+        assert!(!cpu.get_key(1));
+        cpu.end_frame();
+        assert!(cpu.get_key(1));
         cpu.run_cycle();
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x03);
@@ -488,20 +592,25 @@ fn test_ex9e() {
 #[test]
 fn test_exa1() {
     cpu_tester(&mut |cpu, sender| {
-        let rom = [0x60, 0x01,
-                   0xE0, 0xA1,
-                   0x60, 0x02,
-                   0xE0, 0xA1,
-                   0x60, 0x03,
-                   0x60, 0x04];
+        let rom = RomBuilder::new()
+            .ld(V0, 0x01)
+            .sknp(V0)
+            .ld(V0, 0x02)
+            .sknp(V0)
+            .ld(V0, 0x03)
+            .ld(V0, 0x04)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
 
         cpu.run_cycle();
-        let _ = sender.send('2' as u8);
+        let _ = sender.send(b'2');
+        cpu.poll_keys();
         cpu.run_cycle();
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x02);
-        let _ = sender.send('1' as u8);
+        let _ = sender.send(b'1');
+        cpu.poll_keys();
         cpu.run_cycle();
         cpu.run_cycle();
         assert_eq!(cpu.get_reg(0), 0x04);
@@ -511,14 +620,17 @@ fn test_exa1() {
 #[test]
 fn test_fx0a() {
     cpu_tester(&mut |cpu, sender| {
-        let rom = [0xF0, 0x0A];
+        let rom = RomBuilder::new()
+            .ld_vx_k(V0)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
 
         assert_eq!(cpu.get_reg(0), 0x00);
         let sender_clone = sender.clone();
         spawn(move || {
             sleep(Duration::new(1,0));
-            let _ = sender_clone.send('3' as u8);
+            let _ = sender_clone.send(b'3');
         });
         assert_eq!(cpu.get_reg(0), 0x00);
         cpu.run_cycle();
@@ -529,8 +641,11 @@ fn test_fx0a() {
 #[test]
 fn test_fx1e() {
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x60, 0x01,
-                   0xF0, 0x1E];
+        let rom = RomBuilder::new()
+            .ld(V0, 0x01)
+            .add_i(V0)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
 
         cpu.run_cycle();
@@ -543,14 +658,54 @@ fn test_fx1e() {
     });
 }
 
+#[test]
+fn test_fx1e_wraps() {
+    cpu_tester(&mut |cpu, _sender| {
+        let rom = RomBuilder::new()
+            .ld_i(0xFFE)
+            .ld(V0, 0x03)
+            .add_i(V0)
+            .build()
+            .unwrap();
+        cpu.load_rom(&rom);
+        for _ in 0..3 {
+            cpu.run_cycle();
+        }
+        assert_eq!(cpu.get_i(), 0x001);
+    });
+}
+
+#[test]
+fn test_fx29() {
+    cpu_tester(&mut |cpu, _sender| {
+        let rom = RomBuilder::new()
+            .ld(V0, 0x0A)
+            .ld_f_vx(V0)
+            .ld(V0, 0x3B)  // only the low nibble picks the digit
+            .ld_f_vx(V0)
+            .build()
+            .unwrap();
+        cpu.load_rom(&rom);
+        cpu.run_cycle();
+        cpu.run_cycle();
+        assert_eq!(cpu.get_i(), 50);
+        cpu.run_cycle();
+        cpu.run_cycle();
+        assert_eq!(cpu.get_i(), 55);
+    });
+}
+
 #[test]
 fn test_fx33() {
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0x60, 0xFE,
-                   0xF0, 0x33,
-                   0x60, 0x01,
-                   0xF0, 0x1E,
-                   0xF0, 0x1E];
+        let rom = RomBuilder::new()
+            .ld(V0, 0xFE)
+            .ld_b_vx(V0)
+            .ld(V0, 0x01)
+            .add_i(V0)
+            .add_i(V0)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
 
         cpu.run_cycle();
@@ -576,26 +731,29 @@ fn test_fx33() {
 #[test]
 fn test_fx55() {
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0xAF, 0x00,
-                   0x60, 0x00,
-                   0x61, 0x01,
-                   0x62, 0x02,
-                   0x63, 0x03,
-                   0x64, 0x04,
-                   0x65, 0x05,
-                   0x66, 0x06,
-                   0x67, 0x07,
-                   0x68, 0x08,
-                   0xF7, 0x55,
-                   0xAF, 0x00,
-                   0xF1, 0x1E,
-                   0xF1, 0x1E,
-                   0xF1, 0x1E,
-                   0xF1, 0x1E,
-                   0xF1, 0x1E,
-                   0xF1, 0x1E,
-                   0xF1, 0x1E,
-                   0xF1, 0x1E];
+        let rom = RomBuilder::new()
+            .ld_i(0xF00)
+            .ld(V0, 0x00)
+            .ld(V1, 0x01)
+            .ld(V2, 0x02)
+            .ld(V3, 0x03)
+            .ld(V4, 0x04)
+            .ld(V5, 0x05)
+            .ld(V6, 0x06)
+            .ld(V7, 0x07)
+            .ld(V8, 0x08)
+            .ld_i_vx(V7)
+            .ld_i(0xF00)
+            .add_i(V1)
+            .add_i(V1)
+            .add_i(V1)
+            .add_i(V1)
+            .add_i(V1)
+            .add_i(V1)
+            .add_i(V1)
+            .add_i(V1)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
 
         cpu.run_cycle();
@@ -636,8 +794,11 @@ fn test_fx55() {
 #[test]
 fn test_fx65() {
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0xA0, 0x00,
-                   0xF7, 0x65];
+        let rom = RomBuilder::new()
+            .ld_i(0x000)
+            .ld_vx_i(V7)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
 
         cpu.run_cycle();
@@ -657,14 +818,493 @@ fn test_fx65() {
 #[test]
 fn test_collision() {
     cpu_tester(&mut |cpu, _sender| {
-        let rom = [0xD0, 0x01,
-                   0xD0, 0x01];
+        let rom = RomBuilder::new()
+            .draw(V0, V0, 1)
+            .draw(V0, V0, 1)
+            .build()
+            .unwrap();
         cpu.load_rom(&rom);
 
         cpu.run_cycle();
         assert_eq!(cpu.get_carry(), 0x00);
 
+        cpu.end_frame();
         cpu.run_cycle();
         assert_eq!(cpu.get_carry(), 0x01);
     });
 }
+
+#[test]
+fn test_vblank_quirk() {
+    cpu_tester(&mut |cpu, _sender| {
+        let rom = RomBuilder::new()
+            .draw(V0, V0, 1)
+            .add(V1, 0x01)
+            .draw(V0, V0, 1)
+            .add(V1, 0x01)
+            .build()
+            .unwrap();
+        cpu.load_rom(&rom);
+
+        // Nothing runs after a draw until the frame ends, from the cache or
+        // not.
+        cpu.run_cycles(10);
+        assert!(cpu.is_waiting_for_vblank());
+        assert_eq!((cpu.get_pc(), cpu.get_reg(1)), (0x202, 0));
+        cpu.run_cycle();
+        assert_eq!(cpu.get_pc(), 0x202);
+        cpu.end_frame();
+        assert!(!cpu.is_waiting_for_vblank());
+        cpu.run_cycle();
+        cpu.run_cycle();
+        assert_eq!((cpu.get_pc(), cpu.get_reg(1)), (0x206, 1));
+        cpu.run_cycles(10);
+        assert_eq!(cpu.get_pc(), 0x206);
+
+        // Without the quirk, draws run straight on.
+        cpu.set_quirks(Quirks { vblank: false, ..Quirks::default() });
+        cpu.end_frame();
+        cpu.run_cycles(2);
+        assert_eq!((cpu.get_pc(), cpu.get_reg(1)), (0x208, 2));
+    });
+}
+
+#[test]
+fn test_frame_ready() {
+    cpu_tester(&mut |cpu, _sender| {
+        let rom = RomBuilder::new()
+            .ld(V0, 0x00)
+            .draw(V0, V0, 1)
+            .ld(V0, 0x01)
+            .build()
+            .unwrap();
+        cpu.load_rom(&rom);
+        let (frame_sender, frames) = channel();
+        cpu.set_frame_sender(frame_sender);
+
+        cpu.run_cycle();
+        cpu.end_frame();
+        assert!(frames.try_recv().is_err());
+
+        cpu.run_cycle();
+        cpu.end_frame();
+        assert_eq!(frames.try_recv().unwrap().generation, 1);
+
+        cpu.run_cycle();
+        cpu.end_frame();
+        assert!(frames.try_recv().is_err());
+    });
+}
+
+struct EventLog(Rc<RefCell<Vec<String>>>);
+
+impl Observer for EventLog {
+    fn instruction(&mut self, pc: u16, opcode: &Opcode) {
+        self.0.borrow_mut().push(format!("{:X} {}", pc, opcode));
+    }
+
+    fn memory_read(&mut self, addr: usize, value: u8) {
+        self.0.borrow_mut().push(format!("read {:X} {:X}", addr, value));
+    }
+
+    fn memory_write(&mut self, addr: usize, value: u8) {
+        self.0.borrow_mut().push(format!("write {:X} {}", addr, value));
+    }
+
+    fn sprite_drawn(&mut self, x: u8, y: u8, height: u8, collision: bool) {
+        self.0.borrow_mut().push(format!("sprite {} {} {} {}", x, y, height, collision));
+    }
+
+    fn screen_cleared(&mut self) {
+        self.0.borrow_mut().push("clear".to_string());
+    }
+
+    fn subroutine_call(&mut self, from: u16, to: u16) {
+        self.0.borrow_mut().push(format!("call {:X} {:X}", from, to));
+    }
+
+    fn subroutine_return(&mut self, from: u16, to: u16) {
+        self.0.borrow_mut().push(format!("return {:X} {:X}", from, to));
+    }
+
+    fn sound(&mut self, on: bool) {
+        self.0.borrow_mut().push(format!("sound {}", on));
+    }
+}
+
+#[test]
+fn test_observer() {
+    cpu_tester(&mut |cpu, _sender| {
+        let rom = RomBuilder::new()
+            .call("sub")
+            .label("loop")
+            .jp("loop")
+            .label("sub")
+            .ld(V0, 0x01)  // Set x0 to 1
+            .ld_st_vx(V0)  // Sound for 1 frame
+            .ld_i(0x000)  // Point I at font 0
+            .draw(V0, V0, 2)  // Draw 2 rows
+            .ld_b_vx(V0)  // BCD of x0
+            .cls()  // Clear screen
+            .ret()  // Return
+            .build()
+            .unwrap();
+        cpu.load_rom(&rom);
+        cpu.set_quirks(Quirks { vblank: false, ..Quirks::default() });
+        let events = Rc::new(RefCell::new(Vec::new()));
+        cpu.add_observer(Box::new(EventLog(events.clone())));
+        for _ in 0..8 {
+            cpu.run_cycle();
+        }
+        cpu.end_frame();
+        let expected = [
+            "call 200 204", "200 0x2204",
+            "204 0x6001",
+            "sound true", "206 0xF018",
+            "208 0xA000",
+            "read 0 F0", "read 1 90", "sprite 1 1 2 false", "20A 0xD002",
+            "write 0 0", "write 1 0", "write 2 1", "20C 0xF033",
+            "clear", "20E 0xE0",
+            "return 210 202", "210 0xEE",
+            "sound false",
+        ];
+        assert_eq!(*events.borrow(), expected);
+    });
+}
+
+#[test]
+fn test_shift_quirk() {
+    cpu_tester(&mut |cpu, _sender| {
+        let rom = RomBuilder::new()
+            .ld(V0, 0x04)  // Set x0 to 4
+            .ld(V1, 0x81)  // Set x1 to 0x81
+            .shr(V0, V1)  // x0 = x1 >> 1
+            .shr(V0, V1)  // x0 >>= 1 with the shift quirk
+            .build()
+            .unwrap();
+        cpu.load_rom(&rom);
+        for _ in 0..3 {
+            cpu.run_cycle();
+        }
+        assert_eq!(cpu.get_reg(0), 0x40);
+        assert_eq!(cpu.get_carry(), 1);
+
+        cpu.set_quirks(Quirks { shift: true, ..Quirks::default() });
+        cpu.run_cycle();
+        assert_eq!(cpu.get_reg(0), 0x20);
+        assert_eq!(cpu.get_carry(), 0);
+    });
+}
+
+#[test]
+fn test_memory_quirks() {
+    cpu_tester(&mut |cpu, _sender| {
+        let rom = RomBuilder::new()
+            .ld_i(0x300)  // Point I at 0x300
+            .ld_i_vx(V2)  // Store x0-x2
+            .ld_i_vx(V2)  // Store x0-x2, I + X
+            .ld_vx_i(V2)  // Load x0-x2, I unchanged
+            .build()
+            .unwrap();
+        cpu.load_rom(&rom);
+        cpu.run_cycle();
+        cpu.run_cycle();
+        assert_eq!(cpu.get_i(), 0x303);
+
+        cpu.set_quirks(Quirks { memory_increment_by_x: true, ..Quirks::default() });
+        cpu.run_cycle();
+        assert_eq!(cpu.get_i(), 0x305);
+
+        cpu.set_quirks(Quirks { memory_leave_i_unchanged: true, ..Quirks::default() });
+        cpu.run_cycle();
+        assert_eq!(cpu.get_i(), 0x305);
+    });
+}
+
+#[test]
+fn test_jump_quirk() {
+    cpu_tester(&mut |cpu, _sender| {
+        let rom = RomBuilder::new()
+            .ld(V2, 0x04)  // Set x2 to 4
+            .jp_v0(0x200)  // Jump to 0x200 + x2
+            .build()
+            .unwrap();
+        cpu.load_rom(&rom);
+        cpu.set_quirks(Quirks { jump: true, ..Quirks::default() });
+        cpu.run_cycle();
+        cpu.run_cycle();
+        assert_eq!(cpu.get_pc(), 0x204);
+    });
+}
+
+#[test]
+fn test_logic_quirk() {
+    cpu_tester(&mut |cpu, _sender| {
+        let rom = RomBuilder::new()
+            .ld(VF, 0x01)  // Set xF to 1
+            .or(V0, V1)  // x0 |= x1
+            .ld(VF, 0x01)  // Set xF to 1
+            .or(V0, V1)  // x0 |= x1, leaving xF
+            .build()
+            .unwrap();
+        cpu.load_rom(&rom);
+        cpu.run_cycle();
+        cpu.run_cycle();
+        assert_eq!(cpu.get_carry(), 0);
+
+        cpu.set_quirks(Quirks { logic: false, ..Quirks::default() });
+        cpu.run_cycle();
+        cpu.run_cycle();
+        assert_eq!(cpu.get_carry(), 1);
+    });
+}
+
+#[test]
+fn test_seed() {
+    let mut runs = Vec::new();
+    for _ in 0..2 {
+        cpu_tester(&mut |cpu, _sender| {
+            let rom = RomBuilder::new()
+                .rnd(V0, 0xFF)
+                .rnd(V1, 0xFF)
+                .rnd(V2, 0xFF)
+                .build()
+                .unwrap();
+            cpu.load_rom(&rom);
+            cpu.set_seed(42);
+            for _ in 0..3 {
+                cpu.run_cycle();
+            }
+            runs.push((cpu.get_reg(0), cpu.get_reg(1), cpu.get_reg(2)));
+        });
+    }
+    assert_eq!(runs[0], runs[1]);
+}
+
+#[test]
+fn test_logfile() {
+    let path = env::temp_dir().join("rust8_test_opcodes.txt");
+    {
+        let (_sender, receiver) = channel();
+        let mut display = Display::init();
+        let mut keyboard = Arc::new(Mutex::new(Keyboard::init(receiver)));
+        let mut ram: RAM = RAM::init();
+        let mut logfile = File::create(&path).unwrap();
+        let mut cpu = CPU::init(&mut ram, &mut display, &mut keyboard, Some(&mut logfile));
+        cpu.load_rom(&[0x60, 0xAB, 0xA1, 0x23]);
+        cpu.run_cycle();
+        cpu.run_cycle();
+    }
+    assert_eq!(fs::read_to_string(&path).unwrap(), "0x60AB\n0xA123\n");
+}
+
+// Everything an instruction can change.
+#[derive(PartialEq, Debug)]
+struct State {
+    pc: u16,
+    i: u16,
+    regs: Vec<u8>,
+    stack: Vec<u16>,
+    timers: (u8, u8),
+    display: Vec<u64>,
+    memory: Vec<u8>,
+}
+
+fn state(cpu: &CPU) -> State {
+    State {
+        pc: cpu.get_pc(),
+        i: cpu.get_i(),
+        regs: (0..16).map(|x| cpu.get_reg(x)).collect(),
+        stack: cpu.get_stack().to_vec(),
+        timers: (cpu.get_delay(), cpu.get_sound()),
+        display: cpu.get_display().to_vec(),
+        memory: (0..4096).map(|addr| cpu.get_mem8(addr)).collect(),
+    }
+}
+
+// The state after each batch of instructions, ending a frame after every
+// batch, run one instruction at a time or from the block cache. `None` for a
+// batch that faulted, which ends the run. Nothing is attached to the
+// keyboard, so key waits don't hang.
+fn run_batches(rom: &[u8], batches: &[u64], cached: bool) -> Vec<Option<State>> {
+    let (_, receiver) = channel();
+    let mut display = Display::init();
+    let mut keyboard = Arc::new(Mutex::new(Keyboard::init(receiver)));
+    let mut ram: RAM = RAM::init();
+    let mut cpu = CPU::init(&mut ram, &mut display, &mut keyboard, None);
+    cpu.load_rom(rom);
+    cpu.set_seed(8);
+    let mut states = Vec::new();
+    for &batch in batches {
+        if cached {
+            cpu.run_cycles(batch);
+        } else {
+            for _ in 0..batch {
+                cpu.run_cycle();
+            }
+        }
+        cpu.end_frame();
+        if cpu.fault().is_some() {
+            states.push(None);
+            break;
+        }
+        states.push(Some(state(&cpu)));
+    }
+    states
+}
+
+fn assert_cache_exact(rom: &[u8], batches: &[u64]) {
+    let interpreted = run_batches(rom, batches, false);
+    let cached = run_batches(rom, batches, true);
+    assert_eq!(interpreted.len(), cached.len());
+    for (n, (a, b)) in interpreted.iter().zip(cached.iter()).enumerate() {
+        assert_eq!(a, b, "after batch {}", n);
+    }
+}
+
+#[test]
+fn test_cache_self_modifying_code() {
+    let rom = RomBuilder::new()
+        .label("start")
+        .ld(VA, 0x00)  // rewritten to load the count
+        .add_reg(VB, VA)
+        .add(VC, 0x01)
+        .ld(V0, 0x6A)
+        .ld_reg(V1, VC)
+        .ld_i("start")
+        .ld_i_vx(V1)
+        .jp("start")
+        .build()
+        .unwrap();
+    assert_cache_exact(&rom, &[1, 7, 8, 3, 13, 17, 31]);
+    let states = run_batches(&rom, &[80], true);
+    assert_eq!(states[0].as_ref().unwrap().regs[0xB], 45);
+}
+
+#[test]
+fn test_cache_bcd_into_code() {
+    let rom = RomBuilder::new()
+        .ld(V0, 0x00)
+        .ld(V3, 0x2A)
+        .ld_i(0x20B)
+        .label("loop")
+        .add(V5, 0x01)
+        .add(V6, 0x02)
+        .se(V0, 0xFF)  // becomes SE V0, 0
+        .ld_b_vx(V3)  // becomes 0x0402, which fails if run
+        .jp("loop")
+        .build()
+        .unwrap();
+    assert_cache_exact(&rom, &[3, 2, 1, 9, 4, 6, 20]);
+    let states = run_batches(&rom, &[3 + 5 + 4 * 10], true);
+    assert_eq!(states[0].as_ref().unwrap().regs[5], 11);
+}
+
+#[test]
+fn test_cache_random_programs() {
+    let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+    for _ in 0..50 {
+        // Straight-line code, skips and jumps within 0x200-0x2FF, with two
+        // jumps back to the start after it. I points at 0x400-0x4EF, or for
+        // some programs into the code itself, so writes hit cached blocks and
+        // may well turn code into something that fails.
+        let code_writes = rng.gen::<bool>();
+        let mut rom = Vec::new();
+        for _ in 0..128 {
+            let x = rng.gen_range(0, 16u16);
+            let y = rng.gen_range(0, 16u16);
+            let nn = rng.gen_range(0, 256u16);
+            let op = match rng.gen_range(0, 17) {
+                0 => 0x6000 | x << 8 | nn,
+                1 => 0x7000 | x << 8 | nn,
+                2 => 0x8000 | x << 8 | y << 4 | [0, 1, 2, 3, 4, 5, 6, 7, 0xE][rng.gen_range(0, 9)],
+                3 => 0x3000 | x << 8 | nn,
+                4 => 0x4000 | x << 8 | nn,
+                5 => 0x5000 | x << 8 | y << 4,
+                6 => 0x9000 | x << 8,
+                7 if code_writes => 0xA200 | rng.gen_range(0, 0xF0),
+                7 => 0xA400 | rng.gen_range(0, 0xF0),
+                8 => 0xC000 | x << 8 | nn,
+                9 => 0xD000 | x << 8 | y << 4 | rng.gen_range(0, 16),
+                10 => 0xF007 | x << 8,
+                11 => 0xF015 | x << 8,
+                12 => 0xF018 | x << 8,
+                13 => 0xF033 | x << 8,
+                14 => 0xF055 | x << 8,
+                15 => 0xF065 | x << 8,
+                _ => 0x1200 | (rng.gen_range(0, 0x80) * 2),
+            };
+            rom.push((op >> 8) as u8);
+            rom.push(op as u8);
+        }
+        rom.extend_from_slice(&[0x12, 0x00, 0x12, 0x00]);
+        assert_cache_exact(&rom, &[1, 2, 3, 5, 8, 13, 21, 34, 55, 89, 144, 233]);
+    }
+}
+
+#[test]
+fn test_faults_stop_the_cpu() {
+    cpu_tester(&mut |cpu, _sender| {
+        let rom = RomBuilder::new().ld(V0, 1).ret().ld(V0, 2).build().unwrap();
+        cpu.load_rom(&rom);
+        cpu.run_cycle();
+        assert_eq!(cpu.fault(), None);
+        cpu.run_cycle();
+        assert_eq!(cpu.fault(), Some("RET with an empty stack"));
+        assert_eq!(cpu.get_pc(), 0x202);
+        // Nothing more runs, interpreted or cached.
+        cpu.run_cycle();
+        cpu.run_cycles(10);
+        assert_eq!((cpu.get_pc(), cpu.get_reg(0)), (0x202, 1));
+    });
+    cpu_tester(&mut |cpu, _sender| {
+        let rom = RomBuilder::new().label("deeper").call("deeper").build().unwrap();
+        cpu.load_rom(&rom);
+        cpu.run_cycles(100);
+        assert_eq!(cpu.fault(), Some("CALL with 16 calls already on the stack"));
+    });
+    cpu_tester(&mut |cpu, _sender| {
+        cpu.load_rom(&[0x80, 0x18]);
+        cpu.run_cycle();
+        assert_eq!(cpu.fault(), Some("Illegal opcode 0x8018"));
+    });
+}
+
+#[test]
+fn test_addresses_wrap() {
+    cpu_tester(&mut |cpu, _sender| {
+        // I at the last byte: FX55 wraps round to the start of memory, and
+        // a BNNN past the end lands back at the bottom.
+        let rom = RomBuilder::new()
+            .ld(V0, 0xAA)
+            .ld(V1, 0xBB)
+            .ld_i(0xFFF)
+            .ld_i_vx(V1)
+            .ld(V0, 0x02)
+            .jp_v0(0xFFF)
+            .build()
+            .unwrap();
+        cpu.load_rom(&rom);
+        for _ in 0..6 {
+            cpu.run_cycle();
+        }
+        assert_eq!((cpu.get_mem8(0xFFF), cpu.get_mem8(0x000)), (0xAA, 0xBB));
+        assert_eq!(cpu.get_pc(), 0x001);
+        assert_eq!(cpu.fault(), None);
+    });
+}
+
+#[test]
+fn test_run_masks_operands() {
+    cpu_tester(&mut |cpu, _sender| {
+        // Instructions built by hand run as the opcode they encode to would.
+        cpu.run(Instruction::LdByte(0x12, 0xAB));
+        cpu.run(Instruction::LdI(0xF234));
+        cpu.run(Instruction::LdIVx(0x13));
+        assert_eq!(cpu.get_reg(2), 0xAB);
+        assert_eq!((cpu.get_mem8(0x234), cpu.get_mem8(0x236)), (0x00, 0xAB));
+        cpu.run(Instruction::Jp(0xFFFF));
+        assert_eq!(cpu.get_pc(), 0xFFF);
+        assert_eq!(cpu.fault(), None);
+    });
+}
@@ -0,0 +1,56 @@
+extern crate rand;
+extern crate rust8;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use rust8::fuzz;
+
+// Inputs of random bytes, which mostly fault within a few instructions.
+#[test]
+fn test_random_bytes() {
+    let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+    for _ in 0..300 {
+        let len = rng.gen_range(0, 64);
+        let data: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+        fuzz::run(&data);
+    }
+}
+
+// Inputs of opcodes that run, with a jump back to the start at the end.
+// Most jumps and calls stay within the program, but now and then one goes
+// anywhere, as do I and the other operands: off the end of memory, I near
+// the top, deep recursion, key checks and waits, drawing and self-modifying
+// code.
+#[test]
+fn test_random_programs() {
+    let mut rng = XorShiftRng::from_seed([8, 7, 6, 5]);
+    let mut faulted = 0;
+    for _ in 0..300 {
+        let mut data: Vec<u8> = (0..5).map(|_| rng.gen()).collect();
+        let typed = data[4] % 8;
+        data.extend((0..typed).map(|_| *rng.choose(b"1234',.paoeu;qjkl").unwrap()));
+        let len = rng.gen_range(1, 200);
+        for _ in 0..len {
+            let wild = rng.gen_weighted_bool(20);
+            let target = if wild { rng.gen_range(0, 0x1000) } else { 0x200 + rng.gen_range(0, len) * 2 };
+            let op = match rng.gen_range(0, 9) {
+                0 => *rng.choose(&[0x00E0, 0x00EE]).unwrap(),
+                1 => 0x1000 | target,
+                2 if wild => 0x2000 | target,
+                3 => rng.gen_range(0xA000, 0xB000),
+                4 if wild => rng.gen_range(0xB000, 0xC000),
+                5 => (rng.gen_range(0xE, 0x10) << 12) | rng.gen_range(0, 0x1000),
+                6 => rng.gen_range(0xC000, 0xE000),
+                _ => rng.gen_range(0x3000, 0xA000),
+            };
+            data.push((op >> 8) as u8);
+            data.push(op as u8);
+        }
+        data.extend_from_slice(&[0x12, 0x00]);
+        if fuzz::run(&data).is_some() {
+            faulted += 1;
+        }
+    }
+    // Many are cut short by a fault, but not all.
+    assert!(faulted > 0 && faulted < 300, "{} faulted", faulted);
+}
@@ -0,0 +1,135 @@
+extern crate rust8;
+
+use rust8::harness::Harness;
+use rust8::rombuilder::*;
+
+#[test]
+fn test_screen_and_registers() {
+    let rom = RomBuilder::new()
+        .ld(V0, 7)
+        .ld(V1, 0)
+        .ld_f_vx(V0)
+        .draw(V1, V1, 5)
+        .ld_i(0x300)
+        .ld_b_vx(V0)
+        .label("halt")
+        .jp("halt")
+        .build()
+        .unwrap();
+    Harness::new(&rom).run(|machine| {
+        machine.run_until_halt();
+        machine.assert_pc(0x20C);
+        machine.assert_reg(V0, 7);
+        machine.assert_i(0x300);
+        machine.assert_memory(0x300, &[0, 0, 7]);
+        machine.assert_screen(
+            "
+            ####
+            ...#
+            ..#.
+            .#..
+            .#..
+            ",
+        );
+    });
+}
+
+#[test]
+fn test_scripted_keys() {
+    let rom = RomBuilder::new()
+        .ld_vx_k(V0)
+        .ld(V1, 0)
+        .jp(0x206)
+        .label("waits")
+        .sknp(V0)
+        .jp("waits")
+        .ld_vx_k(V0)
+        .jp(0x202)
+        .build()
+        .unwrap();
+    Harness::new(&rom).run(|machine| {
+        machine.press(0xA);
+        machine.run_until_pc(0x206);
+        machine.assert_reg(V0, 0xA);
+
+        // The key stays held until released.
+        machine.steps(10);
+        machine.assert_pc(0x206);
+        machine.release(0xA);
+        machine.run_until_halt();
+        assert!(machine.is_waiting_for_key());
+        machine.assert_pc(0x20A);
+
+        machine.press(0x3);
+        machine.step();
+        machine.assert_reg(V0, 0x3);
+    });
+}
+
+#[test]
+fn test_frames_tick_timers() {
+    let rom = RomBuilder::new()
+        .ld(V0, 10)
+        .ld_dt_vx(V0)
+        .label("wait")
+        .ld_vx_dt(V1)
+        .se(V1, 0)
+        .jp("wait")
+        .label("halt")
+        .jp("halt")
+        .build()
+        .unwrap();
+    Harness::new(&rom).ipf(4).run(|machine| {
+        machine.run_frames(3);
+        assert_eq!(machine.instructions(), 12);
+        assert_eq!(machine.cpu().get_delay(), 7);
+        machine.run_until_halt();
+        assert_eq!(machine.frames(), 10);
+    });
+}
+
+#[test]
+#[should_panic(expected = "! 1 ...#.#")]
+fn test_screen_mismatch_shows_diff() {
+    let rom = RomBuilder::new()
+        .ld(V0, 7)
+        .ld_f_vx(V0)
+        .draw(V1, V1, 5)
+        .label("halt")
+        .jp("halt")
+        .build()
+        .unwrap();
+    Harness::new(&rom).run(|machine| {
+        machine.run_until_halt();
+        machine.assert_screen(
+            "
+            ####
+            ...#.#
+            ",
+        );
+    });
+}
+
+#[test]
+#[should_panic(expected = "screen is 64x32, but the art is 65x1")]
+fn test_screen_size_mismatch() {
+    let rom = RomBuilder::new().label("halt").jp("halt").build().unwrap();
+    Harness::new(&rom).run(|machine| {
+        machine.run_until_halt();
+        machine.assert_screen(&"#".repeat(65));
+    });
+}
+
+#[test]
+#[should_panic(expected = "none is pressed")]
+fn test_key_wait_without_press_fails() {
+    let rom = RomBuilder::new().ld_vx_k(V0).build().unwrap();
+    Harness::new(&rom).run(|machine| machine.step());
+}
+
+#[test]
+#[should_panic(expected = "still running after 100 instructions")]
+fn test_limit() {
+    let rom = RomBuilder::new().label("loop").add(V0, 1).jp("loop").build().unwrap();
+    Harness::new(&rom).limit(100).run(|machine| machine.run_until_halt());
+}
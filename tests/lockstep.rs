@@ -0,0 +1,363 @@
+extern crate rand;
+extern crate rust8;
+
+mod reference;
+
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use reference::{Inputs, Reference};
+use rust8::asm;
+use rust8::harness::{Harness, Machine};
+use rust8::instruction::Instruction;
+use rust8::platform::Platform;
+use rust8::quirks::Quirks;
+
+// Runs ROMs on `CPU` and on the reference interpreter in tests/reference
+// side by side, one instruction at a time, checking registers, I, PC, the
+// stack, the timers, memory and the screen after each. The first difference
+// fails the run with the instructions leading up to it.
+
+const IPF: u64 = 15;
+
+// Instructions shown before a divergence.
+const HISTORY: usize = 8;
+
+// Why a run that never diverged stopped.
+#[derive(Debug, PartialEq)]
+enum Stop {
+    // It ran every step it was given.
+    Done,
+    Halted,
+    WaitingForKey,
+    // It reached something the reference doesn't define.
+    Undefined(String),
+}
+
+fn lockstep(harness: &Harness, rom: &[u8], keys: &[u8], steps: u64) -> Result<Stop, String> {
+    lockstep_with(harness, rom, keys, steps, None)
+}
+
+// As `lockstep`, with the reference on `quirks` instead of the CPU's.
+fn lockstep_with(
+    harness: &Harness,
+    rom: &[u8],
+    keys: &[u8],
+    steps: u64,
+    quirks: Option<Quirks>,
+) -> Result<Stop, String> {
+    harness.run(|machine| {
+        for &key in keys {
+            machine.press(key);
+        }
+        let quirks = quirks.unwrap_or_else(|| machine.cpu().quirks());
+        let mut reference = Reference::new(rom, machine.cpu().memory().len(), quirks);
+        let mut history = Vec::new();
+        if let Some(difference) = compare(machine, &reference) {
+            return Err(format!("before the first instruction: {}", difference));
+        }
+        for step in 0..steps {
+            let opcode = match reference.opcode() {
+                Some(opcode) => opcode,
+                None => return Ok(Stop::Undefined(format!("PC 0x{:03X} is past the end of memory", reference.pc))),
+            };
+            if machine.is_halted() {
+                return Ok(Stop::Halted);
+            }
+            if machine.is_waiting_for_key() {
+                return Ok(Stop::WaitingForKey);
+            }
+            if history.len() == HISTORY {
+                history.remove(0);
+            }
+            history.push(format!("0x{:03X}  {:04X}  {}", reference.pc, opcode, Instruction::decode(opcode)));
+
+            let held = held_keys(machine);
+            let frames = machine.frames();
+            let ran = panic::catch_unwind(AssertUnwindSafe(|| machine.step()));
+            // A CPU that panicked may have left the keyboard poisoned, and
+            // has no inputs to share.
+            let inputs = match ran {
+                Ok(()) => cpu_inputs(machine, opcode, held),
+                Err(_) => Inputs::default(),
+            };
+            if let Err(reason) = reference.step(&inputs) {
+                return Ok(Stop::Undefined(reason));
+            }
+            let difference = match ran {
+                Ok(()) => {
+                    for _ in frames..machine.frames() {
+                        reference.tick();
+                    }
+                    compare(machine, &reference)
+                }
+                Err(panic) => Some(format!("CPU panicked: {}", panic_message(&panic))),
+            };
+            if let Some(difference) = difference {
+                return Err(format!("step {}: {}\n  {}", step, difference, history.join("\n  ")));
+            }
+        }
+        Ok(Stop::Done)
+    })
+}
+
+fn held_keys(machine: &mut Machine) -> [bool; 16] {
+    let mut keys = [false; 16];
+    for (key, held) in keys.iter_mut().enumerate() {
+        *held = machine.cpu().get_key(key);
+    }
+    keys
+}
+
+// What the CPU's instruction got from outside, for the reference to get the
+// same: the keys it saw, the key FX0A took and CXNN's random byte.
+fn cpu_inputs(machine: &mut Machine, opcode: u16, before: [bool; 16]) -> Inputs {
+    let keys = held_keys(machine);
+    let x = ((opcode >> 8) & 0xF) as usize;
+    let vx = machine.cpu().get_reg(x);
+    // A new press releases every other key, so the key FX0A took is the one
+    // held now.
+    let key_press = if opcode & 0xF0FF == 0xF00A {
+        (0..16u8)
+            .find(|&key| keys[key as usize] && !before[key as usize])
+            .or_else(|| (0..16u8).find(|&key| keys[key as usize]))
+    } else {
+        None
+    };
+    Inputs {
+        keys,
+        key_press,
+        // CXNN left the random byte masked in VX, and masking it again
+        // changes nothing.
+        random: vx,
+    }
+}
+
+// The first thing that differs between the two, if anything does.
+fn compare(machine: &mut Machine, reference: &Reference) -> Option<String> {
+    let cpu = machine.cpu();
+    for x in 0..16 {
+        if cpu.get_reg(x) != reference.v[x] {
+            return Some(differs(&format!("V{:X}", x), cpu.get_reg(x), reference.v[x]));
+        }
+    }
+    if cpu.get_i() != reference.i {
+        return Some(differs("I", cpu.get_i(), reference.i));
+    }
+    if cpu.get_pc() != reference.pc {
+        return Some(differs("PC", cpu.get_pc(), reference.pc));
+    }
+    // The CPU keeps the address of each CALL, the reference where it returns.
+    let stack: Vec<u16> = cpu.get_stack().iter().map(|&addr| addr + 2).collect();
+    if stack != reference.stack {
+        return Some(format!(
+            "return addresses are {:03X?} in the CPU but {:03X?} in the reference",
+            stack, reference.stack
+        ));
+    }
+    if cpu.get_delay() != reference.delay {
+        return Some(differs("the delay timer", cpu.get_delay(), reference.delay));
+    }
+    if cpu.get_sound() != reference.sound {
+        return Some(differs("the sound timer", cpu.get_sound(), reference.sound));
+    }
+    let memory = cpu.memory();
+    if memory != &reference.memory[..] {
+        let addr = (0..memory.len()).find(|&addr| memory[addr] != reference.memory[addr]).unwrap();
+        return Some(differs(&format!("memory at 0x{:03X}", addr), memory[addr], reference.memory[addr]));
+    }
+    let rows = cpu.get_display();
+    let expected = reference.rows();
+    if let Some(y) = (0..rows.len()).find(|&y| rows[y] != expected[y]) {
+        let x = (rows[y] ^ expected[y]).leading_zeros();
+        let lit = |row: u64| if row & (1 << (63 - x)) != 0 { "lit" } else { "dark" };
+        return Some(format!(
+            "pixel ({}, {}) is {} in the CPU but {} in the reference",
+            x,
+            y,
+            lit(rows[y]),
+            lit(expected[y])
+        ));
+    }
+    None
+}
+
+fn differs<T: std::fmt::UpperHex>(what: &str, cpu: T, reference: T) -> String {
+    format!("{} is 0x{:02X} in the CPU but 0x{:02X} in the reference", what, cpu, reference)
+}
+
+fn panic_message(panic: &Box<dyn std::any::Any + Send>) -> String {
+    if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else {
+        "(no message)".to_string()
+    }
+}
+
+// A random program: 96 instructions at 0x200-0x2BF ending in a jump back to
+// the start, then four subroutines of eight. Jumps stay inside the main
+// part and calls go to the subroutines, which are straight-line code ending
+// in RET. I points past the program, or for some programs into it, so they
+// rewrite their own code.
+fn random_program(rng: &mut XorShiftRng) -> Vec<u8> {
+    let code_writes = rng.gen::<bool>();
+    let mut ops = Vec::new();
+    while ops.len() < 95 {
+        let x = rng.gen_range(0, 16u16);
+        let op = match rng.gen_range(0, 6) {
+            0 => 0x1200 | (rng.gen_range(0, 0x60) * 2),
+            1 => 0x22C0 | (rng.gen_range(0, 4) * 0x10),
+            2 => 0xB200 | (rng.gen_range(0, 0x60) * 2),
+            // Skips on a key: loads the key number first, so it is one.
+            3 if ops.len() < 94 => {
+                ops.push(0x6000 | x << 8 | rng.gen_range(0, 16));
+                [0xE09E, 0xE0A1][rng.gen_range(0, 2)] | x << 8
+            }
+            _ => random_op(rng, code_writes, true),
+        };
+        ops.push(op);
+    }
+    ops.push(0x1200);
+    for _ in 0..4 {
+        for _ in 0..7 {
+            ops.push(random_op(rng, code_writes, false));
+        }
+        ops.push(0x00EE);
+    }
+    let mut rom = Vec::new();
+    for op in ops {
+        rom.push((op >> 8) as u8);
+        rom.push(op as u8);
+    }
+    rom
+}
+
+// An instruction that goes on to the next one, or with `skips` may skip it.
+fn random_op(rng: &mut XorShiftRng, code_writes: bool, skips: bool) -> u16 {
+    let x = rng.gen_range(0, 16u16);
+    let y = rng.gen_range(0, 16u16);
+    let nn = rng.gen_range(0, 256u16);
+    match rng.gen_range(if skips { 0 } else { 4 }, 22) {
+        0 => 0x3000 | x << 8 | nn,
+        1 => 0x4000 | x << 8 | nn,
+        2 => 0x5000 | x << 8 | y << 4,
+        3 => 0x9000 | x << 8 | y << 4,
+        4 => 0x00E0,
+        5 | 6 => 0x6000 | x << 8 | nn,
+        7 => 0x7000 | x << 8 | nn,
+        8 | 9 => 0x8000 | x << 8 | y << 4 | [0, 1, 2, 3, 4, 5, 6, 7, 0xE][rng.gen_range(0, 9)],
+        10 if code_writes => 0xA200 | rng.gen_range(0, 0xF0),
+        10 => 0xA300 | rng.gen_range(0, 0xF0),
+        11 => 0xC000 | x << 8 | nn,
+        12 => 0xD000 | x << 8 | y << 4 | rng.gen_range(0, 16),
+        13 => 0xF007 | x << 8,
+        14 => 0xF015 | x << 8,
+        15 => 0xF018 | x << 8,
+        16 => 0xF01E | x << 8,
+        17 => 0xF029 | x << 8,
+        18 => 0xF033 | x << 8,
+        19 => 0xF055 | x << 8,
+        20 => 0xF065 | x << 8,
+        _ => 0x7000 | x << 8 | rng.gen_range(0, 16),
+    }
+}
+
+fn assemble(file: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join(file);
+    asm::assemble(&fs::read_to_string(&path).unwrap()).unwrap_or_else(|err| panic!("{}: {}", file, err))
+}
+
+#[test]
+fn test_random_programs() {
+    let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+    let mut finished = 0;
+    for seed in 0..60 {
+        let rom = random_program(&mut rng);
+        for &platform in Platform::all() {
+            let harness = Harness::new(&rom).platform(platform).ipf(IPF).seed(seed);
+            let stop = lockstep(&harness, &rom, &[], 400).unwrap_or_else(|err| {
+                panic!("program {} on {}, {:02X?}\n{}", seed, platform.name(), rom, err)
+            });
+            if let Stop::Undefined(_) = stop {
+                continue;
+            }
+            finished += 1;
+        }
+    }
+    // Most should get through before doing anything undefined, or this
+    // checks little.
+    assert!(finished > 90, "only {} of 180 runs finished", finished);
+}
+
+#[test]
+fn test_conformance_roms() {
+    let fixtures: [(&str, &[u8]); 4] = [
+        ("opcodes.asm", &[]),
+        ("flags.asm", &[]),
+        ("quirks.asm", &[]),
+        ("keypad.asm", &[0x7, 0xA]),
+    ];
+    for &(file, keys) in fixtures.iter() {
+        let rom = assemble(&format!("conformance/{}", file));
+        for &platform in Platform::all() {
+            let harness = Harness::new(&rom).platform(platform).ipf(IPF);
+            let stop = lockstep(&harness, &rom, keys, 60 * IPF)
+                .unwrap_or_else(|err| panic!("{} on {}: {}", file, platform.name(), err));
+            assert!(
+                stop == Stop::Halted || stop == Stop::WaitingForKey,
+                "{} on {}: {:?}",
+                file,
+                platform.name(),
+                stop
+            );
+        }
+    }
+}
+
+#[test]
+fn test_recompiler_demo() {
+    let rom = assemble("recompiled/demo.asm");
+    // Its jump table is for BNNN jumping from V0.
+    for &platform in Platform::all().iter().filter(|platform| !Quirks::for_platform(**platform).jump) {
+        let harness = Harness::new(&rom).platform(platform).ipf(IPF).seed(7);
+        let stop = lockstep(&harness, &rom, &[], 5000)
+            .unwrap_or_else(|err| panic!("demo.asm on {}: {}", platform.name(), err));
+        assert!(stop == Stop::Done || stop == Stop::Halted, "demo.asm on {}: {:?}", platform.name(), stop);
+    }
+}
+
+#[test]
+fn test_reports_first_divergence() {
+    use rust8::rombuilder::*;
+
+    let rom = RomBuilder::new()
+        .ld(V0, 0x03)
+        .ld(V1, 0x10)
+        .shr(V0, V1)
+        .label("halt")
+        .jp("halt")
+        .build()
+        .unwrap();
+    let harness = Harness::new(&rom).platform(Platform::Chip8);
+    let quirks = Quirks {
+        shift: true,
+        ..Quirks::for_platform(Platform::Chip8)
+    };
+    let err = lockstep_with(&harness, &rom, &[], 10, Some(quirks)).unwrap_err();
+    assert!(err.starts_with("step 2: V0 is 0x08 in the CPU but 0x01 in the reference"), "{}", err);
+    assert!(err.contains("0x204  8016  SHR V0, V1"), "{}", err);
+}
+
+#[test]
+fn test_stops_at_undefined_behaviour() {
+    use rust8::rombuilder::*;
+
+    let rom = RomBuilder::new().ld(V0, 1).ret().build().unwrap();
+    let stop = lockstep(&Harness::new(&rom), &rom, &[], 10).unwrap();
+    assert_eq!(stop, Stop::Undefined("RET with an empty stack".to_string()));
+}
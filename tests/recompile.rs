@@ -0,0 +1,75 @@
+extern crate rust8;
+
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+
+use rust8::asm;
+use rust8::cpu::CPU;
+use rust8::display::Display;
+use rust8::displayimpl::screen_to_ascii;
+use rust8::keyboard::Keyboard;
+use rust8::ram::RAM;
+use rust8::recompile::recompile;
+
+// Made by `rust8 recompile` from demo.asm, assembled.
+mod demo {
+    include!("recompiled/demo.rs");
+}
+
+const DEMO_SOURCE: &str = include_str!("recompiled/demo.asm");
+
+// The screen after every frame, then the registers, I, PC and memory.
+fn run_frames<F>(rom: &[u8], frames: usize, ipf: u64, mut run: F) -> (Vec<String>, Vec<u16>, Vec<u8>)
+where
+    F: FnMut(&mut CPU, u64),
+{
+    let (_, receiver) = channel();
+    let mut display = Display::init();
+    let mut keyboard = Arc::new(Mutex::new(Keyboard::init(receiver)));
+    let mut ram = RAM::init();
+    let mut cpu = CPU::init(&mut ram, &mut display, &mut keyboard, None);
+    cpu.load_rom(rom);
+    cpu.set_seed(3);
+    let mut screens = Vec::new();
+    for _ in 0..frames {
+        run(&mut cpu, ipf);
+        cpu.end_frame();
+        screens.push(screen_to_ascii(cpu.display()));
+    }
+    let mut registers: Vec<u16> = (0..16).map(|x| cpu.get_reg(x) as u16).collect();
+    registers.extend_from_slice(&[cpu.get_i(), cpu.get_pc(), cpu.get_delay() as u16]);
+    let memory = (0..4096).map(|addr| cpu.get_mem8(addr)).collect();
+    (screens, registers, memory)
+}
+
+fn interpret(cpu: &mut CPU, n: u64) {
+    for _ in 0..n {
+        cpu.run_cycle();
+    }
+}
+
+#[test]
+fn test_recompiled_source_is_current() {
+    let rom = asm::assemble(DEMO_SOURCE).unwrap();
+    assert_eq!(&demo::ROM[..], &rom[..]);
+    assert_eq!(recompile(&rom, "demo.ch8"), include_str!("recompiled/demo.rs"));
+}
+
+#[test]
+fn test_recompiled_matches_interpreter() {
+    // Odd instruction counts split blocks across frames. Each of the 80
+    // draws also ends a frame, with the vblank quirk.
+    for &ipf in [1, 7, 15, 64].iter() {
+        let frames = 3000 / ipf as usize + 80;
+        let (screens, registers, memory) = run_frames(&demo::ROM, frames, ipf, interpret);
+        let recompiled = run_frames(&demo::ROM, frames, ipf, demo::run);
+        for (frame, (a, b)) in screens.iter().zip(recompiled.0.iter()).enumerate() {
+            assert_eq!(a, b, "frame {} at {} instructions a frame", frame, ipf);
+        }
+        assert_eq!(registers, recompiled.1);
+        assert_eq!(memory, recompiled.2);
+        // The program halts having patched itself.
+        assert_eq!(registers[5], 80);
+        assert_eq!(&memory[0x23E..0x240], &[0x83, 0x80]);
+    }
+}
@@ -0,0 +1,62 @@
+; Exercises the recompiler: calls, skips, a computed jump through a table,
+; timers, random numbers, BCD written into the ROM's own data, and code that
+; rewrites part of a subroutine after 40 passes.
+start:
+    CLS
+    LD V5, 0
+loop:
+    CALL draw
+    ADD V5, 1
+    LD V0, V5
+    LD V1, 0x03
+    AND V0, V1
+    SHL V0, V0
+    JP V0, table
+table:
+    JP bump_x
+    JP bump_y
+    JP roll
+    JP tick
+bump_x:
+    ADD V6, 1
+    JP next
+bump_y:
+    ADD V7, 2
+    JP next
+roll:
+    RND V8, 0x0F
+    JP next
+tick:
+    LD DT, V5
+    JP next
+next:
+    SNE V5, 40
+    CALL patch
+    SE V5, 80
+    JP loop
+halt:
+    JP halt
+
+draw:
+    LD V9, V6
+    LD VA, 0x0F
+    AND V9, VA
+    LD F, V9
+    LD V2, V5
+retarget:
+    LD V3, V7
+    DRW V2, V3, 5
+    LD I, scratch
+    LD B, V5
+    RET
+
+; Turns `LD V3, V7` into `LD V3, V8`.
+patch:
+    LD I, retarget
+    LD V0, 0x83
+    LD V1, 0x80
+    LD [I], V1
+    RET
+
+scratch:
+    DB 0, 0, 0
@@ -0,0 +1,109 @@
+// Recompiled from demo.ch8 by `rust8 recompile`. `run` leaves a CPU with
+// ROM loaded exactly as the interpreter would, and hands anything not
+// recovered here (computed jumps, code the ROM rewrites) to it.
+
+use rust8::cpu::CPU;
+use rust8::instruction::Instruction::*;
+use rust8::ram::ROM_START;
+
+#[rustfmt::skip]
+pub const ROM: [u8; 85] = [
+    0x00, 0xE0, 0x65, 0x00, 0x22, 0x34, 0x75, 0x01, 0x80, 0x50, 0x61, 0x03,
+    0x80, 0x12, 0x80, 0x0E, 0xB2, 0x12, 0x12, 0x1A, 0x12, 0x1E, 0x12, 0x22,
+    0x12, 0x26, 0x76, 0x01, 0x12, 0x2A, 0x77, 0x02, 0x12, 0x2A, 0xC8, 0x0F,
+    0x12, 0x2A, 0xF5, 0x15, 0x12, 0x2A, 0x45, 0x28, 0x22, 0x48, 0x35, 0x50,
+    0x12, 0x04, 0x12, 0x32, 0x89, 0x60, 0x6A, 0x0F, 0x89, 0xA2, 0xF9, 0x29,
+    0x82, 0x50, 0x83, 0x70, 0xD2, 0x35, 0xA2, 0x52, 0xF5, 0x33, 0x00, 0xEE,
+    0xA2, 0x3E, 0x60, 0x83, 0x61, 0x80, 0xF1, 0x55, 0x00, 0xEE, 0x00, 0x00,
+    0x00,
+];
+
+// The recovered code, as start and end addresses.
+const CODE: [(usize, usize); 2] = [
+    (0x200, 0x212),
+    (0x234, 0x248),
+];
+
+// Runs `n` instructions, as `n` calls to `cpu.run_cycle()` would.
+pub fn run(cpu: &mut CPU, n: u64) {
+    // Only the interpreter logs instructions and tells observers of them.
+    if cpu.is_traced() {
+        for _ in 0..n {
+            cpu.run_cycle();
+        }
+        return;
+    }
+    let mut left = n;
+    let mut intact = code_intact(cpu, 0, usize::MAX);
+    while left > 0 {
+        // Stopped, the CPU runs nothing for the rest of the instructions.
+        if cpu.fault().is_some() || cpu.is_waiting_for_vblank() {
+            return;
+        }
+        match cpu.get_pc() {
+            0x200 if intact && left >= 3 => {
+                cpu.run(Cls); // CLS
+                cpu.run(LdByte(0x5, 0x0)); // LD V5, 0x00
+                cpu.run(Call(0x234)); // CALL 0x234
+                left -= 3;
+            }
+            0x206 if intact && left >= 6 => {
+                cpu.run(AddByte(0x5, 0x1)); // ADD V5, 0x01
+                cpu.run(LdReg(0x0, 0x5)); // LD V0, V5
+                cpu.run(LdByte(0x1, 0x3)); // LD V1, 0x03
+                cpu.run(And(0x0, 0x1)); // AND V0, V1
+                cpu.run(Shl(0x0, 0x0)); // SHL V0, V0
+                cpu.run(JpV0(0x212)); // JP V0, 0x212
+                left -= 6;
+            }
+            0x234 if intact && left >= 9 => {
+                cpu.run(LdReg(0x9, 0x6)); // LD V9, V6
+                cpu.run(LdByte(0xA, 0xF)); // LD VA, 0x0F
+                cpu.run(And(0x9, 0xA)); // AND V9, VA
+                cpu.run(LdFVx(0x9)); // LD F, V9
+                cpu.run(LdReg(0x2, 0x5)); // LD V2, V5
+                cpu.run(LdReg(0x3, 0x7)); // LD V3, V7
+                cpu.run(Drw(0x2, 0x3, 0x5)); // DRW V2, V3, 5
+                if cpu.is_waiting_for_vblank() {
+                    return;
+                }
+                cpu.run(LdI(0x252)); // LD I, 0x252
+                let i = cpu.get_i() as usize;
+                cpu.run(LdBVx(0x5)); // LD B, V5
+                intact = code_intact(cpu, i, i + 3);
+                left -= 9;
+            }
+            0x246 if intact && left >= 1 => {
+                cpu.run(Ret); // RET
+                left -= 1;
+            }
+            _ => {
+                let written = writes(cpu);
+                cpu.run_cycle();
+                left -= 1;
+                if let Some((from, to)) = written {
+                    intact = intact && code_intact(cpu, from, to);
+                }
+            }
+        }
+    }
+}
+
+// The memory the instruction at PC writes, if it is FX33 or FX55.
+fn writes(cpu: &CPU) -> Option<(usize, usize)> {
+    let pc = cpu.get_pc() as usize;
+    let op = (cpu.get_mem8(pc) as u16) << 8 | cpu.get_mem8(pc + 1) as u16;
+    let i = cpu.get_i() as usize;
+    match op & 0xF0FF {
+        0xF033 => Some((i, i + 3)),
+        0xF055 => Some((i, i + ((op >> 8) & 0xF) as usize + 1)),
+        _ => None,
+    }
+}
+
+// Whether the recovered code between `from` and `to` is as recompiled.
+fn code_intact(cpu: &CPU, from: usize, to: usize) -> bool {
+    CODE.iter().all(|&(start, end)| {
+        (start.max(from)..end.min(to)).all(|addr| cpu.get_mem8(addr) == ROM[addr - ROM_START])
+    })
+}
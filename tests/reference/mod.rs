@@ -0,0 +1,345 @@
+// A second CHIP-8, written straight from the instruction set's description
+// and kept as plain as possible: no decoding tables, no caches, a bool per
+// pixel. It shares nothing with `CPU` but the quirk settings, so the two
+// can be run side by side and checked against each other (tests/lockstep.rs).
+//
+// Anything the specification leaves undefined (returning with an empty
+// stack, reading or writing past the end of memory, opcodes that aren't
+// instructions) is reported instead of run, since there is no right answer
+// to compare `CPU` against.
+
+use rust8::quirks::Quirks;
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+pub const STACK_DEPTH: usize = 16;
+
+const FONT_START: usize = 0x000;
+const ROM_START: usize = 0x200;
+
+#[rustfmt::skip]
+const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+// What an instruction may need from outside the machine.
+#[derive(Default)]
+pub struct Inputs {
+    // Which keys are held.
+    pub keys: [bool; 16],
+    // The key FX0A gets, if one was pressed.
+    pub key_press: Option<u8>,
+    // The random byte CXNN masks.
+    pub random: u8,
+}
+
+pub struct Reference {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    // Return addresses: the instruction after each CALL.
+    pub stack: Vec<u16>,
+    pub memory: Vec<u8>,
+    pub screen: [[bool; WIDTH]; HEIGHT],
+    pub delay: u8,
+    pub sound: u8,
+    pub quirks: Quirks,
+    // Whether a draw with the vblank quirk has the machine waiting for the
+    // next tick.
+    pub vblank_wait: bool,
+}
+
+impl Reference {
+    pub fn new(rom: &[u8], memory_size: usize, quirks: Quirks) -> Reference {
+        let mut memory = vec![0; memory_size];
+        memory[FONT_START..FONT_START + FONT.len()].copy_from_slice(&FONT);
+        memory[ROM_START..ROM_START + rom.len()].copy_from_slice(rom);
+        Reference {
+            v: [0; 16],
+            i: 0,
+            pc: ROM_START as u16,
+            stack: Vec::new(),
+            memory,
+            screen: [[false; WIDTH]; HEIGHT],
+            delay: 0,
+            sound: 0,
+            quirks,
+            vblank_wait: false,
+        }
+    }
+
+    // The instruction at PC, if it is inside memory.
+    pub fn opcode(&self) -> Option<u16> {
+        let pc = self.pc as usize;
+        if pc + 1 < self.memory.len() {
+            Some((self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16)
+        } else {
+            None
+        }
+    }
+
+    // The 60Hz timers.
+    pub fn tick(&mut self) {
+        self.vblank_wait = false;
+        self.delay = self.delay.saturating_sub(1);
+        self.sound = self.sound.saturating_sub(1);
+    }
+
+    // Screen rows as bits, leftmost pixel highest, as `CPU::get_display`
+    // has them.
+    pub fn rows(&self) -> [u64; HEIGHT] {
+        let mut rows = [0; HEIGHT];
+        for (row, pixels) in rows.iter_mut().zip(self.screen.iter()) {
+            for &pixel in pixels.iter() {
+                *row = *row << 1 | pixel as u64;
+            }
+        }
+        rows
+    }
+
+    // Runs the instruction at PC, or says why it can't be run. Does nothing
+    // while waiting for the next tick.
+    pub fn step(&mut self, inputs: &Inputs) -> Result<(), String> {
+        if self.vblank_wait {
+            return Ok(());
+        }
+        let opcode = match self.opcode() {
+            Some(opcode) => opcode,
+            None => return Err(format!("PC 0x{:03X} is past the end of memory", self.pc)),
+        };
+        let x = ((opcode >> 8) & 0xF) as usize;
+        let y = ((opcode >> 4) & 0xF) as usize;
+        let n = (opcode & 0xF) as usize;
+        let nn = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+        let mut next = self.pc + 2;
+        match (opcode >> 12, x, y, n) {
+            // 00E0: clear the screen.
+            (0x0, 0x0, 0xE, 0x0) => self.screen = [[false; WIDTH]; HEIGHT],
+            // 00EE: return from a subroutine.
+            (0x0, 0x0, 0xE, 0xE) => match self.stack.pop() {
+                Some(addr) => next = addr,
+                None => return Err("RET with an empty stack".to_string()),
+            },
+            // 1NNN: jump to NNN.
+            (0x1, _, _, _) => next = nnn,
+            // 2NNN: call the subroutine at NNN.
+            (0x2, _, _, _) => {
+                if self.stack.len() == STACK_DEPTH {
+                    return Err(format!("CALL with {} calls already on the stack", STACK_DEPTH));
+                }
+                self.stack.push(next);
+                next = nnn;
+            }
+            // 3XNN: skip if VX == NN.
+            (0x3, _, _, _) => {
+                if self.v[x] == nn {
+                    next += 2;
+                }
+            }
+            // 4XNN: skip if VX != NN.
+            (0x4, _, _, _) => {
+                if self.v[x] != nn {
+                    next += 2;
+                }
+            }
+            // 5XY0: skip if VX == VY.
+            (0x5, _, _, 0x0) => {
+                if self.v[x] == self.v[y] {
+                    next += 2;
+                }
+            }
+            // 6XNN: VX = NN.
+            (0x6, _, _, _) => self.v[x] = nn,
+            // 7XNN: VX += NN, leaving VF alone.
+            (0x7, _, _, _) => self.v[x] = self.v[x].wrapping_add(nn),
+            // 8XY0: VX = VY.
+            (0x8, _, _, 0x0) => self.v[x] = self.v[y],
+            // 8XY1, 8XY2, 8XY3: VX |=, &= or ^= VY.
+            (0x8, _, _, 0x1) | (0x8, _, _, 0x2) | (0x8, _, _, 0x3) => {
+                self.v[x] = match n {
+                    0x1 => self.v[x] | self.v[y],
+                    0x2 => self.v[x] & self.v[y],
+                    _ => self.v[x] ^ self.v[y],
+                };
+                if self.quirks.logic {
+                    self.v[0xF] = 0;
+                }
+            }
+            // The arithmetic below sets VF last, so with X = F it holds the
+            // flag rather than the result.
+            // 8XY4: VX += VY, VF = carry.
+            (0x8, _, _, 0x4) => {
+                let sum = self.v[x] as u16 + self.v[y] as u16;
+                self.v[x] = sum as u8;
+                self.v[0xF] = (sum > 0xFF) as u8;
+            }
+            // 8XY5: VX -= VY, VF = NOT borrow.
+            (0x8, _, _, 0x5) => {
+                let (vx, vy) = (self.v[x], self.v[y]);
+                self.v[x] = vx.wrapping_sub(vy);
+                self.v[0xF] = (vx >= vy) as u8;
+            }
+            // 8XY6: VX = VY >> 1 (or VX >> 1), VF = the bit shifted out.
+            (0x8, _, _, 0x6) => {
+                let source = if self.quirks.shift { self.v[x] } else { self.v[y] };
+                self.v[x] = source >> 1;
+                self.v[0xF] = source & 1;
+            }
+            // 8XY7: VX = VY - VX, VF = NOT borrow.
+            (0x8, _, _, 0x7) => {
+                let (vx, vy) = (self.v[x], self.v[y]);
+                self.v[x] = vy.wrapping_sub(vx);
+                self.v[0xF] = (vy >= vx) as u8;
+            }
+            // 8XYE: VX = VY << 1 (or VX << 1), VF = the bit shifted out.
+            (0x8, _, _, 0xE) => {
+                let source = if self.quirks.shift { self.v[x] } else { self.v[y] };
+                self.v[x] = source << 1;
+                self.v[0xF] = source >> 7;
+            }
+            // 9XY0: skip if VX != VY.
+            (0x9, _, _, 0x0) => {
+                if self.v[x] != self.v[y] {
+                    next += 2;
+                }
+            }
+            // ANNN: I = NNN.
+            (0xA, _, _, _) => self.i = nnn,
+            // BNNN: jump to NNN + V0 (or XNN + VX).
+            (0xB, _, _, _) => {
+                let offset = if self.quirks.jump { self.v[x] } else { self.v[0] };
+                next = nnn + offset as u16;
+            }
+            // CXNN: VX = a random byte & NN.
+            (0xC, _, _, _) => self.v[x] = inputs.random & nn,
+            // DXYN: draw the N bytes at I as a sprite at (VX, VY).
+            (0xD, _, _, _) => {
+                let i = self.i as usize;
+                if i + n > self.memory.len() {
+                    return Err(format!("DRW reads past the end of memory from I = 0x{:03X}", i));
+                }
+                self.v[0xF] = self.draw(self.v[x] as usize, self.v[y] as usize, i, n) as u8;
+                self.vblank_wait = self.quirks.vblank;
+            }
+            // EX9E: skip if key VX is held.
+            (0xE, _, 0x9, 0xE) => {
+                if self.key(inputs, x)? {
+                    next += 2;
+                }
+            }
+            // EXA1: skip if key VX isn't held.
+            (0xE, _, 0xA, 0x1) => {
+                if !self.key(inputs, x)? {
+                    next += 2;
+                }
+            }
+            // FX07: VX = the delay timer.
+            (0xF, _, 0x0, 0x7) => self.v[x] = self.delay,
+            // FX0A: wait for a key press and put it in VX.
+            (0xF, _, 0x0, 0xA) => match inputs.key_press {
+                Some(key) => self.v[x] = key,
+                None => next = self.pc,
+            },
+            // FX15: the delay timer = VX.
+            (0xF, _, 0x1, 0x5) => self.delay = self.v[x],
+            // FX18: the sound timer = VX.
+            (0xF, _, 0x1, 0x8) => self.sound = self.v[x],
+            // FX1E: I += VX. I wraps around the end of memory, as
+            // tests/conformance/quirks.asm checks.
+            (0xF, _, 0x1, 0xE) => self.i = ((self.i as usize + self.v[x] as usize) % self.memory.len()) as u16,
+            // FX29: I = the font sprite for the digit in VX. Only its low
+            // nibble counts, as interpreters mask it.
+            (0xF, _, 0x2, 0x9) => self.i = (FONT_START + (self.v[x] & 0xF) as usize * 5) as u16,
+            // FX33: the decimal digits of VX at I, I + 1 and I + 2.
+            (0xF, _, 0x3, 0x3) => {
+                let i = self.memory_at_i(3)?;
+                self.memory[i] = self.v[x] / 100;
+                self.memory[i + 1] = self.v[x] / 10 % 10;
+                self.memory[i + 2] = self.v[x] % 10;
+            }
+            // FX55: V0 to VX at I.
+            (0xF, _, 0x5, 0x5) => {
+                let i = self.memory_at_i(x + 1)?;
+                self.memory[i..=i + x].copy_from_slice(&self.v[..=x]);
+                self.i += self.memory_increment(x);
+            }
+            // FX65: V0 to VX from I.
+            (0xF, _, 0x6, 0x5) => {
+                let i = self.memory_at_i(x + 1)?;
+                self.v[..=x].copy_from_slice(&self.memory[i..=i + x]);
+                self.i += self.memory_increment(x);
+            }
+            _ => return Err(format!("0x{:04X} is not an instruction", opcode)),
+        }
+        self.pc = next;
+        Ok(())
+    }
+
+    // XORs the sprite onto the screen, returning whether any lit pixel went
+    // dark. The start wraps; pixels past an edge wrap or are clipped.
+    fn draw(&mut self, x: usize, y: usize, i: usize, n: usize) -> bool {
+        let (x, y) = (x % WIDTH, y % HEIGHT);
+        let mut collision = false;
+        for row in 0..n {
+            let byte = self.memory[i + row];
+            for col in 0..8 {
+                if byte & (0x80 >> col) == 0 {
+                    continue;
+                }
+                let (mut px, mut py) = (x + col, y + row);
+                if self.quirks.wrap {
+                    px %= WIDTH;
+                    py %= HEIGHT;
+                } else if px >= WIDTH || py >= HEIGHT {
+                    continue;
+                }
+                collision |= self.screen[py][px];
+                self.screen[py][px] = !self.screen[py][px];
+            }
+        }
+        collision
+    }
+
+    fn key(&self, inputs: &Inputs, x: usize) -> Result<bool, String> {
+        match inputs.keys.get(self.v[x] as usize) {
+            Some(&held) => Ok(held),
+            None => Err(format!("V{:X} = 0x{:02X} is not a key", x, self.v[x])),
+        }
+    }
+
+    // I, if the `len` bytes from it are inside memory.
+    fn memory_at_i(&self, len: usize) -> Result<usize, String> {
+        let i = self.i as usize;
+        if i + len > self.memory.len() {
+            return Err(format!("{} bytes at I = 0x{:03X} run past the end of memory", len, i));
+        }
+        Ok(i)
+    }
+
+    // How far FX55 and FX65 move I.
+    fn memory_increment(&self, x: usize) -> u16 {
+        if self.quirks.memory_leave_i_unchanged {
+            0
+        } else if self.quirks.memory_increment_by_x {
+            x as u16
+        } else {
+            x as u16 + 1
+        }
+    }
+}
@@ -0,0 +1,58 @@
+extern crate rust8;
+
+use std::env;
+use std::fs;
+
+use rust8::displayimpl::screen_to_ascii;
+use rust8::regress::{self, Manifest};
+use rust8::rombuilder::*;
+
+// Draws each key pressed, left to right.
+fn keys_rom() -> Vec<u8> {
+    RomBuilder::new()
+        .label("loop")
+        .ld_vx_k(V0)
+        .ld_f_vx(V0)
+        .draw(V1, V2, 5)
+        .add(V1, 5)
+        .jp("loop")
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn test_run_with_keys() {
+    let dir = env::temp_dir().join("rust8_test_regress");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("keys.ch8"), keys_rom()).unwrap();
+    fs::write(dir.join("crash.ch8"), [0x00, 0x00]).unwrap();
+    let manifest = Manifest::parse(
+        "
+        [keys]
+        rom = keys.ch8
+        frames = 20
+        keys = 5:1 2:7 5:-7
+
+        [crash]
+        rom = crash.ch8
+        frames = 1
+        ",
+        &dir,
+    )
+    .unwrap();
+
+    // Waits for keys through the frames between them.
+    let screen = regress::run(&manifest.entries[0]).unwrap();
+    let text = screen_to_ascii(&screen);
+    let rows: Vec<&str> = text.lines().map(|row| row.trim_end()).take(6).collect();
+    assert_eq!(rows, ["####   #", "   #  ##", "  #    #", " #     #", " #    ###", ""]);
+
+    let path = dir.join("keys.txt");
+    regress::save_baseline(&path, &screen).unwrap();
+    let baseline = regress::load_baseline(&path).unwrap().unwrap();
+    assert_eq!(regress::differing_pixels(&baseline, &screen), 0);
+    assert!(regress::load_baseline(&dir.join("none.txt")).unwrap().is_none());
+
+    let err = regress::run(&manifest.entries[1]).unwrap_err();
+    assert!(err.starts_with("crashed: "), "{}", err);
+}
@@ -0,0 +1,127 @@
+extern crate rust8;
+
+use std::fs;
+use std::path::Path;
+
+use rust8::asm;
+use rust8::harness::{Harness, Machine};
+use rust8::platform::Platform;
+use rust8::rombuilder::*;
+use rust8::trace::{self, Format, Trace};
+
+fn assemble(file: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join(file);
+    asm::assemble(&fs::read_to_string(&path).unwrap()).unwrap_or_else(|err| panic!("{}: {}", file, err))
+}
+
+fn opcode(machine: &mut Machine) -> (u16, u16) {
+    let pc = machine.cpu().get_pc();
+    let cpu = machine.cpu();
+    (pc, (cpu.get_mem8(pc as usize) as u16) << 8 | cpu.get_mem8(pc as usize + 1) as u16)
+}
+
+// Up to `steps` instructions of `rom` as `rust8 trace` logs them, pressing
+// `keys` as the ROM reads them.
+fn record(rom: &[u8], keys: &[u8], steps: usize, seed: u64) -> String {
+    Harness::new(rom).platform(Platform::Chip8).seed(seed).run(|machine| {
+        for &key in keys {
+            machine.press(key);
+        }
+        let mut out = String::new();
+        for _ in 0..steps {
+            if machine.is_halted() || machine.is_waiting_for_key() {
+                break;
+            }
+            let (pc, op) = opcode(machine);
+            machine.step();
+            out.push_str(&trace::line(pc, op, machine.cpu()));
+            out.push('\n');
+        }
+        out
+    })
+}
+
+// As `record`, but as an emulator logging NAME=VALUE pairs with the
+// registers before each instruction.
+fn record_keyed(rom: &[u8], steps: usize, seed: u64) -> String {
+    Harness::new(rom).platform(Platform::Chip8).seed(seed).run(|machine| {
+        let mut out = String::new();
+        for step in 0..steps {
+            let (pc, op) = opcode(machine);
+            let cpu = machine.cpu();
+            let regs: Vec<String> = (0..16).map(|x| format!("V{:X}:{:02x}", x, cpu.get_reg(x))).collect();
+            out.push_str(&format!("[{}] PC=0x{:04x} OP=0x{:04x} {} I=0x{:04x}\n", step, pc, op, regs.join(" "), cpu.get_i()));
+            machine.step();
+        }
+        out
+    })
+}
+
+fn replay(rom: &[u8], trace: &Trace, seed: u64) -> Result<usize, trace::Divergence> {
+    Harness::new(rom).platform(Platform::Chip8).seed(seed).run(|machine| trace::replay(machine, trace))
+}
+
+#[test]
+fn test_replays_own_trace() {
+    let rom = assemble("recompiled/demo.asm");
+    let text = record(&rom, &[], 2000, 3);
+    let trace = Trace::parse(&text, Format::detect(&text)).unwrap();
+    assert!(trace.steps.len() > 1000);
+    // Random numbers come from the trace, so the seed doesn't matter.
+    assert_eq!(replay(&rom, &trace, 9), Ok(trace.steps.len()));
+}
+
+#[test]
+fn test_replays_keyed_trace_logged_before() {
+    let rom = assemble("recompiled/demo.asm");
+    let text = record_keyed(&rom, 500, 3);
+    let trace = Trace::parse(&text, Format::parse("keyed,before").unwrap()).unwrap();
+    assert_eq!(replay(&rom, &trace, 9), Ok(500));
+}
+
+#[test]
+fn test_takes_keys_from_trace() {
+    let rom = assemble("conformance/keypad.asm");
+    let text = record(&rom, &[0x7, 0xA], 5000, 0);
+    let trace = Trace::parse(&text, Format::parse("rust8").unwrap()).unwrap();
+    assert_eq!(replay(&rom, &trace, 0), Ok(trace.steps.len()));
+}
+
+#[test]
+fn test_reports_first_divergence() {
+    let rom = RomBuilder::new()
+        .ld(V0, 1)
+        .label("loop")
+        .add(V0, 1)
+        .jp("loop")
+        .build()
+        .unwrap();
+    let text = record(&rom, &[], 20, 0);
+    let mut trace = Trace::parse(&text, Format::detect(&text)).unwrap();
+    trace.steps[9].v[0] = Some(0x42);
+    let divergence = replay(&rom, &trace, 0).unwrap_err();
+    assert_eq!(divergence.step, 9);
+    assert_eq!(divergence.message, "V0 is 0x06 here but 0x42 in the trace");
+    assert_eq!(divergence.context.len(), 6);
+    let report = divergence.to_string();
+    let last = report.lines().last().unwrap();
+    assert!(last.starts_with("!     9 202 7001 V 06 "), "{}", report);
+    assert!(last.contains(" | 202 7001 V 42 "), "{}", report);
+
+    trace.steps[9].v[0] = Some(6);
+    trace.steps[12].pc = 0x300;
+    let divergence = replay(&rom, &trace, 0).unwrap_err();
+    assert_eq!((divergence.step, divergence.message.as_str()), (12, "PC is 0x204 here but 0x300 in the trace"));
+}
+
+#[test]
+fn test_reports_crash() {
+    let rom = RomBuilder::new().ld(V0, 1).ret().build().unwrap();
+    let trace = Trace::parse("200 6001\n202 00EE\n", Format::parse("keyed").unwrap()).unwrap();
+    // Keyed lines need a PC=, so there are no steps to run.
+    assert_eq!(trace.steps.len(), 0);
+    let trace = Trace::parse("pc,op\n200,6001\n202,00EE\n204,0000\n", Format::parse("csv").unwrap()).unwrap();
+    let divergence = replay(&rom, &trace, 0).unwrap_err();
+    assert_eq!(divergence.step, 1);
+    assert!(divergence.message.starts_with("crashed: "), "{}", divergence.message);
+}
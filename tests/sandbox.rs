@@ -0,0 +1,101 @@
+extern crate rust8;
+
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rust8::cpu::CPU;
+use rust8::display::Display;
+use rust8::keyboard::Keyboard;
+use rust8::ram::RAM;
+use rust8::rombuilder::*;
+use rust8::sandbox::{Limit, RunOutcome, Sandbox};
+
+// Runs `rom` headless in `sandbox`, returning the outcome, the instructions
+// and frames run, and how many times the frame callback came.
+fn run(rom: &[u8], mut sandbox: Sandbox) -> (RunOutcome, u64, u64, u64) {
+    let (_, receiver) = channel();
+    let mut keyboard = Arc::new(Mutex::new(Keyboard::init(receiver)));
+    let mut display = Display::init();
+    let mut ram = RAM::init();
+    let mut cpu = CPU::init(&mut ram, &mut display, &mut keyboard, None);
+    cpu.load_rom(rom);
+    let mut callbacks = 0;
+    let outcome = sandbox.run(&mut cpu, |_| callbacks += 1);
+    (outcome, sandbox.instructions_run(), sandbox.frames_run(), callbacks)
+}
+
+#[test]
+fn test_jump_to_self() {
+    let rom = RomBuilder::new()
+        .ld(V0, 0)
+        .label("count")
+        .add(V0, 1)
+        .se(V0, 40)
+        .jp("count")
+        .label("halt")
+        .jp("halt")
+        .build()
+        .unwrap();
+    // Noticed at the end of the frame it gets there in.
+    assert_eq!(run(&rom, Sandbox::new(15).frames(100)), (RunOutcome::Halted(0x208), 120, 8, 8));
+}
+
+#[test]
+fn test_waiting_for_key() {
+    let rom = RomBuilder::new().ld(V0, 1).ld_vx_k(V1).build().unwrap();
+    assert_eq!(run(&rom, Sandbox::new(15)).0, RunOutcome::WaitingForKey(0x202));
+}
+
+#[test]
+fn test_repeating_state() {
+    // V0 wraps round every 256 times through the loop, so the state at the
+    // end of a frame comes back every 512 frames.
+    let rom = RomBuilder::new().label("loop").add(V0, 1).jp("loop").build().unwrap();
+    let (outcome, _, frames, _) = run(&rom, Sandbox::new(15).frames(10_000));
+    assert!(outcome == RunOutcome::Halted(0x200) || outcome == RunOutcome::Halted(0x202), "{}", outcome);
+    assert!(frames > 512 && frames <= 2048, "{}", frames);
+
+    // Waiting on the delay timer, then starting again.
+    let rom = RomBuilder::new()
+        .label("start")
+        .ld(V0, 30)
+        .ld_dt_vx(V0)
+        .label("wait")
+        .ld_vx_dt(V1)
+        .se(V1, 0)
+        .jp("wait")
+        .jp("start")
+        .build()
+        .unwrap();
+    match run(&rom, Sandbox::new(15).frames(10_000)) {
+        (RunOutcome::Halted(_), _, frames, _) => assert!(frames < 1000, "{}", frames),
+        (outcome, ..) => panic!("{}", outcome),
+    }
+}
+
+#[test]
+fn test_random_numbers_keep_it_going() {
+    let rom = RomBuilder::new().label("loop").rnd(V0, 0x01).jp("loop").build().unwrap();
+    assert_eq!(run(&rom, Sandbox::new(15).frames(3000)).0, RunOutcome::BudgetExhausted(Limit::Frames));
+}
+
+#[test]
+fn test_budgets() {
+    let rom = RomBuilder::new().label("loop").rnd(V0, 0xFF).jp("loop").build().unwrap();
+    let outcome = run(&rom, Sandbox::new(15).instructions(100).frames(1000));
+    assert_eq!(outcome, (RunOutcome::BudgetExhausted(Limit::Instructions), 100, 6, 6));
+    let outcome = run(&rom, Sandbox::new(15).instructions(1000).frames(10));
+    assert_eq!(outcome, (RunOutcome::BudgetExhausted(Limit::Frames), 150, 10, 10));
+    let outcome = run(&rom, Sandbox::new(15).timeout(Duration::from_millis(20)));
+    assert_eq!(outcome.0, RunOutcome::BudgetExhausted(Limit::Time));
+    assert!(outcome.2 > 0);
+}
+
+#[test]
+fn test_errors() {
+    let rom = RomBuilder::new().ld(V0, 1).ret().build().unwrap();
+    let (outcome, ..) = run(&rom, Sandbox::new(15).frames(10));
+    assert_eq!(outcome, RunOutcome::Error(0x202, "RET with an empty stack".to_string()));
+    assert_eq!(outcome.to_string(), "RET with an empty stack at 0x202");
+}